@@ -0,0 +1,24 @@
+/// Somewhere the finder can copy text to, the system clipboard outside of tests
+pub(crate) trait Clipboard {
+    fn set_text(&mut self, text: String) -> Result<(), String>;
+}
+
+/// The system clipboard, connected to on the first copy. The connection is kept open for the
+/// rest of the session, as on X11 copied text is only served while it's open.
+#[derive(Default)]
+pub(crate) struct SystemClipboard {
+    inner: Option<arboard::Clipboard>,
+}
+
+impl Clipboard for SystemClipboard {
+    fn set_text(&mut self, text: String) -> Result<(), String> {
+        if self.inner.is_none() {
+            self.inner = Some(arboard::Clipboard::new().map_err(|e| e.to_string())?);
+        }
+        self.inner
+            .as_mut()
+            .unwrap()
+            .set_text(text)
+            .map_err(|e| e.to_string())
+    }
+}
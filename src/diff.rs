@@ -0,0 +1,189 @@
+use crate::tika_document::TikaDocument;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Paths of the documents that differ between two sets of documents
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct DocumentDiff {
+    /// Only present in the new set
+    pub added: Vec<String>,
+    /// Present in both sets, but with different contents
+    pub updated: Vec<String>,
+    /// Only present in the old set
+    pub deleted: Vec<String>,
+}
+
+impl DocumentDiff {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.updated.is_empty() && self.deleted.is_empty()
+    }
+}
+
+fn by_path(docs: Vec<TikaDocument>) -> BTreeMap<String, TikaDocument> {
+    docs.into_iter()
+        .map(|doc| (doc.full_path.to_string_lossy().into_owned(), doc))
+        .collect()
+}
+
+/// Compare two sets of documents keyed on their full path, e.g. the indexed documents against
+/// the documents currently on disk. Each list of paths is sorted.
+pub(crate) fn diff_documents(old: Vec<TikaDocument>, new: Vec<TikaDocument>) -> DocumentDiff {
+    let mut old = by_path(old);
+    let mut diff = DocumentDiff::default();
+
+    for (path, mut doc) in by_path(new) {
+        match old.remove(&path) {
+            None => diff.added.push(path),
+            Some(prev) => {
+                // When a document was indexed isn't part of its contents
+                doc.indexed = prev.indexed.clone();
+                if prev != doc {
+                    diff.updated.push(path);
+                }
+            }
+        }
+    }
+    diff.deleted = old.into_iter().map(|(path, _)| path).collect();
+
+    diff
+}
+
+/// Filenames of the documents that differ between two vaults
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub(crate) struct VaultDiff {
+    /// Only present in the first vault
+    pub only_a: Vec<String>,
+    /// Only present in the second vault
+    pub only_b: Vec<String>,
+    /// Present in both vaults, but with a different title, date or body
+    pub differing: Vec<String>,
+}
+
+fn by_filename(docs: Vec<TikaDocument>) -> BTreeMap<String, TikaDocument> {
+    docs.into_iter()
+        .map(|doc| (doc.filename.clone(), doc))
+        .collect()
+}
+
+/// Compare the documents of two vaults, e.g. a fork and its original. Their paths differ, so
+/// documents are matched up on their filename, which is also what they're indexed under. Each
+/// list of filenames is sorted.
+pub(crate) fn diff_vaults(a: Vec<TikaDocument>, b: Vec<TikaDocument>) -> VaultDiff {
+    let mut a = by_filename(a);
+    let mut diff = VaultDiff::default();
+
+    for (filename, doc) in by_filename(b) {
+        match a.remove(&filename) {
+            None => diff.only_b.push(filename),
+            Some(prev)
+                if (&prev.title, &prev.date, &prev.body) != (&doc.title, &doc.date, &doc.body) =>
+            {
+                diff.differing.push(filename)
+            }
+            Some(_) => {}
+        }
+    }
+    diff.only_a = a.into_iter().map(|(filename, _)| filename).collect();
+
+    diff
+}
+
+#[cfg(test)]
+mod diff_documents_tests {
+    use super::*;
+    use std::ffi::OsString;
+
+    fn doc(path: &str, body: &str) -> TikaDocument {
+        TikaDocument {
+            full_path: OsString::from(path),
+            body: String::from(body),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn new_changed_and_removed() {
+        let indexed = vec![
+            doc("/notes/same.md", "same"),
+            doc("/notes/changed.md", "before"),
+            doc("/notes/removed.md", "gone"),
+        ];
+        let on_disk = vec![
+            doc("/notes/new.md", "new"),
+            doc("/notes/changed.md", "after"),
+            doc("/notes/same.md", "same"),
+        ];
+
+        assert_eq!(
+            DocumentDiff {
+                added: vec![String::from("/notes/new.md")],
+                updated: vec![String::from("/notes/changed.md")],
+                deleted: vec![String::from("/notes/removed.md")],
+            },
+            diff_documents(indexed, on_disk)
+        );
+    }
+
+    #[test]
+    fn unchanged() {
+        let diff = diff_documents(vec![doc("/a.md", "a")], vec![doc("/a.md", "a")]);
+        assert!(diff.is_empty());
+    }
+}
+
+#[cfg(test)]
+mod diff_vaults_tests {
+    use super::*;
+    use crate::tika_document::parse_file;
+    use crate::util::walk_glob;
+    use std::fs;
+    use std::path::Path;
+
+    fn tree(notes: &[(&str, &str, &str)]) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        for (name, title, body) in notes {
+            fs::write(
+                dir.path().join(name),
+                format!(
+                    "---\ndate: 2021-06-22T12:48:16-0400\ntags: test\ntitle: {}\n---\n{}\n",
+                    title, body
+                ),
+            )
+            .unwrap();
+        }
+        dir
+    }
+
+    fn load(dir: &Path) -> Vec<TikaDocument> {
+        walk_glob(&format!("{}/*.md", dir.display()), false)
+            .unwrap()
+            .into_iter()
+            .map(|path| parse_file(&path.unwrap()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn only_in_either_and_differing() {
+        let a = tree(&[
+            ("same.md", "Same", "Shared"),
+            ("retitled.md", "Before", "Shared"),
+            ("edited.md", "Edited", "Before"),
+            ("original.md", "Original", "Only here"),
+        ]);
+        let b = tree(&[
+            ("same.md", "Same", "Shared"),
+            ("retitled.md", "After", "Shared"),
+            ("edited.md", "Edited", "After"),
+            ("fork.md", "Fork", "Only there"),
+        ]);
+
+        assert_eq!(
+            VaultDiff {
+                only_a: vec![String::from("original.md")],
+                only_b: vec![String::from("fork.md")],
+                differing: vec![String::from("edited.md"), String::from("retitled.md")],
+            },
+            diff_vaults(load(a.path()), load(b.path()))
+        );
+    }
+}
@@ -0,0 +1,197 @@
+use crate::indexer::{schema_version, SCHEMA_VERSION};
+use crate::output::{status, Status};
+use crate::util::{read_config, walk_glob};
+use crate::xapian_utils;
+use std::path::Path;
+
+/// Outcome of a single environment check
+#[derive(Debug)]
+pub(crate) struct Check {
+    pub name: &'static str,
+    pub ok: bool,
+    /// A failing critical check makes `tika doctor` exit non-zero
+    pub critical: bool,
+    /// How to fix a failing check
+    pub hint: String,
+}
+
+impl Check {
+    fn new(name: &'static str, critical: bool, result: Result<(), String>) -> Check {
+        let (ok, hint) = match result {
+            Ok(()) => (true, String::new()),
+            Err(hint) => (false, hint),
+        };
+        Check {
+            name,
+            ok,
+            critical,
+            hint,
+        }
+    }
+}
+
+fn check_source_glob(cfg_file: &str) -> Result<(), String> {
+    let cfg = read_config(cfg_file).map_err(|e| format!("Can't read config: {}", e))?;
+    let source = cfg
+        .get("source-glob")
+        .and_then(|v| v.as_str())
+        .ok_or("Set `source-glob` in the config")?;
+    let source = shellexpand::tilde(source);
+    let follow_symlinks = cfg
+        .get("follow-symlinks")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false);
+    let matched = walk_glob(&source, follow_symlinks)
+        .map_err(|e| format!("Invalid `source-glob` '{}': {}", source, e))?
+        .into_iter()
+        .filter_map(Result::ok)
+        .count();
+    if matched == 0 {
+        return Err(format!(
+            "`source-glob` '{}' doesn't match any files",
+            source
+        ));
+    }
+    Ok(())
+}
+
+fn check_writable(db_path: &str) -> Result<(), String> {
+    // Before the first index run the DB doesn't exist, so check it can be created
+    let path = Path::new(db_path);
+    let dir = if path.exists() {
+        path
+    } else {
+        match path.parent() {
+            Some(p) if p != Path::new("") => p,
+            _ => Path::new("."),
+        }
+    };
+    tempfile::tempfile_in(dir)
+        .map(|_| ())
+        .map_err(|e| format!("Can't write to '{}': {}", dir.display(), e))
+}
+
+fn check_index(db_path: &str) -> Result<(), String> {
+    match xapian_utils::all_document_data(&[db_path]).map(|mut docs| docs.next()) {
+        Ok(Some(_)) => Ok(()),
+        _ => Err(String::from("Index is missing or empty, run `tika -i`")),
+    }
+}
+
+fn check_schema(db_path: &str) -> Result<(), String> {
+    // A missing index is reported by the index check
+    if !Path::new(db_path).exists() {
+        return Ok(());
+    }
+    match schema_version(db_path) {
+        Some(SCHEMA_VERSION) => Ok(()),
+        Some(version) => Err(format!(
+            "Index is at schema version {} but this tika uses {}, rebuild it with `tika -i`",
+            version, SCHEMA_VERSION
+        )),
+        None => Err(String::from(
+            "Index was written by an older tika, rebuild it with `tika -i`",
+        )),
+    }
+}
+
+/// Check everything tika depends on, in the order problems are best fixed
+pub(crate) fn run_checks(cfg_file: &str, db_path: &str, editor: Option<String>) -> Vec<Check> {
+    vec![
+        Check::new(
+            "Config file",
+            true,
+            read_config(cfg_file)
+                .map(|_| ())
+                .map_err(|e| format!("Create or fix '{}': {}", cfg_file, e)),
+        ),
+        Check::new("Source glob", true, check_source_glob(cfg_file)),
+        Check::new("Index directory", true, check_writable(db_path)),
+        Check::new("Index", false, check_index(db_path)),
+        Check::new("Index schema", false, check_schema(db_path)),
+        Check::new(
+            "$EDITOR",
+            false,
+            match editor {
+                Some(e) if !e.is_empty() => Ok(()),
+                _ => Err(String::from(
+                    "Set $EDITOR to open documents, `vi` is used otherwise",
+                )),
+            },
+        ),
+    ]
+}
+
+/// Print each check, returning whether all of the critical ones passed
+pub(crate) fn report(checks: &[Check]) -> bool {
+    for check in checks {
+        if check.ok {
+            println!("{}", status(Status::Ok, check.name));
+        } else {
+            let message = format!("{}: {}", check.name, check.hint);
+            println!("{}", status(Status::Err, message));
+        }
+    }
+    checks.iter().all(|c| c.ok || !c.critical)
+}
+
+#[cfg(test)]
+mod run_checks_tests {
+    use super::*;
+    use crate::fixtures::FixtureVault;
+    use std::fs;
+
+    fn failing(checks: &[Check]) -> Vec<&str> {
+        checks.iter().filter(|c| !c.ok).map(|c| c.name).collect()
+    }
+
+    #[test]
+    fn misconfigured_environment() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let cfg_file = dir.path().join("missing.toml");
+        let db_path = dir.path().join("db");
+
+        let checks = run_checks(cfg_file.to_str().unwrap(), db_path.to_str().unwrap(), None);
+        assert_eq!(
+            vec!["Config file", "Source glob", "Index", "$EDITOR"],
+            failing(&checks)
+        );
+        assert!(!report(&checks));
+    }
+
+    #[test]
+    fn glob_matching_nothing() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let cfg_file = dir.path().join("tika.toml");
+        fs::write(
+            &cfg_file,
+            format!("source-glob = \"{}/*.md\"\n", dir.path().display()),
+        )
+        .unwrap();
+        let db_path = dir.path().join("db");
+
+        let checks = run_checks(
+            cfg_file.to_str().unwrap(),
+            db_path.to_str().unwrap(),
+            Some(String::from("vim")),
+        );
+        assert_eq!(vec!["Source glob", "Index"], failing(&checks));
+        assert!(!report(&checks));
+    }
+
+    #[test]
+    fn index_schema_version() {
+        let mut vault = FixtureVault::new();
+        vault.note("note.md", &[], "Body");
+        vault.index();
+        let db_path = vault.db_path();
+        let cfg_file = vault.path().join("tika.toml");
+
+        let checks = run_checks(cfg_file.to_str().unwrap(), &db_path, None);
+        assert!(!failing(&checks).contains(&"Index schema"));
+
+        fs::write(Path::new(&db_path).join("tika-schema"), "0").unwrap();
+        let checks = run_checks(cfg_file.to_str().unwrap(), &db_path, None);
+        assert!(failing(&checks).contains(&"Index schema"));
+    }
+}
@@ -0,0 +1,117 @@
+use crate::output::{status, Status};
+use color_eyre::Report;
+use eyre::eyre;
+use std::io::{self, BufRead, Write};
+use std::process::Command;
+
+/// Default maximum number of files to open at once without asking for confirmation
+pub(crate) const DEFAULT_OPEN_CAP: usize = 20;
+
+/// Editor used when `$EDITOR` is unset or blank
+const DEFAULT_EDITOR: &str = "vi";
+
+/// Build the full command line opening every path in a single editor session. `$EDITOR` may
+/// carry its own arguments, e.g. `code -w`; a blank one falls back to `vi`. Returns `None` when
+/// there are more paths than `cap` and opening them all hasn't been confirmed.
+pub(crate) fn editor_command(
+    editor: &str,
+    paths: &[String],
+    cap: usize,
+    confirmed: bool,
+) -> Option<Vec<String>> {
+    if paths.len() > cap && !confirmed {
+        return None;
+    }
+
+    let mut cmd: Vec<String> = editor.split_whitespace().map(String::from).collect();
+    if cmd.is_empty() {
+        cmd.push(String::from(DEFAULT_EDITOR));
+    }
+    cmd.extend(paths.iter().cloned());
+    Some(cmd)
+}
+
+/// Open all of the given paths in `$EDITOR`, prompting for confirmation past `cap` files
+pub(crate) fn open_all(paths: &[String], cap: usize) -> Result<(), Report> {
+    if paths.is_empty() {
+        return Ok(());
+    }
+
+    let editor = std::env::var("EDITOR").unwrap_or_else(|_| String::from(DEFAULT_EDITOR));
+    let cmd = match editor_command(&editor, paths, cap, false) {
+        Some(cmd) => cmd,
+        None => {
+            if !confirm(&format!("Open all {} files?", paths.len()))? {
+                return Ok(());
+            }
+            editor_command(&editor, paths, cap, true).unwrap()
+        }
+    };
+
+    let exit = Command::new(&cmd[0]).args(&cmd[1..]).status()?;
+    if !exit.success() {
+        let message = format!("Editor '{}' exited with {}", editor, exit);
+        return Err(eyre!(status(Status::Err, message)));
+    }
+    Ok(())
+}
+
+fn confirm(prompt: &str) -> Result<bool, Report> {
+    eprint!("{} [y/N] ", prompt);
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+#[cfg(test)]
+mod editor_command_tests {
+    use super::*;
+
+    fn paths(n: usize) -> Vec<String> {
+        (0..n).map(|i| format!("/notes/{}.md", i)).collect()
+    }
+
+    #[test]
+    fn under_cap() {
+        assert_eq!(
+            Some(vec![
+                String::from("vim"),
+                String::from("/notes/0.md"),
+                String::from("/notes/1.md")
+            ]),
+            editor_command("vim", &paths(2), 20, false)
+        );
+    }
+
+    #[test]
+    fn editor_with_arguments() {
+        assert_eq!(
+            Some(vec![
+                String::from("code"),
+                String::from("-w"),
+                String::from("/notes/0.md")
+            ]),
+            editor_command("code -w", &paths(1), 20, false)
+        );
+    }
+
+    #[test]
+    fn blank_editor_falls_back_to_vi() {
+        for editor in &["", "  \t"] {
+            assert_eq!(
+                Some(vec![String::from("vi"), String::from("/notes/0.md")]),
+                editor_command(editor, &paths(1), 20, false)
+            );
+        }
+    }
+
+    #[test]
+    fn over_cap_needs_confirmation() {
+        assert_eq!(None, editor_command("vim", &paths(21), 20, false));
+        assert_eq!(
+            Some(22),
+            editor_command("vim", &paths(21), 20, true).map(|c| c.len())
+        );
+    }
+}
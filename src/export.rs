@@ -0,0 +1,137 @@
+use crate::xapian_utils;
+use color_eyre::Report;
+use std::io::{self, Write};
+
+/// Export every indexed document to `out` as JSON, one document per line, leaving out the
+/// fields carrying their body text unless `bodies` is set
+pub(crate) fn export<W: Write>(db_paths: &[&str], out: W, bodies: bool) -> Result<usize, Report> {
+    let docs = xapian_utils::all_document_data(db_paths)?;
+    let count = if bodies {
+        write_documents(out, docs)?
+    } else {
+        write_documents(out, docs.map(|data| without_body(&data)))?
+    };
+    Ok(count)
+}
+
+/// Fields of a stored document holding text from its body
+const BODY_FIELDS: [&str; 4] = ["body", "summary", "preview", "snippet"];
+
+/// The stored JSON document `data` without any of its `BODY_FIELDS`; data that isn't a JSON
+/// object is left as it is
+fn without_body(data: &str) -> String {
+    match serde_json::from_str(data) {
+        Ok(serde_json::Value::Object(mut doc)) => {
+            for field in &BODY_FIELDS {
+                doc.remove(*field);
+            }
+            serde_json::Value::Object(doc).to_string()
+        }
+        _ => String::from(data),
+    }
+}
+
+/// Write each JSON document to `out` as soon as it is produced, so memory use stays bounded
+/// regardless of how many documents there are
+pub(crate) fn write_documents<W, I>(mut out: W, docs: I) -> Result<usize, io::Error>
+where
+    W: Write,
+    I: IntoIterator<Item = String>,
+{
+    let mut count = 0;
+    for doc in docs {
+        out.write_all(doc.as_bytes())?;
+        out.write_all(b"\n")?;
+        count += 1;
+    }
+    out.flush()?;
+
+    Ok(count)
+}
+
+#[cfg(test)]
+mod write_documents_tests {
+    use super::*;
+    use std::cell::Cell;
+
+    /// Writer that checks each line is written before the next document is generated
+    struct LockstepWriter<'a> {
+        produced: &'a Cell<usize>,
+        written: usize,
+    }
+
+    impl Write for LockstepWriter<'_> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if buf == b"\n" {
+                self.written += 1;
+                assert_eq!(self.produced.get(), self.written);
+            }
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn large_vault_is_streamed() {
+        let produced = Cell::new(0);
+        let docs = (0..100_000).map(|i| {
+            produced.set(produced.get() + 1);
+            format!(r#"{{"filename":"note-{}.md"}}"#, i)
+        });
+        let out = LockstepWriter {
+            produced: &produced,
+            written: 0,
+        };
+
+        let count = write_documents(out, docs).expect("Failed to write documents");
+        assert_eq!(100_000, count);
+    }
+}
+
+#[cfg(test)]
+mod export_tests {
+    use super::*;
+    use crate::fixtures::FixtureVault;
+    use crate::indexer::IndexOptions;
+    use crate::tika_document::preview;
+    use serde_json::Value;
+
+    fn exported(bodies: bool) -> Value {
+        let mut vault = FixtureVault::new();
+        vault.note("standup.md", &[], "Talked about the release");
+        vault.index_with(&IndexOptions {
+            preview_chars: 40,
+            ..Default::default()
+        });
+        let mut out = Vec::new();
+        assert_eq!(1, export(&[&vault.db_path()], &mut out, bodies).unwrap());
+        serde_json::from_slice(&out).expect("Export isn't JSON")
+    }
+
+    #[test]
+    fn bodies_are_included() {
+        let doc = exported(true);
+        assert_eq!("Talked about the release\n", doc["body"]);
+        assert_eq!("Talked about the release", doc["summary"]);
+        assert_eq!(doc["preview"], preview("Talked about the release\n", 40));
+    }
+
+    #[test]
+    fn no_body_leaves_out_body_text() {
+        let doc = exported(false);
+        assert_eq!("standup", doc["title"]);
+        for field in &BODY_FIELDS {
+            assert_eq!(None, doc.get(*field), "{} was exported", field);
+        }
+    }
+
+    #[test]
+    fn no_body_leaves_out_snippets() {
+        let data = r#"{"title":"standup","snippet":"about the release"}"#;
+        let doc: Value = serde_json::from_str(&without_body(data)).unwrap();
+        assert_eq!(serde_json::json!({"title": "standup"}), doc);
+    }
+}
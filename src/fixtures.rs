@@ -0,0 +1,142 @@
+//! Fixture vaults for tests: Markdown notes in a temporary directory, indexed and searched through
+//! the same functions as the CLI
+
+use crate::indexer::{index_paths, IndexOptions};
+use crate::tika_document::TikaDocument;
+use crate::xapian_utils::{parse_user_query, query_db, DEFAULT_LIMIT};
+use std::fs;
+use std::path::{Path, PathBuf};
+use xapian_rusty::Query;
+
+/// Frontmatter every fixture note gets unless it sets the key itself, `title` aside
+const DEFAULT_FIELDS: [(&str, &str); 2] = [("date", "2021-06-22T12:48:16-0400"), ("tags", "test")];
+
+/// A temporary directory of notes, with an index kept alongside them
+pub(crate) struct FixtureVault {
+    dir: tempfile::TempDir,
+    notes: Vec<PathBuf>,
+}
+
+impl FixtureVault {
+    pub(crate) fn new() -> FixtureVault {
+        FixtureVault {
+            dir: tempfile::tempdir().expect("Failed to create temp dir"),
+            notes: Vec::new(),
+        }
+    }
+
+    /// Directory holding the notes
+    pub(crate) fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    pub(crate) fn db_path(&self) -> String {
+        self.dir.path().join("db").to_str().unwrap().to_owned()
+    }
+
+    /// Write a note at `name`, relative to the vault and creating any directories on the way,
+    /// with the YAML frontmatter `fields` such as `("tags", "[rust, cli]")`. The date and tags
+    /// are filled in when not given, and the title is the file stem.
+    pub(crate) fn note(&mut self, name: &str, fields: &[(&str, &str)], body: &str) -> PathBuf {
+        let path = self.dir.path().join(name);
+        let title = path.file_stem().unwrap().to_string_lossy().into_owned();
+        let defaults = DEFAULT_FIELDS
+            .iter()
+            .copied()
+            .chain(std::iter::once(("title", title.as_str())))
+            .filter(|(key, _)| fields.iter().all(|(given, _)| given != key));
+        let mut contents = String::from("---\n");
+        for (key, value) in fields.iter().copied().chain(defaults) {
+            contents.push_str(&format!("{}: {}\n", key, value));
+        }
+        contents.push_str(&format!("---\n{}\n", body));
+        self.file(name, &contents)
+    }
+
+    /// Write a file at `name` with exactly `contents`, e.g. one without frontmatter
+    pub(crate) fn file(&mut self, name: &str, contents: &str) -> PathBuf {
+        let path = self.dir.path().join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, contents).unwrap();
+
+        self.notes.push(path.clone());
+        path
+    }
+
+    /// Index every note written so far, returning the number of documents indexed
+    pub(crate) fn index(&self) -> usize {
+        self.index_with(&Default::default())
+    }
+
+    pub(crate) fn index_with(&self, opts: &IndexOptions) -> usize {
+        index_paths(&self.db_path(), self.notes.iter().cloned(), opts).expect("Failed to index")
+    }
+
+    /// Matches for the user query `qstr`, as typed on the command line
+    pub(crate) fn search(&self, qstr: &str) -> Vec<TikaDocument> {
+        let query = parse_user_query(&format!("{} ;", qstr)).expect("Failed to parse");
+        self.run(query)
+    }
+
+    /// Matches for an already built query, e.g. one narrowed by a filter
+    pub(crate) fn run(&self, query: Query) -> Vec<TikaDocument> {
+        query_db(&[&self.db_path()], query, DEFAULT_LIMIT).expect("Failed to query")
+    }
+}
+
+/// Titles of `docs`, sorted so that tests don't depend on ranking
+pub(crate) fn titles(docs: Vec<TikaDocument>) -> Vec<String> {
+    let mut titles: Vec<String> = docs.into_iter().map(|doc| doc.title).collect();
+    titles.sort();
+    titles
+}
+
+#[cfg(test)]
+mod fixture_vault_tests {
+    use super::*;
+    use crate::xapian_utils::{in_date_range, match_all};
+    use chrono::NaiveDate;
+
+    #[test]
+    fn basic_search() {
+        let mut vault = FixtureVault::new();
+        vault.note("kubernetes.md", &[], "Rolling out the cluster upgrade");
+        vault.note("groceries.md", &[], "Eggs and milk");
+        assert_eq!(2, vault.index());
+
+        assert_eq!(vec!["kubernetes"], titles(vault.search("cluster")));
+        assert_eq!(vec!["groceries"], titles(vault.search("title:groceries")));
+        assert!(vault.search("nothing").is_empty());
+    }
+
+    #[test]
+    fn tag_filter() {
+        let mut vault = FixtureVault::new();
+        vault.note("borrowck.md", &[("tags", "[rust, compilers]")], "Notes");
+        vault.note("goroutines.md", &[("tags", "go")], "Notes");
+        vault.note("work/cargo.md", &[("tags", "rust")], "Notes");
+        vault.index();
+
+        assert_eq!(vec!["borrowck", "cargo"], titles(vault.search("tag:rust")));
+        assert_eq!(vec!["goroutines"], titles(vault.search("tag:go")));
+    }
+
+    #[test]
+    fn date_range() {
+        let mut vault = FixtureVault::new();
+        for (name, date) in &[
+            ("winter.md", "2021-01-15T09:00:00Z"),
+            ("spring.md", "2021-04-15T09:00:00Z"),
+            ("summer.md", "2021-07-15T09:00:00Z"),
+        ] {
+            vault.note(name, &[("date", date)], "Seasonal notes");
+        }
+        vault.index();
+
+        let day = |m, d| Some(NaiveDate::from_ymd(2021, m, d));
+        let between = in_date_range(match_all().unwrap(), day(2, 1), day(7, 1), &chrono::Utc);
+        assert_eq!(vec!["spring"], titles(vault.run(between.unwrap())));
+        let since = in_date_range(match_all().unwrap(), day(4, 15), None, &chrono::Utc);
+        assert_eq!(vec!["spring", "summer"], titles(vault.run(since.unwrap())));
+    }
+}
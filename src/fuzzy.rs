@@ -0,0 +1,130 @@
+//! fzf-style subsequence matching of document titles, for `finder-mode = "fuzzy"`
+
+use crate::tika_document::TikaDocument;
+
+/// Score for each character of the pattern matched
+const MATCH: i64 = 16;
+/// Extra score for matching at the start of a word, e.g. the `t` of `the` or `T` of `TikaDoc`
+const BOUNDARY: i64 = 8;
+/// Extra score for matching the character straight after the previous match
+const CONSECUTIVE: i64 = 8;
+/// Penalty for the first character skipped between two matches
+const GAP_START: i64 = 3;
+/// Penalty for each further character skipped
+const GAP_EXTENSION: i64 = 1;
+
+/// How well `pattern` matches `text` as a subsequence, ignoring case and whitespace in the
+/// pattern, or `None` when it doesn't. Matches at word starts and runs of consecutive matches
+/// score higher, gaps between matches lower; the best placement of the pattern is scored.
+pub(crate) fn score(pattern: &str, text: &str) -> Option<i64> {
+    let pattern: Vec<char> = pattern
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .flat_map(char::to_lowercase)
+        .collect();
+    let first = match pattern.first() {
+        Some(&first) => first,
+        None => return Some(0),
+    };
+    let text: Vec<char> = text.chars().collect();
+    let lower: Vec<char> = text
+        .iter()
+        .map(|&c| c.to_lowercase().next().unwrap_or(c))
+        .collect();
+
+    // The best score with the pattern so far matched, ending at each position of the text
+    let mut best: Vec<Option<i64>> = (0..text.len())
+        .map(|j| Some(MATCH + bonus(&text, j)).filter(|_| lower[j] == first))
+        .collect();
+    for &p in &pattern[1..] {
+        let mut next = vec![None; text.len()];
+        for j in (0..text.len()).filter(|&j| lower[j] == p) {
+            next[j] = (0..j)
+                .filter_map(|k| {
+                    let step = match j - k - 1 {
+                        0 => CONSECUTIVE,
+                        gap => -GAP_START - GAP_EXTENSION * (gap as i64 - 1),
+                    };
+                    best[k].map(|score| score + step)
+                })
+                .max()
+                .map(|score| score + MATCH + bonus(&text, j));
+        }
+        best = next;
+    }
+    best.into_iter().flatten().max()
+}
+
+/// Bonus for matching at position `j` of `text`, for starting a word
+fn bonus(text: &[char], j: usize) -> i64 {
+    let starts_word = j == 0
+        || !text[j - 1].is_alphanumeric()
+        || text[j - 1].is_lowercase() && text[j].is_uppercase();
+    if starts_word {
+        BOUNDARY
+    } else {
+        0
+    }
+}
+
+/// Up to `limit` of `docs` whose titles match `pattern`, the best matches first and otherwise in
+/// their order; all of them, up to `limit`, for an empty pattern
+pub(crate) fn rank(docs: &[TikaDocument], pattern: &str, limit: usize) -> Vec<TikaDocument> {
+    let mut scored: Vec<(i64, &TikaDocument)> = docs
+        .iter()
+        .filter_map(|doc| score(pattern, &doc.title).map(|score| (score, doc)))
+        .collect();
+    // Stable, so equally good matches keep their order
+    scored.sort_by(|(a, _), (b, _)| b.cmp(a));
+    scored
+        .into_iter()
+        .take(limit)
+        .map(|(_, doc)| doc.clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod score_tests {
+    use super::*;
+
+    #[test]
+    fn close_match_beats_loose_match() {
+        let close = score("tk", "tika").unwrap();
+        let loose = score("tk", "the week").unwrap();
+        assert!(close > loose, "{} <= {}", close, loose);
+        assert_eq!(None, score("tk", "kettle"));
+        assert_eq!(Some(0), score("", "anything"));
+    }
+
+    #[test]
+    fn best_placement_is_scored() {
+        // The `k` right after a later `t` beats the first `t`, far from any `k`
+        assert!(score("tk", "t..........tk").unwrap() > score("tk", "t..........k").unwrap());
+        assert_eq!(score("TK", "tika"), score("t k", "Tika"));
+    }
+}
+
+#[cfg(test)]
+mod rank_tests {
+    use super::*;
+
+    fn titles(docs: Vec<TikaDocument>) -> Vec<String> {
+        docs.into_iter().map(|doc| doc.title).collect()
+    }
+
+    #[test]
+    fn best_matches_first() {
+        let docs: Vec<TikaDocument> = ["the week", "kettle", "tika", "Rust toolkit"]
+            .iter()
+            .map(|title| TikaDocument {
+                title: String::from(*title),
+                ..Default::default()
+            })
+            .collect();
+        assert_eq!(
+            vec!["tika", "Rust toolkit", "the week"],
+            titles(rank(&docs, "tk", 10))
+        );
+        assert_eq!(vec!["the week", "kettle"], titles(rank(&docs, "", 2)));
+    }
+}
@@ -0,0 +1,114 @@
+use color_eyre::Report;
+use eyre::eyre;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Markdown files staged in git, for `--staged` in a pre-commit hook
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct Staged {
+    /// Files added, modified or renamed into place
+    pub changed: Vec<PathBuf>,
+    /// Files staged for deletion, or renamed away
+    pub deleted: Vec<PathBuf>,
+}
+
+/// The Markdown files under `dir` staged in the git repository holding it. `git` is run rather
+/// than linking libgit2, as it's already there wherever a pre-commit hook runs.
+pub(crate) fn staged(dir: &Path) -> Result<Staged, Report> {
+    let root = git(dir, &["rev-parse", "--show-toplevel"])?;
+    let root = PathBuf::from(String::from_utf8_lossy(&root).trim_end());
+    let name_status = git(
+        dir,
+        &["diff", "--cached", "--name-status", "--no-renames", "-z"],
+    )?;
+    let dir = dir.canonicalize()?;
+    Ok(parse_name_status(&root, &name_status, &dir))
+}
+
+/// Run `git` in `dir`, failing with its stderr unless it succeeds
+fn git(dir: &Path, args: &[&str]) -> Result<Vec<u8>, Report> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .map_err(|e| eyre!("Failed to run git: {}", e))?;
+    if !output.status.success() {
+        return Err(eyre!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(output.stdout)
+}
+
+/// Read `git diff --name-status -z` output, of paths relative to the repository at `root`,
+/// keeping the Markdown files under `dir`
+fn parse_name_status(root: &Path, output: &[u8], dir: &Path) -> Staged {
+    let mut staged = Staged::default();
+    let mut fields = output
+        .split(|&b| b == 0)
+        .map(|field| String::from_utf8_lossy(field).into_owned());
+    while let (Some(status), Some(path)) = (fields.next(), fields.next()) {
+        let path = root.join(path);
+        if !path.starts_with(dir) || path.extension().map_or(true, |ext| ext != "md") {
+            continue;
+        }
+        if status.starts_with('D') {
+            staged.deleted.push(path);
+        } else {
+            staged.changed.push(path);
+        }
+    }
+    staged
+}
+
+#[cfg(test)]
+mod staged_tests {
+    use super::*;
+    use std::fs;
+
+    fn run(dir: &Path, args: &[&str]) {
+        let args: Vec<&str> = ["-c", "user.name=Test", "-c", "user.email=test@example.com"]
+            .iter()
+            .chain(args)
+            .copied()
+            .collect();
+        git(dir, &args).expect("git failed");
+    }
+
+    #[test]
+    fn only_staged_markdown_files() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let repo = dir.path().canonicalize().unwrap();
+        run(&repo, &["init", "-q"]);
+        for name in &["modified.md", "removed.md", "untouched.md"] {
+            fs::write(repo.join(name), "Committed").unwrap();
+        }
+        run(&repo, &["add", "."]);
+        run(&repo, &["commit", "-q", "-m", "Notes"]);
+
+        fs::write(repo.join("modified.md"), "Changed").unwrap();
+        fs::create_dir(repo.join("sub")).unwrap();
+        fs::write(repo.join("sub/added.md"), "New").unwrap();
+        fs::write(repo.join("image.png"), "Not Markdown").unwrap();
+        run(&repo, &["add", "modified.md", "sub/added.md", "image.png"]);
+        run(&repo, &["rm", "-q", "removed.md"]);
+        fs::write(repo.join("unstaged.md"), "Not added").unwrap();
+        fs::write(repo.join("untouched.md"), "Changed but not added").unwrap();
+
+        let staged = staged(&repo).expect("Failed to list staged files");
+        assert_eq!(
+            Staged {
+                changed: vec![repo.join("modified.md"), repo.join("sub/added.md")],
+                deleted: vec![repo.join("removed.md")],
+            },
+            staged
+        );
+
+        let sub = staged(&repo.join("sub")).expect("Failed to list staged files");
+        assert_eq!(vec![repo.join("sub/added.md")], sub.changed);
+        assert!(sub.deleted.is_empty());
+    }
+}
@@ -0,0 +1,1443 @@
+use crate::output::{status, Status};
+use crate::profile::{self, Phase};
+use crate::tika_document::{
+    parse_documents, preview, LoadError, LoadErrorKind, ParseOptions, TikaDocument,
+};
+use crate::xapian_utils::{
+    self, dir_id, ATTACHMENTS_SLOT, DATABASE_LOCK_ERROR, DATE_SLOT, DIR_PREFIX, DRAFT_SLOT,
+};
+use color_eyre::{eyre::eyre, Report};
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use xapian_rusty::{
+    Document, Stem, TermGenerator, WritableDatabase, XError, BRASS, DB_CREATE_OR_OPEN,
+};
+
+/// How long to keep retrying to open a DB another process is writing to, unless configured
+pub(crate) const DEFAULT_LOCK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Wait before the first retry of a locked DB, doubled for each retry after it
+const LOCK_RETRY_START: Duration = Duration::from_millis(50);
+
+/// Version of the index layout: its terms, value slots and stored documents. Bump it whenever a
+/// change needs existing indexes rebuilt.
+pub(crate) const SCHEMA_VERSION: u32 = 1;
+
+/// File within each DB's directory recording the `SCHEMA_VERSION` it was written with
+const SCHEMA_FILE: &str = "tika-schema";
+
+/// The `SCHEMA_VERSION` the DB at `db_path` was written with, if it records one
+pub(crate) fn schema_version(db_path: &str) -> Option<u32> {
+    fs::read_to_string(Path::new(db_path).join(SCHEMA_FILE))
+        .ok()
+        .and_then(|version| version.trim().parse().ok())
+}
+
+/// Record that the DB at `db_path` is at the current `SCHEMA_VERSION`. Only call this once
+/// every document has been indexed into it, as partial writes leave older documents behind.
+pub(crate) fn write_schema_version(db_path: &str) -> Result<(), Report> {
+    fs::write(
+        Path::new(db_path).join(SCHEMA_FILE),
+        SCHEMA_VERSION.to_string(),
+    )?;
+    Ok(())
+}
+
+/// What to do with a file given more than once in one indexing run, e.g. by overlapping globs
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum DuplicatePath {
+    /// Index it the first time only
+    Skip,
+    /// Index it again, replacing the first copy
+    Overwrite,
+    /// Fail the run
+    Error,
+}
+
+impl DuplicatePath {
+    /// The policy named `name` in the `on-duplicate-path` config, if any
+    pub(crate) fn from_name(name: &str) -> Option<DuplicatePath> {
+        match name {
+            "skip" => Some(DuplicatePath::Skip),
+            "overwrite" => Some(DuplicatePath::Overwrite),
+            "error" => Some(DuplicatePath::Error),
+            _ => None,
+        }
+    }
+}
+
+impl Default for DuplicatePath {
+    fn default() -> Self {
+        DuplicatePath::Overwrite
+    }
+}
+
+/// Settings controlling how documents are indexed
+#[derive(Debug, Default)]
+pub(crate) struct IndexOptions {
+    /// When non-zero, commit the DB after every `commit_every` documents so that progress
+    /// survives an interrupted run; the DB is always committed once at the end
+    pub commit_every: usize,
+    /// Additional `chrono` formats to try when parsing document dates
+    pub date_formats: Vec<String>,
+    /// Name of the vault the documents belong to, stored on each document
+    pub vault: String,
+    /// Report documents that fail to load as JSON objects rather than `❌` lines
+    pub json_errors: bool,
+    /// Lowercase words left out of the indexed title, subtitle, summary and body
+    pub stopwords: Vec<String>,
+    /// Leave document bodies out of the index and the stored documents, so only the metadata
+    /// can be searched
+    pub metadata_only: bool,
+    /// When non-zero, store a plain text preview of up to this many characters of each body
+    pub preview_chars: usize,
+    /// How files are read into documents, e.g. as several documents for a daily log kept in
+    /// one file
+    pub parse: ParseOptions,
+    /// How long to keep retrying while another process holds the DB's write lock; zero fails
+    /// straight away
+    pub lock_timeout: Duration,
+    /// What to do with a file given more than once to `index_paths`
+    pub on_duplicate_path: DuplicatePath,
+    pub verbosity: i8,
+}
+
+/// Parse and index each of the given Markdown files into the DB at `db_path`. Returns the
+/// number of documents indexed.
+pub(crate) fn index_paths<I>(db_path: &str, paths: I, opts: &IndexOptions) -> Result<usize, Report>
+where
+    I: IntoIterator<Item = PathBuf>,
+{
+    let (mut db, mut tg) = open_writable(db_path, opts.lock_timeout)?;
+
+    let mut count = 0;
+    let mut seen = HashSet::new();
+    for path in paths {
+        if !seen.insert(path.clone()) {
+            match opts.on_duplicate_path {
+                DuplicatePath::Skip => continue,
+                DuplicatePath::Overwrite => {}
+                DuplicatePath::Error => {
+                    return Err(eyre!("{} was given more than once", path.display()))
+                }
+            }
+        }
+        match load(&path, opts) {
+            Ok(tikadocs) => {
+                for tikadoc in tikadocs {
+                    profile::time(Phase::Index, || {
+                        update_index(&mut db, &mut tg, &tikadoc, opts)
+                    })?;
+                    count += 1;
+                    if opts.verbosity > 0 {
+                        println!("{}", status(Status::Ok, &tikadoc.filename));
+                    }
+                    if opts.commit_every > 0 && count % opts.commit_every == 0 {
+                        profile::time(Phase::Commit, || db.commit())?;
+                    }
+                }
+            }
+            Err(e) => e.report(opts.json_errors),
+        }
+    }
+
+    profile::time(Phase::Commit, || db.commit())?;
+
+    Ok(count)
+}
+
+/// Parse and index a single Markdown file into the DB at `db_path`, replacing any earlier copy
+/// of it, and commit straight away
+pub(crate) fn index_file(db_path: &str, path: &PathBuf, opts: &IndexOptions) -> Result<(), Report> {
+    let tikadocs = load(path, opts)?;
+
+    let (mut db, mut tg) = open_writable(db_path, opts.lock_timeout)?;
+    for tikadoc in &tikadocs {
+        update_index(&mut db, &mut tg, tikadoc, opts)?;
+    }
+    db.commit()?;
+
+    Ok(())
+}
+
+/// Move the Markdown file at `old` to `new` and update the DB at `db_path` to match, refusing to
+/// overwrite an existing file
+pub(crate) fn move_file(
+    db_path: &str,
+    old: &PathBuf,
+    new: &PathBuf,
+    opts: &IndexOptions,
+) -> Result<(), Report> {
+    if new.exists() {
+        return Err(eyre!("Refusing to overwrite {}", new.display()));
+    }
+    // The documents may set their own filenames, which is what they're stored under
+    let old_filenames: Vec<String> = match parse_documents(old, &opts.parse) {
+        Ok(tikadocs) => tikadocs.into_iter().map(|doc| doc.filename).collect(),
+        Err(_) => vec![old
+            .file_name()
+            .and_then(|name| name.to_str())
+            .map(String::from)
+            .ok_or_else(|| eyre!("Not a file: {}", old.display()))?],
+    };
+    fs::rename(old, new)?;
+
+    let tikadocs = load(new, opts)?;
+    let (mut db, mut tg) = open_writable(db_path, opts.lock_timeout)?;
+    for old_filename in &old_filenames {
+        db.delete_document(&unique_id(old_filename))?;
+    }
+    for tikadoc in &tikadocs {
+        update_index(&mut db, &mut tg, tikadoc, opts)?;
+    }
+    db.commit()?;
+
+    Ok(())
+}
+
+/// The `paths` whose files were modified after `since`, or all of them without a `since`,
+/// checked before any of them are parsed. Files without a readable modification time are kept,
+/// so that indexing reports them.
+pub(crate) fn changed_since<I>(paths: I, since: Option<SystemTime>) -> impl Iterator<Item = PathBuf>
+where
+    I: IntoIterator<Item = PathBuf>,
+{
+    paths.into_iter().filter(move |path| {
+        since.map_or(true, |since| {
+            fs::metadata(path)
+                .and_then(|meta| meta.modified())
+                .map_or(true, |mtime| mtime > since)
+        })
+    })
+}
+
+/// Set the modification time of the Markdown file at `path` to now and reindex it into the DB at
+/// `db_path`, so it sorts first with `query --sort modified`
+pub(crate) fn touch_file(db_path: &str, path: &PathBuf, opts: &IndexOptions) -> Result<(), Report> {
+    fs::OpenOptions::new()
+        .write(true)
+        .open(path)?
+        .set_modified(SystemTime::now())?;
+    index_file(db_path, path, opts)
+}
+
+/// Delete the document's file and remove the document from the DB at `db_path`, waiting up to
+/// `lock_timeout` for another process writing to it
+pub(crate) fn delete_file(
+    db_path: &str,
+    tikadoc: &TikaDocument,
+    lock_timeout: Duration,
+) -> Result<(), Report> {
+    // Open the DB first, so the file stays while the DB can't be updated
+    let (mut db, _) = open_writable(db_path, lock_timeout)?;
+    fs::remove_file(&tikadoc.full_path)?;
+    db.delete_document(&unique_id(&tikadoc.filename))?;
+    db.commit()?;
+
+    Ok(())
+}
+
+/// Remove the documents of the files at `paths`, which may already be gone, from the DB at
+/// `db_path`. Returns the number of documents removed.
+pub(crate) fn remove_paths(
+    db_path: &str,
+    paths: &[PathBuf],
+    opts: &IndexOptions,
+) -> Result<usize, Report> {
+    if paths.is_empty() {
+        return Ok(0);
+    }
+    // The files can't be parsed for the filenames their documents are stored under, which may
+    // be set in the frontmatter or have a `#N` section suffix, so look the documents up by the
+    // file names in their indexed paths, then keep those whose path is one of `paths`
+    let query = xapian_utils::with_file_names(paths)?;
+    let paths: HashSet<PathBuf> = paths.iter().map(|path| comparable_path(path)).collect();
+    let filenames: Vec<String> = xapian_utils::query_db(&[db_path], query, 0)?
+        .into_iter()
+        .filter(|doc| {
+            let full_path = doc.full_path.to_string_lossy();
+            paths.contains(&comparable_path(Path::new(without_section(&full_path))))
+        })
+        .map(|doc| doc.filename)
+        .collect();
+
+    let (mut db, _) = open_writable(db_path, opts.lock_timeout)?;
+    for filename in &filenames {
+        db.delete_document(&unique_id(filename))?;
+    }
+    db.commit()?;
+
+    Ok(filenames.len())
+}
+
+/// `full_path` without the `#N` suffix of a document read from one section of a file
+fn without_section(full_path: &str) -> &str {
+    match full_path.rsplit_once('#') {
+        Some((path, n)) if !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()) => path,
+        _ => full_path,
+    }
+}
+
+/// `path` with its directory resolved, so that a path found through a symlink or relative
+/// glob compares equal to the canonical one git reports, even after the file is deleted
+fn comparable_path(path: &Path) -> PathBuf {
+    let dir = path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."));
+    match (dir.canonicalize(), path.file_name()) {
+        (Ok(dir), Some(name)) => dir.join(name),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// Compact the DB at `db_path` by running `compactor`, Xapian's `xapian-compact`, over it,
+/// merging what many incremental reindexes have written into as small a DB as it can be.
+/// Returns the size of the DB in bytes before and after.
+pub(crate) fn compact(
+    db_path: &str,
+    compactor: &str,
+    opts: &IndexOptions,
+) -> Result<(u64, u64), Report> {
+    let db = Path::new(db_path);
+    let compacted = PathBuf::from(format!("{}.compacting", db_path));
+    let old = PathBuf::from(format!("{}.old", db_path));
+    let before = dir_size(db)?;
+    {
+        // Hold the write lock until the compacted DB is in place, so no write is lost
+        let _writable = open_writable(db_path, opts.lock_timeout)?;
+        if compacted.exists() {
+            fs::remove_dir_all(&compacted)?;
+        }
+        let output = Command::new(compactor)
+            .arg(db)
+            .arg(&compacted)
+            .output()
+            .map_err(|e| eyre!("Failed to run {}: {}", compactor, e))?;
+        if !output.status.success() {
+            return Err(eyre!(
+                "{} failed: {}",
+                compactor,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        // xapian-compact only copies Xapian's own files
+        if db.join(SCHEMA_FILE).exists() {
+            fs::copy(db.join(SCHEMA_FILE), compacted.join(SCHEMA_FILE))?;
+        }
+        fs::rename(db, &old)?;
+        fs::rename(&compacted, db)?;
+    }
+    fs::remove_dir_all(&old)?;
+
+    Ok((before, dir_size(db)?))
+}
+
+/// Total size in bytes of the files in the directory at `dir`
+fn dir_size(dir: &Path) -> Result<u64, Report> {
+    let mut size = 0;
+    for entry in fs::read_dir(dir)? {
+        let meta = entry?.metadata()?;
+        if meta.is_file() {
+            size += meta.len();
+        }
+    }
+    Ok(size)
+}
+
+/// Check that each of the files at `paths` loads as it would for indexing, reporting the ones
+/// that don't. Returns the number that failed.
+pub(crate) fn lint<I>(paths: I, opts: &IndexOptions) -> usize
+where
+    I: IntoIterator<Item = PathBuf>,
+{
+    let mut failed = 0;
+    for path in paths {
+        if let Err(e) = load(&path, opts) {
+            e.report(opts.json_errors);
+            failed += 1;
+        }
+    }
+    failed
+}
+
+/// `text` with each of the (lowercase) `stopwords` blanked out
+fn without_stopwords<'a>(text: &'a str, stopwords: &[String]) -> Cow<'a, str> {
+    if stopwords.is_empty() {
+        return Cow::Borrowed(text);
+    }
+
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(char::is_alphanumeric) {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = rest
+            .find(|c: char| !c.is_alphanumeric())
+            .unwrap_or_else(|| rest.len());
+        let word = &rest[..end];
+        if stopwords.contains(&word.to_lowercase()) {
+            out.push(' ');
+        } else {
+            out.push_str(word);
+        }
+        rest = &rest[end..];
+    }
+    out.push_str(rest);
+
+    Cow::Owned(out)
+}
+
+/// Term identifying the document stored for `filename`
+fn unique_id(filename: &str) -> String {
+    "Q".to_owned() + filename
+}
+
+/// Parse the documents in a Markdown file ready for indexing, checking their dates can be
+/// understood
+fn load(path: &PathBuf, opts: &IndexOptions) -> Result<Vec<TikaDocument>, LoadError> {
+    let mut tikadocs = parse_documents(path, &opts.parse)?;
+    for tikadoc in tikadocs.iter_mut() {
+        if tikadoc.parse_date(&opts.date_formats).is_err() {
+            return Err(LoadError::new(
+                path,
+                LoadErrorKind::Date,
+                format!("Unrecognized date '{}'", tikadoc.date),
+            ));
+        }
+        if opts.verbosity > 0 {
+            if let Some(warning) = tikadoc.utc_day_shift(&opts.date_formats) {
+                eprintln!("{}", warning);
+            }
+        }
+        tikadoc.vault = opts.vault.clone();
+        tikadoc.indexed = chrono::Utc::now().to_rfc3339();
+        if opts.preview_chars > 0 {
+            tikadoc.preview = preview(&tikadoc.body, opts.preview_chars);
+        }
+        if opts.metadata_only {
+            tikadoc.body.clear();
+        }
+    }
+    Ok(tikadocs)
+}
+
+/// Run `open` again while it fails on the DB's write lock being held, backing off between tries,
+/// until `timeout` has passed
+fn retry_locked<T>(
+    db_path: &str,
+    timeout: Duration,
+    mut open: impl FnMut() -> Result<T, XError>,
+) -> Result<T, Report> {
+    let started = Instant::now();
+    let mut wait = LOCK_RETRY_START;
+    loop {
+        match open() {
+            Err(XError::Xapian(DATABASE_LOCK_ERROR)) => {
+                let waited = started.elapsed();
+                if waited >= timeout {
+                    return Err(eyre!(
+                        "Another tika process is indexing {}, gave up waiting after {:.1}s",
+                        db_path,
+                        waited.as_secs_f64()
+                    ));
+                }
+                thread::sleep(wait.min(timeout - waited));
+                wait *= 2;
+            }
+            result => return Ok(result?),
+        }
+    }
+}
+
+fn open_writable(
+    db_path: &str,
+    lock_timeout: Duration,
+) -> Result<(WritableDatabase, TermGenerator), Report> {
+    // Only a new DB is known to be at the current schema, an existing one keeps whatever
+    // version it records until it's rebuilt
+    let created = !Path::new(db_path).exists();
+    let db = retry_locked(db_path, lock_timeout, || {
+        WritableDatabase::new(db_path, BRASS, DB_CREATE_OR_OPEN)
+    })?;
+    if created {
+        write_schema_version(db_path)?;
+    }
+    let mut tg = TermGenerator::new()?;
+    let mut stemmer = Stem::new("en")?;
+    tg.set_stemmer(&mut stemmer)?;
+    Ok((db, tg))
+}
+
+pub(crate) fn update_index(
+    db: &mut WritableDatabase,
+    tg: &mut TermGenerator,
+    tikadoc: &TikaDocument,
+    opts: &IndexOptions,
+) -> Result<(), Report> {
+    // Create a new Xapian Document to store attributes on the passed-in TikaDocument
+    let mut doc = Document::new()?;
+    tg.set_document(&mut doc)?;
+
+    tg.index_text_with_prefix(&tikadoc.author, "A")?;
+    for coauthor in &tikadoc.coauthors {
+        tg.index_text_with_prefix(coauthor, "A")?;
+    }
+    tg.index_text_with_prefix(&tikadoc.date_str(&opts.date_formats)?, "D")?;
+    tg.index_text_with_prefix(&tikadoc.filename, "F")?;
+    tg.index_text_with_prefix(&tikadoc.full_path.clone().into_string().unwrap(), "F")?;
+    tg.index_text_with_prefix(&tikadoc.id, "XID")?;
+    for dir in Path::new(&tikadoc.full_path).ancestors().skip(1) {
+        if !dir.as_os_str().is_empty() {
+            tg.index_text_with_prefix(&dir_id(dir), DIR_PREFIX)?;
+        }
+    }
+    let stopwords = &opts.stopwords;
+    tg.index_text_with_prefix(&without_stopwords(&tikadoc.title, stopwords), "S")?;
+    for alias in &tikadoc.aliases {
+        tg.index_text_with_prefix(&without_stopwords(alias, stopwords), "S")?;
+    }
+    tg.index_text_with_prefix(&without_stopwords(&tikadoc.subtitle, stopwords), "XS")?;
+    tg.index_text_with_prefix(&without_stopwords(&tikadoc.summary, stopwords), "XSUMMARY")?;
+    for tag in &tikadoc.tags {
+        tg.index_text_with_prefix(&tag, "K")?;
+    }
+    tg.index_text_with_prefix(&tikadoc.status, "XSTATUS")?;
+    tg.index_text_with_prefix(&tikadoc.source_url, "XSOURCE")?;
+
+    tg.index_text(&without_stopwords(&tikadoc.body, stopwords))?;
+
+    // Store the date as a value for range queries
+    doc.add_double(
+        DATE_SLOT,
+        tikadoc.parse_date(&opts.date_formats)?.timestamp() as f64,
+    )?;
+    doc.add_double(DRAFT_SLOT, if tikadoc.draft { 1.0 } else { 0.0 })?;
+    doc.add_double(
+        ATTACHMENTS_SLOT,
+        if tikadoc.has_attachments { 1.0 } else { 0.0 },
+    )?;
+
+    // Convert the TikaDocument into JSON and set it in the DB for retrieval later
+    doc.set_data(&tikadoc.to_stored()?)?;
+
+    let id = unique_id(&tikadoc.filename);
+    doc.add_boolean_term(&id)?;
+    db.replace_document(&id, &mut doc)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod index_paths_tests {
+    use super::*;
+    use crate::fixtures::{titles, FixtureVault};
+    use crate::xapian_utils::{all_document_data, match_data};
+    use std::fs;
+    use xapian_rusty::{Query, XapianOp};
+
+    #[test]
+    fn commit_every_document() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let db_path = dir.path().join("db");
+        let db_path = db_path.to_str().unwrap();
+
+        let paths: Vec<PathBuf> = (0..3)
+            .map(|i| {
+                let path = dir.path().join(format!("note{}.md", i));
+                fs::write(
+                    &path,
+                    format!(
+                        "---\ndate: 2021-06-22T12:48:16-0400\ntags: test\ntitle: Note {}\n---\nBody\n",
+                        i
+                    ),
+                )
+                .unwrap();
+                path
+            })
+            .collect();
+
+        // Paths are consumed lazily, so a fresh reader can check what has been committed before
+        // each subsequent document is indexed
+        let paths = paths.into_iter().enumerate().map(|(i, path)| {
+            if i > 0 {
+                let visible = all_document_data(&[db_path])
+                    .expect("Failed to open DB")
+                    .count();
+                assert_eq!(i, visible);
+            }
+            path
+        });
+
+        let opts = IndexOptions {
+            commit_every: 1,
+            ..Default::default()
+        };
+        assert_eq!(
+            3,
+            index_paths(db_path, paths, &opts).expect("Failed to index")
+        );
+        assert_eq!(3, all_document_data(&[db_path]).unwrap().count());
+    }
+
+    #[test]
+    fn custom_date_format_is_range_queryable() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let db_path = dir.path().join("db");
+        let db_path = db_path.to_str().unwrap();
+        let path = dir.path().join("note.md");
+        fs::write(
+            &path,
+            "---\ndate: 22/06/2021\ntags: test\ntitle: Custom date\n---\nBody\n",
+        )
+        .unwrap();
+
+        let opts = IndexOptions {
+            date_formats: vec![String::from("%d/%m/%Y")],
+            ..Default::default()
+        };
+        assert_eq!(1, index_paths(db_path, vec![path], &opts).unwrap());
+
+        // 2021-06-22T00:00:00Z
+        let day_start = 1_624_320_000.0;
+        let on_day = Query::new_range(
+            XapianOp::OpValueRange,
+            DATE_SLOT,
+            day_start,
+            day_start + 86_399.0,
+        )
+        .unwrap();
+        assert_eq!(1, match_data(&[db_path], on_day, 10).unwrap().count());
+        let day_after = Query::new_range(
+            XapianOp::OpValueRange,
+            DATE_SLOT,
+            day_start + 86_400.0,
+            f64::MAX,
+        )
+        .unwrap();
+        assert_eq!(0, match_data(&[db_path], day_after, 10).unwrap().count());
+    }
+
+    #[test]
+    fn only_files_changed_since_are_indexed() {
+        let mut vault = FixtureVault::new();
+        let now = SystemTime::now();
+        let day = Duration::from_secs(86_400);
+        for (name, age) in &[("old", 30), ("recent", 2), ("today", 0)] {
+            let path = vault.note(&format!("{}.md", name), &[], "Synced notes");
+            fs::OpenOptions::new()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(now - day * *age)
+                .unwrap();
+        }
+
+        let paths: Vec<PathBuf> = ["old", "recent", "today"]
+            .iter()
+            .map(|name| vault.path().join(format!("{}.md", name)))
+            .collect();
+        let opts = Default::default();
+        let indexed = index_paths(
+            &vault.db_path(),
+            changed_since(paths, Some(now - day * 7)),
+            &opts,
+        );
+        assert_eq!(2, indexed.expect("Failed to index"));
+        assert_eq!(vec!["recent", "today"], titles(vault.search("synced")));
+    }
+
+    #[test]
+    fn duplicate_paths_follow_the_policy() {
+        for (policy, indexed) in &[
+            (DuplicatePath::Skip, Some(1)),
+            (DuplicatePath::Overwrite, Some(2)),
+            (DuplicatePath::Error, None),
+        ] {
+            let mut vault = FixtureVault::new();
+            let path = vault.note("twice.md", &[], "Given twice");
+            let opts = IndexOptions {
+                on_duplicate_path: *policy,
+                ..Default::default()
+            };
+            let result = index_paths(&vault.db_path(), vec![path.clone(), path], &opts);
+            match indexed {
+                Some(indexed) => {
+                    assert_eq!(*indexed, result.expect("Failed to index"), "{:?}", policy);
+                    assert_eq!(1, vault.search("given").len(), "{:?}", policy);
+                }
+                None => {
+                    let e = result.expect_err("Duplicate path was indexed");
+                    assert!(e.to_string().contains("more than once"), "{}", e);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn plaintext_is_searchable_by_body() {
+        let mut vault = FixtureVault::new();
+        vault.file(
+            "quick thoughts.txt",
+            "Remember to renew the parking permit\n",
+        );
+        let opts = IndexOptions {
+            parse: ParseOptions {
+                plaintext: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(1, vault.index_with(&opts));
+
+        let matches = vault.search("parking");
+        assert_eq!(1, matches.len());
+        assert_eq!("quick thoughts", matches[0].title);
+        assert_eq!(matches[0].modified, matches[0].date);
+        assert!(matches[0].tags.is_empty());
+        assert!(matches[0].author.is_empty());
+    }
+
+    #[test]
+    fn org_keywords_are_read_as_frontmatter() {
+        let mut vault = FixtureVault::new();
+        vault.file(
+            "sync.org",
+            "#+TITLE: Weekly sync\n#+DATE: <2021-06-22 Tue 12:48>\n#+FILETAGS: :work:meetings:\n\n\
+             * Agenda\nRenew the parking permit\n",
+        );
+        let opts = IndexOptions {
+            parse: ParseOptions {
+                org: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(1, vault.index_with(&opts));
+
+        let matches = vault.search("parking");
+        assert_eq!(1, matches.len());
+        assert_eq!("Weekly sync", matches[0].title);
+        assert_eq!("2021-06-22T12:48:00+00:00", matches[0].date);
+        assert_eq!(vec!["work", "meetings"], matches[0].tags);
+        assert_eq!("* Agenda\nRenew the parking permit\n", matches[0].body);
+        assert_eq!(1, vault.search("tag:meetings").len());
+    }
+}
+
+#[cfg(test)]
+mod remove_paths_tests {
+    use super::*;
+    use crate::fixtures::FixtureVault;
+    use std::ffi::OsString;
+
+    #[test]
+    fn sections_and_renamed_documents_are_removed() {
+        let mut vault = FixtureVault::new();
+        let log = vault.file(
+            "log.md",
+            "---\ndate: 2021-06-21T09:00:00-0400\ntags: log\ntitle: Monday\n---\nDaily entry\n\
+             ---\ndate: 2021-06-22T09:00:00-0400\ntags: log\ntitle: Tuesday\n---\nDaily entry\n",
+        );
+        let renamed = vault.note("renamed.md", &[("filename", "custom.md")], "Daily entry");
+        let kept = vault.note("kept.md", &[], "Daily entry");
+        // Shares the deleted file's name, so it's looked up too, but not removed
+        let namesake = vault.note(
+            "archive/log.md",
+            &[("filename", "archive-log.md")],
+            "Daily entry",
+        );
+        let opts = IndexOptions {
+            parse: ParseOptions {
+                split_sections: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(5, vault.index_with(&opts));
+
+        fs::remove_file(&log).unwrap();
+        fs::remove_file(&renamed).unwrap();
+        let removed =
+            remove_paths(&vault.db_path(), &[log, renamed], &opts).expect("Failed to remove paths");
+        assert_eq!(3, removed);
+        let mut paths: Vec<OsString> = vault
+            .search("daily")
+            .into_iter()
+            .map(|doc| doc.full_path)
+            .collect();
+        paths.sort();
+        assert_eq!(
+            vec![namesake.into_os_string(), kept.into_os_string()],
+            paths
+        );
+    }
+}
+
+#[cfg(test)]
+mod schema_version_tests {
+    use super::*;
+    use crate::fixtures::FixtureVault;
+
+    #[test]
+    fn partial_writes_leave_a_stale_version() {
+        let mut vault = FixtureVault::new();
+        let note = vault.note("note.md", &[], "Notes on schemas");
+        let gone = vault.note("gone.md", &[], "Notes on schemas");
+        vault.index();
+        let db_path = vault.db_path();
+        assert_eq!(Some(SCHEMA_VERSION), schema_version(&db_path));
+
+        // An index written before schema versions were recorded
+        fs::remove_file(Path::new(&db_path).join(SCHEMA_FILE)).unwrap();
+        let opts = IndexOptions::default();
+        index_file(&db_path, &note, &opts).expect("Failed to index file");
+        touch_file(&db_path, &note, &opts).expect("Failed to touch file");
+        remove_paths(&db_path, &[gone], &opts).expect("Failed to remove paths");
+        assert_eq!(None, schema_version(&db_path));
+
+        write_schema_version(&db_path).expect("Failed to write schema version");
+        assert_eq!(Some(SCHEMA_VERSION), schema_version(&db_path));
+    }
+}
+
+#[cfg(test)]
+mod compact_tests {
+    use super::*;
+    use crate::fixtures::FixtureVault;
+
+    /// Whether Xapian's `xapian-compact` is on the `PATH`, as `make test` puts it
+    fn have_compactor() -> bool {
+        Command::new("xapian-compact")
+            .arg("--version")
+            .output()
+            .is_ok()
+    }
+
+    #[test]
+    fn incremental_commits_are_compacted() {
+        if !have_compactor() {
+            eprintln!("Skipping, xapian-compact isn't on the PATH, run the tests with `make test`");
+            return;
+        }
+        let mut vault = FixtureVault::new();
+        for i in 0..50 {
+            vault.note(&format!("note-{}.md", i), &[], "Notes on compaction");
+        }
+        let opts = IndexOptions {
+            commit_every: 1,
+            ..Default::default()
+        };
+        vault.index_with(&opts);
+        vault.index_with(&opts);
+
+        let (before, after) =
+            compact(&vault.db_path(), "xapian-compact", &opts).expect("Failed to compact");
+        assert!(after < before, "{} >= {}", after, before);
+        assert_eq!(50, vault.search("compaction").len());
+        assert_eq!(Some(SCHEMA_VERSION), schema_version(&vault.db_path()));
+        assert!(!Path::new(&format!("{}.old", vault.db_path())).exists());
+    }
+
+    #[test]
+    fn missing_compactor_leaves_the_db_alone() {
+        let mut vault = FixtureVault::new();
+        vault.note("note.md", &[], "Notes on compaction");
+        vault.index();
+
+        let e = compact(
+            &vault.db_path(),
+            "no-such-xapian-compact",
+            &Default::default(),
+        )
+        .expect_err("Compacted without a compactor");
+        assert!(e.to_string().contains("no-such-xapian-compact"), "{}", e);
+        assert_eq!(1, vault.search("compaction").len());
+    }
+}
+
+#[cfg(test)]
+mod index_file_tests {
+    use super::*;
+    use crate::fixtures::FixtureVault;
+    use crate::tika_document::parse_file;
+    use crate::xapian_utils::{parse_user_query, query_db, DEFAULT_LIMIT};
+    use std::fs;
+
+    #[test]
+    fn new_file_is_immediately_queryable() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let db_path = dir.path().join("db");
+        let db_path = db_path.to_str().unwrap();
+        let path = dir.path().join("saved.md");
+        fs::write(
+            &path,
+            "---\ndate: 2021-06-22T12:48:16-0400\ntags: test\ntitle: Just saved\n---\nFresh words\n",
+        )
+        .unwrap();
+
+        index_file(db_path, &path, &Default::default()).expect("Failed to index file");
+
+        let query = parse_user_query("fresh ;").expect("Failed to parse");
+        let matches = query_db(&[db_path], query, DEFAULT_LIMIT).expect("Failed to query");
+        assert_eq!(1, matches.len());
+        assert_eq!("Just saved", matches[0].title);
+    }
+
+    #[test]
+    fn id_is_stable_across_reindexing() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let db_path = dir.path().join("db");
+        let db_path = db_path.to_str().unwrap();
+        let path = dir.path().join("stable.md");
+        fs::write(
+            &path,
+            "---\ndate: 2021-06-22T12:48:16-0400\ntags: test\ntitle: Stable\n---\nSame words\n",
+        )
+        .unwrap();
+
+        index_file(db_path, &path, &Default::default()).expect("Failed to index file");
+        let id = parse_file(&path).unwrap().id;
+        index_file(db_path, &path, &Default::default()).expect("Failed to reindex file");
+
+        let query = parse_user_query(&format!("id:{} ;", id)).expect("Failed to parse");
+        let matches = query_db(&[db_path], query, DEFAULT_LIMIT).expect("Failed to query");
+        assert_eq!(1, matches.len());
+        assert_eq!(id, matches[0].id);
+    }
+
+    #[test]
+    fn sections_are_indexed_separately() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let db_path = dir.path().join("db");
+        let db_path = db_path.to_str().unwrap();
+        let path = dir.path().join("log.md");
+        fs::write(
+            &path,
+            "---\ndate: 2021-06-21T09:00:00-0400\ntags: log\ntitle: Monday\n---\nDaily entry\n\
+             ---\ndate: 2021-06-22T09:00:00-0400\ntags: log\ntitle: Tuesday\n---\nDaily entry\n",
+        )
+        .unwrap();
+
+        let opts = IndexOptions {
+            parse: ParseOptions {
+                split_sections: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        index_file(db_path, &path, &opts).expect("Failed to index file");
+
+        let query = parse_user_query("daily ;").expect("Failed to parse");
+        let mut matches: Vec<(String, String)> = query_db(&[db_path], query, DEFAULT_LIMIT)
+            .expect("Failed to query")
+            .into_iter()
+            .map(|doc| (doc.title, doc.full_path.to_string_lossy().into_owned()))
+            .collect();
+        matches.sort();
+        let log = path.to_str().unwrap();
+        assert_eq!(
+            vec![
+                (String::from("Monday"), format!("{}#1", log)),
+                (String::from("Tuesday"), format!("{}#2", log)),
+            ],
+            matches
+        );
+    }
+
+    #[test]
+    fn mapped_keys_are_indexed_as_fields() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let db_path = dir.path().join("db");
+        let db_path = db_path.to_str().unwrap();
+        let path = dir.path().join("imported.md");
+        fs::write(
+            &path,
+            "---\ncreated_at: 2021-06-22T12:48:16-0400\ntags: test\nname: Imported\n---\nBody\n",
+        )
+        .unwrap();
+
+        let opts = IndexOptions {
+            parse: ParseOptions {
+                field_map: vec![
+                    (String::from("created_at"), String::from("date")),
+                    (String::from("name"), String::from("title")),
+                ],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        index_file(db_path, &path, &opts).expect("Failed to index file");
+
+        let query = parse_user_query("title:imported ;").expect("Failed to parse");
+        let matches = query_db(&[db_path], query, DEFAULT_LIMIT).expect("Failed to query");
+        assert_eq!(1, matches.len());
+        assert_eq!("2021-06-22T12:48:16-0400", matches[0].date);
+    }
+
+    #[test]
+    fn touch_makes_a_note_the_most_recent() {
+        use crate::ranking::sort_by_modified;
+        use crate::xapian_utils::match_all;
+        use std::time::Duration;
+
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let db_path = dir.path().join("db");
+        let db_path = db_path.to_str().unwrap();
+        let mut paths = Vec::new();
+        for (title, hours_ago) in &[("Stale", 2u64), ("Fresh", 1)] {
+            let path = dir.path().join(format!("{}.md", title));
+            fs::write(
+                &path,
+                format!(
+                    "---\ndate: 2021-06-22T12:48:16-0400\ntags: test\ntitle: {}\n---\nBody\n",
+                    title
+                ),
+            )
+            .unwrap();
+            fs::OpenOptions::new()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(SystemTime::now() - Duration::from_secs(hours_ago * 3600))
+                .unwrap();
+            paths.push(path);
+        }
+        index_paths(db_path, paths.clone(), &Default::default()).expect("Failed to index");
+
+        let recent = || -> Vec<String> {
+            let docs = query_db(&[db_path], match_all().unwrap(), DEFAULT_LIMIT).unwrap();
+            sort_by_modified(docs)
+                .into_iter()
+                .map(|doc| doc.title)
+                .collect()
+        };
+        assert_eq!(vec!["Fresh", "Stale"], recent());
+
+        let body = fs::read_to_string(&paths[0]).unwrap();
+        touch_file(db_path, &paths[0], &Default::default()).expect("Failed to touch");
+        assert_eq!(vec!["Stale", "Fresh"], recent());
+        assert_eq!(body, fs::read_to_string(&paths[0]).unwrap());
+    }
+
+    #[test]
+    fn every_field_survives_indexing() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let db_path = dir.path().join("db");
+        let db_path = db_path.to_str().unwrap();
+        let path = dir.path().join("full.md");
+        fs::write(
+            &path,
+            "---\nauthor: [Alice, Bob]\ndate: 2021-06-22T12:48:16-0400\ndraft: true\n\
+             status: doing\nsubtitle: Everything at once\ntags: [rust, machine learning]\n\
+             title: [Full, Complete]\n---\n# Full\n\nSee ![diagram](assets/diagram.png).\n",
+        )
+        .unwrap();
+
+        let opts = IndexOptions {
+            vault: String::from("work"),
+            preview_chars: 20,
+            ..Default::default()
+        };
+        index_file(db_path, &path, &opts).expect("Failed to index file");
+
+        let mut expected = parse_documents(&path, &opts.parse).unwrap().remove(0);
+        expected.vault = String::from("work");
+        expected.preview = preview(&expected.body, 20);
+        assert!(expected.draft && expected.has_attachments);
+        assert!(!expected.id.is_empty() && !expected.modified.is_empty());
+
+        let query = parse_user_query("title:complete ;").expect("Failed to parse");
+        let matches = query_db(&[db_path], query, DEFAULT_LIMIT).expect("Failed to query");
+        assert!(!matches[0].indexed.is_empty());
+        expected.indexed = matches[0].indexed.clone();
+        assert_eq!(vec![expected], matches);
+    }
+
+    #[test]
+    fn inline_hashtags_are_indexed_as_tags() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let db_path = dir.path().join("db");
+        let db_path = db_path.to_str().unwrap();
+        let path = dir.path().join("tagged.md");
+        fs::write(
+            &path,
+            "---\ndate: 2021-06-22T12:48:16-0400\ntags: test\ntitle: Tagged\n---\n\
+             Picked up #kubernetes today, more on #helm later\n",
+        )
+        .unwrap();
+
+        let opts = IndexOptions {
+            parse: ParseOptions {
+                inline_hashtags: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        index_file(db_path, &path, &opts).expect("Failed to index file");
+
+        for tag in &["kubernetes", "helm"] {
+            let query = parse_user_query(&format!("tag:{} ;", tag)).expect("Failed to parse");
+            let matches = query_db(&[db_path], query, DEFAULT_LIMIT).expect("Failed to query");
+            assert_eq!(1, matches.len(), "tag:{} didn't match", tag);
+            assert_eq!(vec!["test", "kubernetes", "helm"], matches[0].tags);
+        }
+    }
+
+    #[test]
+    fn messy_tags_are_normalized() {
+        let mut vault = FixtureVault::new();
+        vault.note(
+            "messy.md",
+            &[("tags", r#"[Kubernetes, " kubernetes", kubernetes, ""]"#)],
+            "Body",
+        );
+        let opts = IndexOptions {
+            parse: ParseOptions {
+                normalize_tags: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        vault.index_with(&opts);
+
+        let matches = vault.search("tag:kubernetes");
+        assert_eq!(1, matches.len());
+        assert_eq!(vec!["kubernetes"], matches[0].tags);
+    }
+
+    #[test]
+    fn sidecar_tags_are_merged() {
+        let mut vault = FixtureVault::new();
+        vault.note("note.md", &[("tags", "[test, rust]")], "Body");
+        // Written beside the note rather than added to the vault as a note of its own
+        fs::write(
+            vault.path().join("note.md.tags"),
+            "kubernetes\nhelm, rust\n\n",
+        )
+        .unwrap();
+        vault.index();
+
+        for tag in &["test", "kubernetes", "helm"] {
+            let matches = vault.search(&format!("tag:{}", tag));
+            assert_eq!(1, matches.len(), "tag:{} didn't match", tag);
+            assert_eq!(vec!["test", "rust", "kubernetes", "helm"], matches[0].tags);
+        }
+    }
+
+    #[test]
+    fn clipped_source_url_is_searchable() {
+        let mut vault = FixtureVault::new();
+        vault.note(
+            "clipped.md",
+            &[("source", "https://github.com/ssosik/tika")],
+            "Clipped from the web",
+        );
+        vault.note("written.md", &[], "Written by hand");
+        vault.index();
+
+        let matches = vault.search("source_url:github.com");
+        assert_eq!(1, matches.len());
+        let json = serde_json::to_value(&matches[0]).unwrap();
+        assert_eq!("https://github.com/ssosik/tika", json["source_url"]);
+
+        let written = vault.search("hand");
+        assert_eq!("", written[0].source_url);
+    }
+
+    #[test]
+    fn preview_is_stored_in_plain_text() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let db_path = dir.path().join("db");
+        let db_path = db_path.to_str().unwrap();
+        let path = dir.path().join("long.md");
+        fs::write(
+            &path,
+            format!(
+                "---\ndate: 2021-06-22T12:48:16-0400\ntags: test\ntitle: Long\n---\n\
+                 # Long\n\n{}\n",
+                "Some **bold** words and a [link](other.md) with `code`. ".repeat(20)
+            ),
+        )
+        .unwrap();
+
+        let opts = IndexOptions {
+            preview_chars: 40,
+            metadata_only: true,
+            ..Default::default()
+        };
+        index_file(db_path, &path, &opts).expect("Failed to index file");
+
+        let query = parse_user_query("title:long ;").expect("Failed to parse");
+        let matches = query_db(&[db_path], query, DEFAULT_LIMIT).expect("Failed to query");
+        let stored = &matches[0].preview;
+        assert!(stored.chars().count() <= 40, "preview too long: {}", stored);
+        assert!(stored.starts_with("Long Some bold words and a link with"));
+        assert!(
+            !stored.contains(|c: char| "#*_[]()`".contains(c)),
+            "{}",
+            stored
+        );
+        assert_eq!("", matches[0].body);
+    }
+
+    #[test]
+    fn metadata_only_leaves_out_the_body() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let db_path = dir.path().join("db");
+        let db_path = db_path.to_str().unwrap();
+        let path = dir.path().join("lean.md");
+        fs::write(
+            &path,
+            "---\ndate: 2021-06-22T12:48:16-0400\ntags: test\ntitle: Kubernetes\n---\nZebra\n",
+        )
+        .unwrap();
+
+        let opts = IndexOptions {
+            metadata_only: true,
+            ..Default::default()
+        };
+        index_file(db_path, &path, &opts).expect("Failed to index file");
+
+        let query = parse_user_query("zebra ;").expect("Failed to parse");
+        assert!(query_db(&[db_path], query, DEFAULT_LIMIT)
+            .unwrap()
+            .is_empty());
+        let query = parse_user_query("title:kubernetes ;").expect("Failed to parse");
+        let matches = query_db(&[db_path], query, DEFAULT_LIMIT).unwrap();
+        assert_eq!(1, matches.len());
+        assert_eq!("", matches[0].body);
+    }
+
+    #[test]
+    fn unparseable_file_is_an_error() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let db_path = dir.path().join("db");
+        let missing = dir.path().join("missing.md");
+        assert!(index_file(db_path.to_str().unwrap(), &missing, &Default::default()).is_err());
+    }
+}
+
+#[cfg(test)]
+mod coauthors_tests {
+    use super::*;
+    use crate::xapian_utils::{parse_user_query, query_db, DEFAULT_LIMIT};
+
+    #[test]
+    fn every_author_is_searchable() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let db_path = dir.path().join("db");
+        let db_path = db_path.to_str().unwrap();
+        let path = dir.path().join("standup.md");
+        fs::write(
+            &path,
+            "---\nauthor: [Alice, Bob]\ndate: 2021-06-22T12:48:16-0400\ntags: test\ntitle: Standup\n---\nNotes\n",
+        )
+        .unwrap();
+        index_file(db_path, &path, &Default::default()).expect("Failed to index file");
+
+        for author in &["alice", "bob"] {
+            let query = parse_user_query(&format!("author:{} ;", author)).unwrap();
+            let matches = query_db(&[db_path], query, DEFAULT_LIMIT).expect("Failed to query");
+            assert_eq!(1, matches.len(), "author:{}", author);
+            assert_eq!("Alice", matches[0].author);
+        }
+    }
+}
+
+#[cfg(test)]
+mod delete_file_tests {
+    use super::*;
+    use crate::tika_document::parse_file;
+    use crate::xapian_utils::{parse_user_query, query_db, DEFAULT_LIMIT};
+
+    #[test]
+    fn file_and_document_are_gone() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let db_path = dir.path().join("db");
+        let db_path = db_path.to_str().unwrap();
+        let path = dir.path().join("doomed.md");
+        fs::write(
+            &path,
+            "---\ndate: 2021-06-22T12:48:16-0400\ntags: test\ntitle: Doomed\n---\nObsolete note\n",
+        )
+        .unwrap();
+        index_file(db_path, &path, &Default::default()).expect("Failed to index file");
+
+        let count = || {
+            let query = parse_user_query("obsolete ;").expect("Failed to parse");
+            query_db(&[db_path], query, DEFAULT_LIMIT)
+                .expect("Failed to query")
+                .len()
+        };
+        assert_eq!(1, count());
+
+        delete_file(db_path, &parse_file(&path).unwrap(), DEFAULT_LOCK_TIMEOUT)
+            .expect("Failed to delete file");
+        assert!(!path.exists());
+        assert_eq!(0, count());
+    }
+
+    #[test]
+    fn locked_db_keeps_the_file() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let db_path = dir.path().join("db");
+        let db_path = db_path.to_str().unwrap();
+        let path = dir.path().join("kept.md");
+        fs::write(
+            &path,
+            "---\ndate: 2021-06-22T12:48:16-0400\ntags: test\ntitle: Kept\n---\nStill here\n",
+        )
+        .unwrap();
+        index_file(db_path, &path, &Default::default()).expect("Failed to index file");
+        let _holder =
+            WritableDatabase::new(db_path, BRASS, DB_CREATE_OR_OPEN).expect("Failed to open DB");
+
+        let e = delete_file(
+            db_path,
+            &parse_file(&path).unwrap(),
+            Duration::from_millis(100),
+        )
+        .expect_err("Deleted while the DB was locked");
+        assert!(e.to_string().contains("gave up waiting"), "{}", e);
+        assert!(path.exists());
+    }
+}
+
+#[cfg(test)]
+mod move_file_tests {
+    use super::*;
+    use crate::xapian_utils::{parse_user_query, query_db, DEFAULT_LIMIT};
+
+    fn note(dir: &std::path::Path, name: &str) -> PathBuf {
+        let path = dir.join(name);
+        fs::write(
+            &path,
+            "---\ndate: 2021-06-22T12:48:16-0400\ntags: test\ntitle: Moving\n---\nWandering note\n",
+        )
+        .unwrap();
+        path
+    }
+
+    #[test]
+    fn queries_return_new_path() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let db_path = dir.path().join("db");
+        let db_path = db_path.to_str().unwrap();
+        let old = note(dir.path(), "old.md");
+        index_paths(db_path, vec![old.clone()], &Default::default()).expect("Failed to index");
+
+        let new = dir.path().join("new.md");
+        move_file(db_path, &old, &new, &Default::default()).expect("Failed to move");
+        assert!(!old.exists());
+
+        let query = parse_user_query("wandering ;").expect("Failed to parse");
+        let paths: Vec<_> = query_db(&[db_path], query, DEFAULT_LIMIT)
+            .expect("Failed to query")
+            .into_iter()
+            .map(|doc| doc.full_path)
+            .collect();
+        assert_eq!(vec![new.into_os_string()], paths);
+    }
+
+    #[test]
+    fn refuses_to_overwrite() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let db_path = dir.path().join("db");
+        let old = note(dir.path(), "old.md");
+        let new = note(dir.path(), "new.md");
+        assert!(move_file(db_path.to_str().unwrap(), &old, &new, &Default::default()).is_err());
+        assert!(old.exists());
+    }
+}
+
+#[cfg(test)]
+mod stopwords_tests {
+    use super::*;
+    use crate::xapian_utils::{parse_user_query, query_db, DEFAULT_LIMIT};
+
+    #[test]
+    fn blanks_out_whole_words() {
+        let stopwords = vec![String::from("meeting")];
+        assert_eq!(
+            "Weekly  : meetings,  ",
+            without_stopwords("Weekly Meeting: meetings, meeting", &stopwords)
+        );
+    }
+
+    #[test]
+    fn stopword_query_returns_nothing() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let db_path = dir.path().join("db");
+        let db_path = db_path.to_str().unwrap();
+        let path = dir.path().join("standup.md");
+        fs::write(
+            &path,
+            "---\ndate: 2021-06-22T12:48:16-0400\ntags: test\ntitle: Meeting\n---\nMeeting about rust\n",
+        )
+        .unwrap();
+        let opts = IndexOptions {
+            stopwords: vec![String::from("meeting")],
+            ..Default::default()
+        };
+        index_paths(db_path, vec![path], &opts).expect("Failed to index");
+
+        let count = |q: &str| {
+            let query = parse_user_query(q).expect("Failed to parse");
+            query_db(&[db_path], query, DEFAULT_LIMIT)
+                .expect("Failed to query")
+                .len()
+        };
+        assert_eq!(0, count("meeting ;"));
+        assert_eq!(1, count("rust ;"));
+    }
+}
+
+#[cfg(test)]
+mod retry_locked_tests {
+    use super::*;
+    use crate::fixtures::FixtureVault;
+
+    #[test]
+    fn retries_until_the_lock_is_released() {
+        let mut tries = 0;
+        let opened = retry_locked("db", Duration::from_secs(5), || {
+            tries += 1;
+            if tries < 3 {
+                Err(XError::Xapian(DATABASE_LOCK_ERROR))
+            } else {
+                Ok(tries)
+            }
+        });
+        assert_eq!(3, opened.expect("Failed to open"));
+    }
+
+    #[test]
+    fn held_lock_fails_with_a_clear_error() {
+        let mut vault = FixtureVault::new();
+        vault.note("note.md", &[], "Body");
+        let holder = WritableDatabase::new(&vault.db_path(), BRASS, DB_CREATE_OR_OPEN)
+            .expect("Failed to open DB");
+        let opts = IndexOptions {
+            lock_timeout: Duration::from_millis(200),
+            ..Default::default()
+        };
+
+        let started = Instant::now();
+        let err = index_file(&vault.db_path(), &vault.path().join("note.md"), &opts).unwrap_err();
+        assert!(started.elapsed() >= opts.lock_timeout);
+        assert!(
+            err.to_string().contains("Another tika process is indexing"),
+            "{}",
+            err
+        );
+
+        drop(holder);
+        assert_eq!(1, vault.index_with(&opts));
+    }
+}
@@ -0,0 +1,138 @@
+use crate::tika_document::TikaDocument;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+/// Targets of the `[[wiki links]]` in `content`, without any `#heading` or `|label`, e.g. `Rust`
+/// for `[[Rust#Traits|traits]]`
+pub(crate) fn wiki_links(content: &str) -> Vec<&str> {
+    content
+        .split("[[")
+        .skip(1)
+        .filter_map(|rest| rest.find("]]").map(|end| &rest[..end]))
+        .filter(|link| !link.contains('\n'))
+        .filter_map(|link| link.split(|c| c == '|' || c == '#').next())
+        .map(str::trim)
+        .filter(|target| !target.is_empty())
+        .collect()
+}
+
+/// How many notes link to each of `docs` and how many each links to, as `(inbound, outbound)`
+/// in the same order. A link names a note by its title, an alias or its file stem, ignoring
+/// case, and counts once however often it's written; links to notes that aren't indexed count
+/// as outbound and links from a note to itself not at all.
+pub(crate) fn link_degrees(docs: &[TikaDocument]) -> Vec<(usize, usize)> {
+    let mut names: HashMap<String, usize> = HashMap::new();
+    for (i, doc) in docs.iter().enumerate() {
+        let stem = Path::new(&doc.filename)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned());
+        for name in std::iter::once(&doc.title)
+            .chain(&doc.aliases)
+            .cloned()
+            .chain(stem)
+        {
+            names.entry(name.to_lowercase()).or_insert(i);
+        }
+    }
+
+    let mut degrees = vec![(0, 0); docs.len()];
+    for (i, doc) in docs.iter().enumerate() {
+        let mut linked = HashSet::new();
+        let mut missing = HashSet::new();
+        for target in wiki_links(&doc.body) {
+            let target = target.to_lowercase();
+            match names.get(&target) {
+                Some(&j) if j == i => {}
+                Some(&j) => {
+                    linked.insert(j);
+                }
+                None => {
+                    missing.insert(target);
+                }
+            }
+        }
+        degrees[i].1 = linked.len() + missing.len();
+        for j in linked {
+            degrees[j].0 += 1;
+        }
+    }
+    degrees
+}
+
+/// The notes in `docs` that link to no note and that no note links to
+pub(crate) fn orphans(docs: &[TikaDocument]) -> Vec<&TikaDocument> {
+    docs.iter()
+        .zip(link_degrees(docs))
+        .filter(|(_, degree)| *degree == (0, 0))
+        .map(|(doc, _)| doc)
+        .collect()
+}
+
+/// The notes in `docs` with at least `min` links in and out, with their number of links, the
+/// most linked first and then by title
+pub(crate) fn hubs(docs: &[TikaDocument], min: usize) -> Vec<(usize, &TikaDocument)> {
+    let mut hubs: Vec<(usize, &TikaDocument)> = docs
+        .iter()
+        .zip(link_degrees(docs))
+        .map(|(doc, (inbound, outbound))| (inbound + outbound, doc))
+        .filter(|(links, _)| *links >= min)
+        .collect();
+    hubs.sort_by(|(a, a_doc), (b, b_doc)| b.cmp(a).then_with(|| a_doc.title.cmp(&b_doc.title)));
+    hubs
+}
+
+#[cfg(test)]
+mod wiki_links_tests {
+    use super::*;
+
+    #[test]
+    fn targets_without_headings_or_labels() {
+        assert_eq!(
+            vec!["Rust", "Go", "Tika"],
+            wiki_links("See [[Rust#Traits|traits]], [[ Go ]] and [[Tika]]; not [[]] or [[open")
+        );
+    }
+}
+
+#[cfg(test)]
+mod link_degree_tests {
+    use super::*;
+    use crate::fixtures::FixtureVault;
+    use crate::xapian_utils::match_all;
+
+    /// `a` links to `b`, `c` by an alias, itself and a missing note; `b` links back to `a`;
+    /// nothing links to or from `d`
+    fn linked_vault() -> Vec<TikaDocument> {
+        let mut vault = FixtureVault::new();
+        vault.note(
+            "a.md",
+            &[],
+            "See [[b]], [[B|again]], [[sea|c]], [[a]] and [[nowhere]]",
+        );
+        vault.note("b.md", &[], "Back to [[A#Top]]");
+        vault.note("c.md", &[("aliases", "[sea]")], "No links here");
+        vault.note("d.md", &[], "Nor here");
+        vault.index();
+        vault.run(match_all().unwrap())
+    }
+
+    fn titles(docs: Vec<&TikaDocument>) -> Vec<&str> {
+        docs.into_iter().map(|doc| doc.title.as_str()).collect()
+    }
+
+    #[test]
+    fn orphans_have_no_links_either_way() {
+        let docs = linked_vault();
+        assert_eq!(vec!["d"], titles(orphans(&docs)));
+    }
+
+    #[test]
+    fn hubs_have_enough_links() {
+        let docs = linked_vault();
+        let hubs: Vec<(usize, &str)> = hubs(&docs, 2)
+            .into_iter()
+            .map(|(links, doc)| (links, doc.title.as_str()))
+            .collect();
+        assert_eq!(vec![(4, "a"), (2, "b")], hubs);
+    }
+}
@@ -1,13 +1,341 @@
+mod clipboard;
+mod diff;
+mod doctor;
+mod editor;
+mod export;
+#[cfg(test)]
+mod fixtures;
+mod fuzzy;
+mod git;
+mod indexer;
+mod links;
+mod output;
+mod profile;
+mod ranking;
+mod repl;
+mod rpc;
+mod tags;
 mod tika_document;
 mod tui_app;
 mod util;
 mod xapian_utils;
 
-use crate::tika_document::{parse_file, TikaDocument};
-use crate::util::glob_files;
-use clap::{App, Arg, ArgMatches, SubCommand};
-use color_eyre::Report;
-use xapian_rusty::{Document, Stem, TermGenerator, WritableDatabase, BRASS, DB_CREATE_OR_OPEN};
+use crate::output::Status;
+use crate::tika_document::{
+    filter_fields, parse_documents, preview, DateSource, FileMeta, LoadError, LoadErrorKind,
+    ParseOptions, TikaDocument,
+};
+use crate::util::{
+    ensure_index_dir, glob_base, glob_files, index_dir, parse_since, read_config, saved_queries,
+    select_vaults, split_args, stopwords, vaults, Vault,
+};
+use crate::xapian_utils::DEFAULT_DB_NAME;
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use color_eyre::{eyre::eyre, Report};
+
+/// The `query` subcommand, also used to parse the definitions of saved queries
+fn query_subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("query")
+        .about("Query the index")
+        .arg(
+            Arg::with_name("query")
+                .required_unless("all")
+                .multiple(true)
+                .help("Query string, words passed separately are joined with spaces"),
+        )
+        .arg(
+            Arg::with_name("all")
+                .long("all")
+                .conflicts_with("query")
+                .help(
+                    "Match every document, e.g. to browse them all with --sort; returns every \
+                    match unless --limit is given",
+                ),
+        )
+        .arg(
+            Arg::with_name("format")
+                .long("format")
+                .value_name("FORMAT")
+                .possible_values(&["json", "plain", "md"])
+                .default_value("json")
+                .help(
+                    "Print the matches as a JSON object, as a title and path per line, or as a \
+                    Markdown list of links",
+                ),
+        )
+        .arg(
+            Arg::with_name("link-style")
+                .long("link-style")
+                .value_name("STYLE")
+                .possible_values(&["standard", "wiki"])
+                .default_value("standard")
+                .help(
+                    "Link to the matches as `[title](path)` or as `[[title]]` with \
+                    `--format md`",
+                ),
+        )
+        .arg(Arg::with_name("explain-query").long("explain-query").help(
+            "Print how the query was parsed to stderr before running it, naming the \
+                    field behind each term prefix",
+        ))
+        .arg(
+            Arg::with_name("under")
+                .long("under")
+                .value_name("DIRECTORY")
+                .help(
+                    "Only match documents somewhere under DIRECTORY, relative to where the \
+                    source glob starts unless absolute; needs a reindex of older indexes",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("width")
+                .long("width")
+                .value_name("N")
+                .help(
+                    "Cut `--format plain` lines down to N columns, defaults to the width of the \
+                    terminal when writing to one; 0 never cuts them",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("separator")
+                .long("separator")
+                .value_name("SEP")
+                .default_value("\\n")
+                .help(
+                    "End each `--format plain` line with SEP rather than a newline; `\\n`, \
+                    `\\t` and `\\0` stand for a newline, tab and NUL",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("header")
+                .long("header")
+                .help("Start `--format plain` output with a line naming its columns"),
+        )
+        .arg(
+            Arg::with_name("has-attachments")
+                .long("has-attachments")
+                .help("Only match documents that link to local files such as images"),
+        )
+        .arg(
+            Arg::with_name("on")
+                .long("on")
+                .value_name("YYYY-MM-DD")
+                .help("Only match documents dated on this day in the local timezone")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("status")
+                .long("status")
+                .value_name("STATUS")
+                .multiple(true)
+                .number_of_values(1)
+                .help("Only match documents with this frontmatter `status`; repeat to match any"),
+        )
+        .arg(
+            Arg::with_name("tag")
+                .long("tag")
+                .value_name("TAG")
+                .multiple(true)
+                .number_of_values(1)
+                .help("Only match documents tagged with TAG; repeat to match any"),
+        )
+        .arg(
+            Arg::with_name("all-tags")
+                .long("all-tags")
+                .requires("tag")
+                .help("Only match documents tagged with every --tag"),
+        )
+        .arg(
+            Arg::with_name("not-tag")
+                .long("not-tag")
+                .value_name("TAG")
+                .multiple(true)
+                .number_of_values(1)
+                .help("Leave out documents tagged with TAG"),
+        )
+        .arg(
+            Arg::with_name("has-field")
+                .long("has-field")
+                .value_name("FIELD")
+                .multiple(true)
+                .number_of_values(1)
+                .help("Only match documents with a non-empty FIELD, e.g. `author`"),
+        )
+        .arg(
+            Arg::with_name("lacks-field")
+                .long("lacks-field")
+                .value_name("FIELD")
+                .multiple(true)
+                .number_of_values(1)
+                .help("Only match documents without a value for FIELD"),
+        )
+        .arg(Arg::with_name("recency-boost").long("recency-boost").help(
+            "Rank newer documents higher, halving the boost every \
+                    `recency-half-life-days` from the config or 30 days",
+        ))
+        .arg(
+            Arg::with_name("sort")
+                .long("sort")
+                .possible_values(&["score", "date", "modified", "title", "words", "indexed"])
+                .default_value("score")
+                .help(
+                    "Order the matches by relevance, by date with the newest first, by when \
+                    their files were last modified as of indexing, most recent first, by title \
+                    alphabetically, by word count with the longest first, or by when they were \
+                    indexed, most recent first",
+                ),
+        )
+        .arg(
+            Arg::with_name("group-by")
+                .long("group-by")
+                .value_name("FIELD")
+                .possible_values(&["tag"])
+                .conflicts_with_all(&["legacy-output", "open-all"])
+                .help(
+                    "List the matches under each of their tags, the tags with the most matches \
+                    first; untagged matches are left out",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("dedupe-by")
+                .long("dedupe-by")
+                .value_name("KEY")
+                .possible_values(&["title", "stem"])
+                .help(
+                    "Keep only the best ranked match with each title, ignoring case, or each \
+                    file name without its extension",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("limit-per-tag")
+                .long("limit-per-tag")
+                .value_name("N")
+                .requires("group-by")
+                .help("List at most N matches under each tag, counting the rest")
+                .takes_value(true),
+        )
+        .arg(Arg::with_name("paginate").long("paginate").help(
+            "Order the matches newest first by date, then by filename, rather than as \
+                    --sort says, and give each one a `cursor` to fetch the page after it with \
+                    --after",
+        ))
+        .arg(
+            Arg::with_name("after")
+                .long("after")
+                .value_name("CURSOR")
+                .help(
+                    "Return the --limit matches that come after the one with this `cursor`; \
+                    implies --paginate",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("reverse")
+                .long("reverse")
+                .help("Flip the order of the matches, e.g. oldest first with `--sort date`"),
+        )
+        .arg(
+            Arg::with_name("prefix")
+                .long("prefix")
+                .help("Match the last word of the query as a prefix, e.g. `kube` for `kubernetes`"),
+        )
+        .arg(
+            Arg::with_name("explain")
+                .long("explain")
+                .help("Report the fields each match was found in as `matched_fields`"),
+        )
+        .arg(
+            Arg::with_name("snippets")
+                .long("snippets")
+                .help("Add the words of the body around each match's first hit as `snippet`"),
+        )
+        .arg(
+            Arg::with_name("context")
+                .long("context")
+                .value_name("N")
+                .help(
+                    "Keep N words either side of the hit in snippets, defaults to 8; implies \
+                    --snippets",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("with-file-meta")
+                .long("with-file-meta")
+                .help("Add each match's file size and modification time as `file_meta`"),
+        )
+        .arg(
+            Arg::with_name("limit")
+                .long("limit")
+                .value_name("N")
+                .help("Return at most N matches, defaults to 100; 0 returns every match")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("paths-only")
+                .long("paths-only")
+                .conflicts_with_all(&[
+                    "has-field",
+                    "lacks-field",
+                    "recency-boost",
+                    "explain",
+                    "snippets",
+                    "context",
+                    "with-file-meta",
+                    "legacy-output",
+                    "output-json-array",
+                    "open-all",
+                    "paginate",
+                    "after",
+                    "sort",
+                    "dedupe-by",
+                ])
+                .help(
+                    "Print just the full path of each match, one per line and in order of \
+                    relevance, without reading the rest of the documents",
+                ),
+        )
+        .arg(
+            Arg::with_name("print0")
+                .long("print0")
+                .requires("paths-only")
+                .help("End each path with a NUL byte rather than a newline, e.g. for `xargs -0`"),
+        )
+        .arg(
+            Arg::with_name("legacy-output")
+                .long("legacy-output")
+                .help("Print each match as a separate JSON object, one per line"),
+        )
+        .arg(
+            Arg::with_name("output-json-array")
+                .long("output-json-array")
+                .conflicts_with_all(&["legacy-output", "group-by"])
+                .help(
+                    "Print the matches as a JSON array, `[]` when there are none, written out as \
+                    each match is",
+                ),
+        )
+        .arg(
+            Arg::with_name("open-all")
+                .long("open-all")
+                .help("Open every matching document in $EDITOR"),
+        )
+        .arg(
+            Arg::with_name("open-cap")
+                .long("open-cap")
+                .value_name("N")
+                .help(
+                    "Ask for confirmation before opening more than N files, defaults to \
+                    `open-all-cap` from the config or 20",
+                )
+                .takes_value(true),
+        )
+}
 
 fn setup<'a>(default_config_file: &str) -> Result<ArgMatches, Report> {
     if std::env::var("RUST_LIB_BACKTRACE").is_err() {
@@ -19,6 +347,8 @@ fn setup<'a>(default_config_file: &str) -> Result<ArgMatches, Report> {
         .version("1.0")
         .author("Steve <steve@little-fluffy.cloud>")
         .about("Things I Know About: Zettlekasten-like Markdown+FrontMatter Indexer and query tool")
+        // Words that aren't a subcommand are a query with `default-command = "query"`
+        .setting(AppSettings::AllowExternalSubcommands)
         .arg(
             Arg::with_name("config")
                 .short("c")
@@ -44,6 +374,85 @@ fn setup<'a>(default_config_file: &str) -> Result<ArgMatches, Report> {
                 .short("i")
                 .help("Index data rather than querying the DB"),
         )
+        .arg(
+            Arg::with_name("diff")
+                .long("diff")
+                .requires("update-index")
+                .help("Print what reindexing would add, update and delete without changing the DB"),
+        )
+        .arg(
+            Arg::with_name("changed-since")
+                .long("changed-since")
+                .value_name("WHEN")
+                .requires("update-index")
+                .conflicts_with("diff")
+                .help(
+                    "Only reindex files modified after WHEN, a YYYY-MM-DD day, an RFC 3339 time \
+                    or a span back from now like 30m, 1h, 2d or 1w",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("staged")
+                .long("staged")
+                .requires("update-index")
+                .conflicts_with("changed-since")
+                .help(
+                    "Only reindex the Markdown files staged in git, e.g. from a pre-commit hook, \
+                    removing the ones staged for deletion from the index",
+                ),
+        )
+        .arg(
+            Arg::with_name("profile")
+                .long("profile")
+                .requires("update-index")
+                .help(
+                    "Print how long indexing spent globbing, reading, parsing frontmatter, \
+                    working over Markdown, adding to the index and committing, to stderr",
+                ),
+        )
+        .arg(
+            Arg::with_name("tags")
+                .long("tags")
+                .help("Start the interactive finder by browsing tags"),
+        )
+        .arg(
+            Arg::with_name("ascii")
+                .long("ascii")
+                .help("Mark status lines with `[OK]`, `[ERR]` and `[WARN]` rather than emoji"),
+        )
+        .arg(
+            Arg::with_name("json-errors")
+                .long("json-errors")
+                .help("Report files that fail to load as JSON objects on stderr"),
+        )
+        .arg(
+            Arg::with_name("include-drafts")
+                .long("include-drafts")
+                .help("Include documents marked `draft: true` in query and finder results"),
+        )
+        .arg(
+            Arg::with_name("allow-delete").long("allow-delete").help(
+                "Let Ctrl-d in the interactive finder delete the highlighted note, after asking",
+            ),
+        )
+        .arg(
+            Arg::with_name("vault")
+                .long("vault")
+                .value_name("NAME")
+                .help("Use the named vault from the config, or `all` to use every vault")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("index-dir")
+                .long("index-dir")
+                .value_name("DIRECTORY")
+                .help(
+                    "Keep the indexes in DIRECTORY, created if missing; defaults to `index-dir` \
+                    from the config or `tika` under the XDG cache directory",
+                )
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name("source")
                 .short("s")
@@ -51,11 +460,171 @@ fn setup<'a>(default_config_file: &str) -> Result<ArgMatches, Report> {
                 .help("Glob path to markdown files to load")
                 .takes_value(true),
         )
+        .subcommand(query_subcommand())
+        .subcommand(
+            SubCommand::with_name("saved")
+                .about("Run a query saved in the `[queries]` config table")
+                .arg(
+                    Arg::with_name("name")
+                        .required_unless("list")
+                        .help("Name of the saved query"),
+                )
+                .arg(
+                    Arg::with_name("list")
+                        .long("list")
+                        .help("List the saved queries and their definitions"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("index-file")
+                .about("Index a single Markdown file, replacing any earlier copy of it")
+                .arg(
+                    Arg::with_name("path")
+                        .required(true)
+                        .help("Markdown file to index"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("mv")
+                .about("Move a Markdown file and update the index to match")
+                .arg(Arg::with_name("old").required(true).help("File to move"))
+                .arg(
+                    Arg::with_name("new")
+                        .required(true)
+                        .help("Where to move it, which mustn't exist yet"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("touch")
+                .about(
+                    "Mark a Markdown file as modified now and reindex it, without changing its \
+                    contents, so it sorts first with `query --sort modified`",
+                )
+                .arg(
+                    Arg::with_name("path")
+                        .required(true)
+                        .help("Markdown file to touch"),
+                ),
+        )
+        .subcommand(SubCommand::with_name("optimize").about(
+            "Compact the index with Xapian's `xapian-compact`, reporting its size before and \
+            after; worth running after many incremental reindexes",
+        ))
+        .subcommand(
+            SubCommand::with_name("repl")
+                .about("Answer queries read from stdin, one per line, with a JSON result per line"),
+        )
+        .subcommand(
+            SubCommand::with_name("doctor")
+                .about("Check the config, source documents, index and editor for problems"),
+        )
+        .subcommand(
+            SubCommand::with_name("diff")
+                .about(
+                    "Compare the documents matching two source globs, printing `<` for those \
+                    only in A, `>` for those only in B and `M` for those differing",
+                )
+                .arg(
+                    Arg::with_name("source-a")
+                        .required(true)
+                        .value_name("GLOB-A")
+                        .help("Glob path to the first set of markdown files"),
+                )
+                .arg(
+                    Arg::with_name("source-b")
+                        .required(true)
+                        .value_name("GLOB-B")
+                        .help("Glob path to the second set of markdown files"),
+                )
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .value_name("FORMAT")
+                        .possible_values(&["json", "plain"])
+                        .default_value("plain")
+                        .help("Print the differences as a JSON object or one document per line"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("get")
+                .about(
+                    "Print the indexed document with the given content ID, or the documents with \
+                    the given filenames, as JSON",
+                )
+                .arg(
+                    Arg::with_name("id")
+                        .long("id")
+                        .value_name("HASH")
+                        .required_unless("file")
+                        .conflicts_with("file")
+                        .help("The document's `id`, a hash of its frontmatter and body")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("file")
+                        .long("file")
+                        .value_name("FILENAME")
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help(
+                            "Print the document with this filename, or `null` if there's none, \
+                            on its own line and in the order given; `-` reads filenames from \
+                            stdin, one per line",
+                        ),
+                ),
+        )
         .subcommand(
-            SubCommand::with_name("query")
-                .about("Query the index")
-                .arg(Arg::with_name("query").required(true).help("Query string")),
+            SubCommand::with_name("export")
+                .about("Export every indexed document as JSON, one document per line")
+                .arg(
+                    Arg::with_name("no-body")
+                        .long("no-body")
+                        .conflicts_with("bodies")
+                        .help(
+                            "Leave out each document's body, summary and preview, for only its \
+                            metadata",
+                        ),
+                )
+                .arg(Arg::with_name("bodies").long("bodies").help(
+                    "Include each document's body, summary and preview, as by default, failing \
+                    when `index-body = false` keeps them out of the index",
+                )),
+        )
+        .subcommand(
+            SubCommand::with_name("lint")
+                .about(
+                    "Check that the source files load as they would for indexing, reporting \
+                    the ones that don't and exiting with 1 if any",
+                )
+                .arg(
+                    Arg::with_name("staged")
+                        .long("staged")
+                        .help("Only check the Markdown files staged in git"),
+                ),
         )
+        .subcommand(SubCommand::with_name("orphans").about(
+            "List the notes that no `[[wiki link]]` points to and that link to no note, as a \
+            title and path per line",
+        ))
+        .subcommand(
+            SubCommand::with_name("hubs")
+                .about(
+                    "List the notes with the most `[[wiki links]]` in and out, as the number of \
+                    links, title and path per line",
+                )
+                .arg(
+                    Arg::with_name("min")
+                        .long("min")
+                        .value_name("N")
+                        .default_value("5")
+                        .help("Only list notes with at least N links")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(SubCommand::with_name("rpc").about(
+            "Answer line-delimited JSON-RPC 2.0 requests on stdin, for `search`, `get`, \
+            `index_file` and `tags`, keeping the index open between them",
+        ))
         .get_matches();
 
     tui_app::setup_panic();
@@ -66,43 +635,467 @@ fn setup<'a>(default_config_file: &str) -> Result<ArgMatches, Report> {
 fn main() -> Result<(), Report> {
     let default_config_file = shellexpand::tilde("~/.config/tika/tika.toml");
     let cli = setup(&default_config_file)?;
+    let config = read_config(cli.value_of("config").unwrap())
+        .unwrap_or_else(|_| toml::Value::Table(toml::value::Table::new()));
+    output::set_ascii(
+        cli.is_present("ascii")
+            || config
+                .get("ascii")
+                .and_then(|v| v.as_bool())
+                .unwrap_or(false),
+    );
+
+    let index_dir = index_dir(cli.value_of("index-dir"), &config);
+    let default_db_path = index_dir
+        .join(DEFAULT_DB_NAME)
+        .to_string_lossy()
+        .into_owned();
+
+    if cli.subcommand_matches("doctor").is_some() {
+        let checks = doctor::run_checks(
+            cli.value_of("config").unwrap(),
+            &default_db_path,
+            std::env::var("EDITOR").ok(),
+        );
+        if !doctor::report(&checks) {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    if let Some(diff_cli) = cli.subcommand_matches("diff") {
+        let cfg_file = cli.value_of("config").unwrap();
+        let verbosity = cli.occurrences_of("v") as i8;
+        let json_errors = cli.is_present("json-errors");
+        let changes = diff::diff_vaults(
+            load_documents(
+                cfg_file,
+                diff_cli.value_of("source-a"),
+                verbosity,
+                json_errors,
+            ),
+            load_documents(
+                cfg_file,
+                diff_cli.value_of("source-b"),
+                verbosity,
+                json_errors,
+            ),
+        );
+        if diff_cli.value_of("format") == Some("json") {
+            println!("{}", serde_json::to_string(&changes)?);
+        } else {
+            for filename in &changes.only_a {
+                println!("< {}", filename);
+            }
+            for filename in &changes.only_b {
+                println!("> {}", filename);
+            }
+            for filename in &changes.differing {
+                println!("M {}", filename);
+            }
+        }
+        return Ok(());
+    }
+
+    ensure_index_dir(&index_dir).map_err(|e| eyre!(e))?;
+
+    // With no vaults configured, or none selected, use the top-level source glob and the default DB
+    let selected = match cli.value_of("vault") {
+        Some(name) => select_vaults(vaults(&config, &index_dir)?, name).map_err(|e| eyre!(e))?,
+        None => vec![],
+    };
+    let db_paths: Vec<&str> = if selected.is_empty() {
+        vec![default_db_path.as_str()]
+    } else {
+        selected.iter().map(|v| v.db_path.as_str()).collect()
+    };
+
+    // If requested, only report what reindexing would change
+    if cli.is_present("diff") {
+        let cfg_file = cli.value_of("config").unwrap();
+        let verbosity = cli.occurrences_of("v") as i8;
+        let json_errors = cli.is_present("json-errors");
+        let mut on_disk = if selected.is_empty() {
+            load_documents(cfg_file, cli.value_of("source"), verbosity, json_errors)
+        } else {
+            selected
+                .iter()
+                .flat_map(|v| {
+                    load_documents(cfg_file, Some(&v.source_glob), verbosity, json_errors)
+                })
+                .collect()
+        };
+        let preview_chars = preview_chars(&config);
+        if preview_chars > 0 {
+            for doc in on_disk.iter_mut() {
+                doc.preview = preview(&doc.body, preview_chars);
+            }
+        }
+        if !index_body(&config) {
+            // The index doesn't store bodies, so don't count them as changes
+            on_disk.iter_mut().for_each(|doc| doc.body.clear());
+        }
+        let indexed = xapian_utils::documents(&db_paths)?.collect();
+
+        let changes = diff::diff_documents(indexed, on_disk);
+        for path in &changes.added {
+            println!("A {}", path);
+        }
+        for path in &changes.updated {
+            println!("M {}", path);
+        }
+        for path in &changes.deleted {
+            println!("D {}", path);
+        }
+        if changes.is_empty() && verbosity > 0 {
+            eprintln!("{}", output::status(Status::Ok, "The index is up to date"));
+        }
+        return Ok(());
+    }
+
+    let mut opts = indexer::IndexOptions {
+        commit_every: config
+            .get("commit-every")
+            .and_then(|v| v.as_integer())
+            .unwrap_or(0) as usize,
+        date_formats: config
+            .get("date-formats")
+            .and_then(|v| v.as_array())
+            .map(|formats| {
+                formats
+                    .iter()
+                    .filter_map(|f| f.as_str().map(String::from))
+                    .collect()
+            })
+            .unwrap_or_default(),
+        verbosity: cli.occurrences_of("v") as i8,
+        json_errors: cli.is_present("json-errors"),
+        stopwords: stopwords(&config)?,
+        metadata_only: !index_body(&config),
+        preview_chars: preview_chars(&config),
+        parse: parse_options(&config),
+        lock_timeout: config
+            .get("lock-timeout-secs")
+            .and_then(|v| v.as_integer())
+            .map_or(indexer::DEFAULT_LOCK_TIMEOUT, |secs| {
+                std::time::Duration::from_secs(secs.max(0) as u64)
+            }),
+        on_duplicate_path: config
+            .get("on-duplicate-path")
+            .and_then(|v| v.as_str())
+            .and_then(indexer::DuplicatePath::from_name)
+            .unwrap_or_default(),
+        ..Default::default()
+    };
+    let base_stopwords = opts.stopwords.clone();
+
+    // Commands writing to a single index
+    let single_db_path = match selected.as_slice() {
+        [] => Some(default_db_path.as_str()),
+        [vault] => {
+            opts.vault = vault.name.clone();
+            opts.stopwords.extend(vault.stopwords.iter().cloned());
+            Some(vault.db_path.as_str())
+        }
+        _ => None,
+    };
+
+    if let Some(index_cli) = cli.subcommand_matches("index-file") {
+        let db_path = single_db_path.ok_or_else(|| eyre!("index-file needs a single vault"))?;
+        let path = std::path::PathBuf::from(index_cli.value_of("path").unwrap());
+        indexer::index_file(db_path, &path, &opts)?;
+        return Ok(());
+    }
+
+    if let Some(touch_cli) = cli.subcommand_matches("touch") {
+        let db_path = single_db_path.ok_or_else(|| eyre!("touch needs a single vault"))?;
+        let path = std::path::PathBuf::from(touch_cli.value_of("path").unwrap());
+        indexer::touch_file(db_path, &path, &opts)?;
+        return Ok(());
+    }
+
+    if cli.subcommand_matches("optimize").is_some() {
+        let compactor = config
+            .get("xapian-compact")
+            .and_then(|v| v.as_str())
+            .unwrap_or("xapian-compact");
+        for db_path in &db_paths {
+            let (before, after) = indexer::compact(db_path, compactor, &opts)?;
+            println!(
+                "{}",
+                output::status(
+                    Status::Ok,
+                    format!("{}: {} bytes before, {} after", db_path, before, after)
+                )
+            );
+        }
+        return Ok(());
+    }
+
+    if let Some(mv_cli) = cli.subcommand_matches("mv") {
+        let db_path = single_db_path.ok_or_else(|| eyre!("mv needs a single vault"))?;
+        let old = std::path::PathBuf::from(mv_cli.value_of("old").unwrap());
+        let new = std::path::PathBuf::from(mv_cli.value_of("new").unwrap());
+        indexer::move_file(db_path, &old, &new, &opts)?;
+        return Ok(());
+    }
+
+    if let Some(lint_cli) = cli.subcommand_matches("lint") {
+        let sources: Vec<Option<&str>> = if selected.is_empty() {
+            vec![cli.value_of("source")]
+        } else {
+            selected
+                .iter()
+                .map(|v| Some(v.source_glob.as_str()))
+                .collect()
+        };
+        let mut failed = 0;
+        for source in sources {
+            let paths: Vec<std::path::PathBuf> = if lint_cli.is_present("staged") {
+                staged_paths(&config, source)?.changed
+            } else {
+                source_paths(&cli, source).collect()
+            };
+            failed += indexer::lint(paths, &opts);
+        }
+        if failed > 0 {
+            eprintln!(
+                "{}",
+                output::status(Status::Err, format!("{} files failed to load", failed))
+            );
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
 
     // If requested, reindex the data
     if cli.occurrences_of("update-index") > 0 {
-        let mut db = WritableDatabase::new("mydb", BRASS, DB_CREATE_OR_OPEN)?;
-        let mut tg = TermGenerator::new()?;
-        let mut stemmer = Stem::new("en")?;
-        tg.set_stemmer(&mut stemmer)?;
-
-        // TODO is there a rustier way to do this?
-        for entry in glob_files(
-            &cli.value_of("config").unwrap(),
-            cli.value_of("source"),
-            cli.occurrences_of("v") as i8,
-        )
-        .expect("Failed to read glob pattern")
+        let since = match cli.value_of("changed-since") {
+            Some(since) => Some(parse_since(since, chrono::Local::now()).map_err(|e| eyre!(e))?),
+            None => None,
+        };
+        if selected.is_empty() {
+            let source = cli.value_of("source");
+            index_source(&cli, &config, source, &default_db_path, since, &opts)?;
+        }
+        for Vault {
+            name,
+            source_glob,
+            db_path,
+            stopwords: vault_stopwords,
+        } in &selected
         {
-            match entry {
-                // TODO convert this to iterator style using map/filter
-                Ok(path) => {
-                    if let Ok(tikadoc) = parse_file(&path) {
-                        update_index(&mut db, &mut tg, &tikadoc)?;
-                        if cli.occurrences_of("v") > 0 {
-                            println!("✅ {}", tikadoc.filename);
+            opts.vault = name.clone();
+            opts.stopwords = base_stopwords
+                .iter()
+                .chain(vault_stopwords)
+                .cloned()
+                .collect();
+            index_source(&cli, &config, Some(source_glob), db_path, since, &opts)?;
+        }
+        if cli.is_present("profile") {
+            eprintln!("{}", profile::report(&profile::totals()));
+        }
+    }
+
+    // Where each source glob starts, which `query --under` is relative to
+    let source_roots: Vec<std::path::PathBuf> = if selected.is_empty() {
+        cli.value_of("source")
+            .or_else(|| config.get("source-glob").and_then(|v| v.as_str()))
+            .into_iter()
+            .map(|glob| glob_base(&shellexpand::tilde(glob)))
+            .collect()
+    } else {
+        selected
+            .iter()
+            .map(|v| glob_base(&shellexpand::tilde(&v.source_glob)))
+            .collect()
+    };
+
+    if let Some(query_cli) = cli.subcommand_matches("query") {
+        return run_query(
+            &cli,
+            query_cli,
+            &config,
+            &db_paths,
+            &source_roots,
+            &opts.date_formats,
+        );
+    }
+
+    if let Some(saved_cli) = cli.subcommand_matches("saved") {
+        if saved_cli.is_present("list") {
+            for (name, definition) in saved_queries(&config) {
+                println!("{}\t{}", name, definition);
+            }
+            return Ok(());
+        }
+        let query_cli = saved_query(&config, saved_cli.value_of("name").unwrap())?;
+        return run_query(
+            &cli,
+            &query_cli,
+            &config,
+            &db_paths,
+            &source_roots,
+            &opts.date_formats,
+        );
+    }
+
+    if cli.subcommand_matches("repl").is_some() {
+        let stdin = std::io::stdin();
+        let stdout = std::io::stdout();
+        repl::repl(
+            &db_paths,
+            stdin.lock(),
+            stdout.lock(),
+            cli.is_present("include-drafts"),
+            xapian_utils::DEFAULT_LIMIT,
+        )?;
+        return Ok(());
+    }
+
+    if let Some(get_cli) = cli.subcommand_matches("get") {
+        if let Some(files) = get_cli.values_of("file") {
+            use std::io::BufRead;
+            let mut filenames = Vec::new();
+            for file in files {
+                if file == "-" {
+                    for line in std::io::stdin().lock().lines() {
+                        let line = line?;
+                        if !line.trim().is_empty() {
+                            filenames.push(line.trim().to_owned());
                         }
-                    } else {
-                        eprintln!("❌ Failed to load file {}", path.display());
                     }
+                } else {
+                    filenames.push(file.to_owned());
                 }
-
-                Err(e) => eprintln!("❌ {:?}", e),
             }
+            let docs = xapian_utils::documents_by_filename(&db_paths, &filenames)?;
+            let lines = docs
+                .iter()
+                .map(serde_json::to_string)
+                .collect::<Result<Vec<_>, _>>()?;
+            let stdout = std::io::stdout();
+            output::write_lines(stdout.lock(), lines)?;
+            return Ok(());
+        }
+        let id = get_cli.value_of("id").unwrap();
+        let query = xapian_utils::parse_user_query(&format!("id:{} ;", id))?;
+        let doc = xapian_utils::query_db(&db_paths, query, 1)?
+            .pop()
+            .ok_or_else(|| eyre!("No document with id {}", id))?;
+        println!("{}", serde_json::to_string(&doc)?);
+        return Ok(());
+    }
+
+    if cli.subcommand_matches("orphans").is_some() {
+        let docs: Vec<TikaDocument> = xapian_utils::documents(&db_paths)?.collect();
+        let lines = links::orphans(&docs)
+            .into_iter()
+            .map(|doc| output::plain_line(doc, &[], false, None));
+        let stdout = std::io::stdout();
+        output::write_lines(stdout.lock(), lines)?;
+        return Ok(());
+    }
+
+    if let Some(hubs_cli) = cli.subcommand_matches("hubs") {
+        let min = hubs_cli.value_of("min").unwrap().parse()?;
+        let docs: Vec<TikaDocument> = xapian_utils::documents(&db_paths)?.collect();
+        let lines = links::hubs(&docs, min).into_iter().map(|(links, doc)| {
+            format!("{}\t{}", links, output::plain_line(doc, &[], false, None))
+        });
+        let stdout = std::io::stdout();
+        output::write_lines(stdout.lock(), lines)?;
+        return Ok(());
+    }
+
+    if cli.subcommand_matches("rpc").is_some() {
+        let mut server = rpc::Server::new(
+            &db_paths,
+            single_db_path,
+            &opts,
+            cli.is_present("include-drafts"),
+        )?;
+        let stdin = std::io::stdin();
+        let stdout = std::io::stdout();
+        rpc::serve(&mut server, stdin.lock(), stdout.lock())?;
+        return Ok(());
+    }
+
+    if let Some(export_cli) = cli.subcommand_matches("export") {
+        if export_cli.is_present("bodies") && !index_body(&config) {
+            return Err(eyre!(
+                "--bodies needs the bodies in the index, but `index-body = false` leaves them out"
+            ));
         }
+        let stdout = std::io::stdout();
+        let bodies = !export_cli.is_present("no-body");
+        export::export(&db_paths, std::io::BufWriter::new(stdout.lock()), bodies)?;
+        return Ok(());
+    }
 
-        db.commit()?;
+    if let (word, Some(external_cli)) = cli.subcommand() {
+        let args = std::iter::once(String::from(word))
+            .chain(external_cli.values_of_lossy("").unwrap_or_default());
+        let query_cli = implicit_query(&config, args)?;
+        return run_query(
+            &cli,
+            &query_cli,
+            &config,
+            &db_paths,
+            &source_roots,
+            &opts.date_formats,
+        );
+    }
+
+    // The finder needs raw mode, which needs a terminal on both ends
+    if !tui_app::has_terminal() {
+        if cli.occurrences_of("update-index") > 0 {
+            // Indexing from a script or cron job is done
+            return Ok(());
+        }
+        eprintln!(
+            "{}",
+            output::status(
+                Status::Err,
+                "The interactive finder requires a terminal; use `tika query` instead"
+            )
+        );
+        std::process::exit(2);
     }
 
-    let mut iter = IntoIterator::into_iter(tui_app::interactive_query()?); // strings is moved here
+    // Documents in the default DB have no vault name
+    let vault_names: Vec<&str> = if selected.is_empty() {
+        vec![""]
+    } else {
+        selected.iter().map(|v| v.name.as_str()).collect()
+    };
+    let finder_opts = tui_app::FinderOptions {
+        browse_tags: cli.is_present("tags"),
+        include_drafts: cli.is_present("include-drafts"),
+        allow_delete: cli.is_present("allow-delete"),
+        suggest_on_empty: suggest_on_empty(&config),
+        pinned_tags: config
+            .get("pinned-tags")
+            .and_then(|v| v.as_array())
+            .map(|tags| {
+                tags.iter()
+                    .filter_map(|t| t.as_str().map(String::from))
+                    .collect()
+            })
+            .unwrap_or_default(),
+        fuzzy: config.get("finder-mode").and_then(|v| v.as_str()) == Some("fuzzy"),
+        quick_open: config
+            .get("finder-quick-open")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false),
+        lock_timeout: opts.lock_timeout,
+    };
+    let mut iter = IntoIterator::into_iter(tui_app::interactive_query(
+        &db_paths,
+        &vault_names,
+        &finder_opts,
+    )?); // strings is moved here
     while let Some(s) = iter.next() {
         // next() moves a string out of the iter
         println!("{}", s);
@@ -111,33 +1104,562 @@ fn main() -> Result<(), Report> {
     Ok(())
 }
 
-fn update_index(
-    db: &mut WritableDatabase,
-    tg: &mut TermGenerator,
-    tikadoc: &TikaDocument,
+/// Run the query described by the `query` subcommand arguments and print the matches
+fn run_query(
+    cli: &ArgMatches,
+    query_cli: &ArgMatches,
+    config: &toml::Value,
+    db_paths: &[&str],
+    source_roots: &[std::path::PathBuf],
+    date_formats: &[String],
 ) -> Result<(), Report> {
-    // Create a new Xapian Document to store attributes on the passed-in TikaDocument
-    let mut doc = Document::new()?;
-    tg.set_document(&mut doc)?;
+    let user_query = query_cli
+        .values_of("query")
+        .unwrap_or_default()
+        .collect::<Vec<_>>()
+        .join(" ");
+    let (filters, user_query) = xapian_utils::extract_filters(&user_query)?;
+    let mut qstr = user_query.clone();
+    // Add a trailing ` ;` to the query to hint to Nom that it has a "full" string
+    qstr.push_str(&" ;");
+    let mut query = if user_query.is_empty() {
+        // The query was nothing but filters
+        xapian_utils::match_all()?
+    } else if query_cli.is_present("prefix") {
+        xapian_utils::parse_prefix_query(&qstr)?
+    } else {
+        xapian_utils::parse_user_query(&qstr)?
+    };
+    if filters.after.is_some() || filters.before.is_some() {
+        query = xapian_utils::in_date_range(query, filters.after, filters.before, &chrono::Local)?;
+    }
+    if !index_body(config) && !xapian_utils::body_terms(&qstr).is_empty() {
+        eprintln!(
+            "{}",
+            output::status(
+                Status::Warn,
+                "Body search is unavailable with `index-body = false`, words without a field \
+                prefix such as `title:` won't match"
+            )
+        );
+    }
+    if !cli.is_present("include-drafts") {
+        query = xapian_utils::exclude_drafts(query)?;
+    }
+    if query_cli.is_present("has-attachments") {
+        query = xapian_utils::with_attachments(query)?;
+    }
+    if let Some(day) = query_cli.value_of("on") {
+        let day = chrono::NaiveDate::parse_from_str(day, "%Y-%m-%d")
+            .map_err(|e| eyre!("Invalid --on date '{}': {}", day, e))?;
+        query = xapian_utils::on_day(query, day, &chrono::Local)?;
+    }
+    if let Some(under) = query_cli.value_of("under") {
+        let under = std::path::Path::new(under);
+        let dirs: Vec<std::path::PathBuf> = if under.is_absolute() {
+            vec![under.to_path_buf()]
+        } else {
+            source_roots.iter().map(|root| root.join(under)).collect()
+        };
+        query = xapian_utils::under(query, &dirs)?;
+    }
+    if let Some(statuses) = query_cli.values_of("status") {
+        query = xapian_utils::with_status(query, &statuses.collect::<Vec<_>>())?;
+    }
+    if let Some(tags) = query_cli.values_of("tag") {
+        let all = query_cli.is_present("all-tags");
+        query = xapian_utils::with_tags(query, &tags.collect::<Vec<_>>(), all)?;
+    }
+    if let Some(tags) = query_cli.values_of("not-tag") {
+        query = xapian_utils::without_tags(query, &tags.collect::<Vec<_>>())?;
+    }
+    let limit = match query_cli.value_of("limit") {
+        Some(limit) => limit.parse()?,
+        None if query_cli.is_present("all") => 0,
+        None => xapian_utils::DEFAULT_LIMIT,
+    };
+    if query_cli.is_present("explain-query") {
+        eprintln!("{}", xapian_utils::explain_query(&mut query));
+    }
+    if query_cli.is_present("paths-only") {
+        if !filters.has.is_empty() {
+            return Err(eyre!("`has:` filters can't be combined with --paths-only"));
+        }
+        let paths = xapian_utils::query_paths(db_paths, query, limit)?;
+        let print0 = query_cli.is_present("print0");
+        let stdout = std::io::stdout();
+        if query_cli.is_present("reverse") {
+            let mut paths: Vec<_> = paths.collect();
+            paths.reverse();
+            output::write_paths(stdout.lock(), paths, print0)?;
+        } else {
+            output::write_paths(stdout.lock(), paths, print0)?;
+        }
+        return Ok(());
+    }
+    let has: Vec<&str> = query_cli
+        .values_of("has-field")
+        .unwrap_or_default()
+        .chain(filters.has.iter().map(String::as_str))
+        .collect();
+    let lacks: Vec<&str> = query_cli
+        .values_of("lacks-field")
+        .unwrap_or_default()
+        .collect();
+    let paginate = query_cli.is_present("paginate") || query_cli.is_present("after");
+    let sort = query_cli.value_of("sort");
+    let dedupe_by = query_cli
+        .value_of("dedupe-by")
+        .and_then(ranking::DedupeKey::from_name);
+    let fetch_limit = fetch_limit(query_cli, limit, !has.is_empty() || !lacks.is_empty());
+    let mut matches = filter_fields(
+        xapian_utils::query_db(db_paths, query, fetch_limit)?,
+        &has,
+        &lacks,
+    )?;
+    if matches.is_empty() && suggest_on_empty(config) {
+        let include_drafts = cli.is_present("include-drafts");
+        let suggestions =
+            xapian_utils::suggestions(db_paths, &qstr, include_drafts, xapian_utils::SUGGESTIONS)?;
+        if let Some(message) = xapian_utils::no_matches_message(&suggestions) {
+            eprintln!("{}", message);
+        }
+    }
+    let boost = config
+        .get("recency-boost")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false);
+    if boost || query_cli.is_present("recency-boost") {
+        let half_life = config
+            .get("recency-half-life-days")
+            .and_then(|v| v.as_float().or_else(|| v.as_integer().map(|i| i as f64)))
+            .unwrap_or(ranking::DEFAULT_HALF_LIFE_DAYS);
+        matches = ranking::recency_boost(matches, half_life, chrono::Utc::now(), date_formats);
+    }
+    matches = ranking::weighted(matches);
+    let promote = config
+        .get("promote-exact-title")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false);
+    if promote {
+        matches = ranking::promote_exact_titles(matches, &user_query);
+    }
+    if let Some(key) = dedupe_by {
+        matches = ranking::dedupe_by(matches, key);
+    }
+    if paginate {
+        let after = query_cli.value_of("after");
+        matches = ranking::page(matches, after, limit, date_formats).map_err(|e| eyre!(e))?;
+    } else {
+        match sort {
+            Some("date") => matches = ranking::sort_by_date(matches, date_formats),
+            Some("modified") => matches = ranking::sort_by_modified(matches),
+            Some("title") => matches = ranking::sort_by_title(matches),
+            Some("words") => matches = ranking::sort_by_words(matches),
+            Some("indexed") => matches = ranking::sort_by_indexed(matches),
+            _ => {}
+        }
+        if query_cli.is_present("reverse") {
+            matches.reverse();
+        }
+        if limit > 0 {
+            matches.truncate(limit);
+        }
+    }
+    if query_cli.is_present("snippets") || query_cli.is_present("context") {
+        let context = match query_cli.value_of("context") {
+            Some(context) => context.parse()?,
+            None => output::DEFAULT_CONTEXT,
+        };
+        let terms = xapian_utils::query_terms(&qstr);
+        for m in matches.iter_mut() {
+            m.snippet = output::snippet(&m.body, &terms, context);
+        }
+    }
+    if query_cli.is_present("with-file-meta") {
+        for m in matches.iter_mut() {
+            m.file_meta = Some(FileMeta::read(&m.full_path));
+        }
+    }
+    if query_cli.is_present("explain") {
+        let terms = xapian_utils::query_terms(&qstr);
+        for m in matches.iter_mut() {
+            m.matched_fields = output::matched_fields(m, &terms);
+        }
+    }
+
+    let groups = match query_cli.value_of("group-by") {
+        Some(_) => {
+            let limit_per_tag = match query_cli.value_of("limit-per-tag") {
+                Some(limit) => limit.parse()?,
+                None => 0,
+            };
+            Some(tags::group_by_tag(&matches, limit_per_tag))
+        }
+        None => None,
+    };
+
+    let stdout = std::io::stdout();
+    if query_cli.is_present("open-all") {
+        let cap = match query_cli.value_of("open-cap") {
+            Some(cap) => cap.parse()?,
+            None => config
+                .get("open-all-cap")
+                .and_then(|v| v.as_integer())
+                .map(|cap| cap as usize)
+                .unwrap_or(editor::DEFAULT_OPEN_CAP),
+        };
+        let paths: Vec<String> = matches
+            .iter()
+            .filter_map(|m| m.full_path.to_str().map(String::from))
+            .collect();
+        editor::open_all(&paths, cap)?;
+    } else if query_cli.value_of("format") == Some("plain") {
+        let terms = xapian_utils::query_terms(&qstr);
+        let styled = termion::is_tty(&stdout);
+        let width = match query_cli.value_of("width") {
+            Some(width) => Some(width.parse()?).filter(|&width| width > 0),
+            None if styled => termion::terminal_size()
+                .ok()
+                .map(|(columns, _)| columns as usize),
+            None => None,
+        };
+        let header = if query_cli.is_present("header") {
+            let vaults = matches.iter().any(|m| !m.vault.is_empty());
+            Some(output::plain_header(vaults))
+        } else {
+            None
+        };
+        let line = |m: &TikaDocument| output::plain_line(m, &terms, styled, width);
+        let lines: Box<dyn Iterator<Item = String> + '_> = match &groups {
+            Some(groups) => Box::new(output::grouped_lines(groups, line).into_iter()),
+            None => Box::new(matches.iter().map(line)),
+        };
+        let separator = output::unescape_separator(query_cli.value_of("separator").unwrap());
+        output::write_separated(stdout.lock(), header.into_iter().chain(lines), &separator)?;
+    } else if query_cli.value_of("format") == Some("md") {
+        if groups.is_some() {
+            return Err(eyre!("--group-by can't be combined with --format md"));
+        }
+        let wiki = query_cli.value_of("link-style") == Some("wiki");
+        let lines = matches.iter().map(|m| output::markdown_line(m, wiki));
+        output::write_lines(stdout.lock(), lines)?;
+    } else if query_cli.is_present("output-json-array") {
+        output::write_json_array(stdout.lock(), &matches)?;
+    } else if query_cli.is_present("legacy-output") {
+        let lines = matches
+            .iter()
+            .map(serde_json::to_string)
+            .collect::<Result<Vec<_>, _>>()?;
+        output::write_lines(stdout.lock(), lines)?;
+    } else if let Some(groups) = groups {
+        let result = output::GroupedResult::new(groups, matches.len());
+        let result = serde_json::to_string(&result)?;
+        output::write_lines(stdout.lock(), std::iter::once(result))?;
+    } else {
+        let result = serde_json::to_string(&output::QueryResult::new(matches))?;
+        output::write_lines(stdout.lock(), std::iter::once(result))?;
+    }
+    Ok(())
+}
+
+/// Number of matches to ask Xapian for to print `limit` of them, where 0 means every match. A
+/// page, or the first matches in another order than relevance, can come from anywhere in the
+/// matches, and presence filters and deduplication can drop any of them, so those need every
+/// match, cut down to the limit afterwards.
+fn fetch_limit(query_cli: &ArgMatches, limit: usize, field_filters: bool) -> usize {
+    let reordered = matches!(query_cli.value_of("sort"), Some(sort) if sort != "score")
+        || query_cli.is_present("reverse");
+    if reordered
+        || field_filters
+        || query_cli.is_present("paginate")
+        || query_cli.is_present("after")
+        || query_cli.is_present("dedupe-by")
+    {
+        0
+    } else {
+        limit
+    }
+}
+
+/// Whether document bodies are indexed, turned off by `index-body = false` in the config
+fn index_body(config: &toml::Value) -> bool {
+    config
+        .get("index-body")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(true)
+}
+
+/// Length of the body previews stored when indexing, set by `preview-chars`; 0 stores none
+fn preview_chars(config: &toml::Value) -> usize {
+    config
+        .get("preview-chars")
+        .and_then(|v| v.as_integer())
+        .unwrap_or(0)
+        .max(0) as usize
+}
 
-    tg.index_text_with_prefix(&tikadoc.author, "A")?;
-    tg.index_text_with_prefix(&tikadoc.date_str()?, "D")?;
-    tg.index_text_with_prefix(&tikadoc.filename, "F")?;
-    tg.index_text_with_prefix(&tikadoc.full_path.clone().into_string().unwrap(), "F")?;
-    tg.index_text_with_prefix(&tikadoc.title, "S")?;
-    tg.index_text_with_prefix(&tikadoc.subtitle, "XS")?;
-    for tag in &tikadoc.tags {
-        tg.index_text_with_prefix(&tag, "K")?;
+/// How to read files into documents, set by `multi-document-files`, `default-date`,
+/// `title-from-first-line`, `inline-hashtags` and the `[field-map]` table
+fn parse_options(config: &toml::Value) -> ParseOptions {
+    let defaults = ParseOptions::default();
+    ParseOptions {
+        split_sections: config
+            .get("multi-document-files")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false),
+        field_map: config
+            .get("field-map")
+            .and_then(|v| v.as_table())
+            .map(|map| {
+                map.iter()
+                    .filter_map(|(key, field)| Some((key.clone(), String::from(field.as_str()?))))
+                    .collect()
+            })
+            .unwrap_or_default(),
+        default_date: config
+            .get("default-date")
+            .and_then(|v| v.as_array())
+            .map(|sources| {
+                sources
+                    .iter()
+                    .filter_map(|source| DateSource::from_name(source.as_str()?))
+                    .collect()
+            })
+            .unwrap_or(defaults.default_date),
+        title_from_first_line: config
+            .get("title-from-first-line")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false),
+        inline_hashtags: config
+            .get("inline-hashtags")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false),
+        plaintext: config
+            .get("index-plaintext")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false),
+        org: config
+            .get("index-org")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false),
+        normalize_tags: config
+            .get("normalize-tags")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false),
+        source_url_key: config
+            .get("source-url-key")
+            .and_then(|v| v.as_str())
+            .map(String::from)
+            .unwrap_or(defaults.source_url_key),
+        ..defaults
     }
+}
 
-    tg.index_text(&tikadoc.body)?;
+/// Whether to suggest documents for queries matching nothing, set by `suggest-on-empty`
+fn suggest_on_empty(config: &toml::Value) -> bool {
+    config
+        .get("suggest-on-empty")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false)
+}
 
-    // Convert the TikaDocument into JSON and set it in the DB for retrieval later
-    doc.set_data(&serde_json::to_string(&tikadoc).unwrap())?;
+/// Arguments of the `query` subcommand for the saved query `name`
+fn saved_query(config: &toml::Value, name: &str) -> Result<ArgMatches<'static>, Report> {
+    let (_, definition) = saved_queries(config)
+        .into_iter()
+        .find(|(saved, _)| saved == name)
+        .ok_or_else(|| eyre!("No saved query named '{}' in the config", name))?;
+    let args = std::iter::once(String::from("query")).chain(split_args(&definition));
+    query_subcommand()
+        .get_matches_from_safe(args)
+        .map_err(|e| eyre!("Saved query '{}' is invalid: {}", name, e.message))
+}
 
-    let id = "Q".to_owned() + &tikadoc.filename;
-    doc.add_boolean_term(&id)?;
-    db.replace_document(&id, &mut doc)?;
+/// Arguments of the `query` subcommand for `tika <args>` without a subcommand, which is only a
+/// query with `default-command = "query"` in the config
+fn implicit_query<I>(config: &toml::Value, args: I) -> Result<ArgMatches<'static>, Report>
+where
+    I: IntoIterator<Item = String>,
+{
+    let mut args = args.into_iter().peekable();
+    if config.get("default-command").and_then(|v| v.as_str()) != Some("query") {
+        return Err(eyre!(
+            "Unknown subcommand '{}'; set `default-command = \"query\"` in the config to search \
+            for words given without one",
+            args.peek().map(String::as_str).unwrap_or_default()
+        ));
+    }
+    query_subcommand()
+        .get_matches_from_safe(std::iter::once(String::from("query")).chain(args))
+        .map_err(|e| eyre!("{}", e.message))
+}
 
+/// Index the files matching `source`, or the config's source glob, into the DB at `db_path`:
+/// those changed since `since`, or with `--staged` the staged ones, also removing the ones
+/// staged for deletion. Reindexing every file brings the DB up to the current schema version.
+fn index_source(
+    cli: &ArgMatches,
+    config: &toml::Value,
+    source: Option<&str>,
+    db_path: &str,
+    since: Option<std::time::SystemTime>,
+    opts: &indexer::IndexOptions,
+) -> Result<(), Report> {
+    if cli.is_present("staged") {
+        let staged = staged_paths(config, source)?;
+        indexer::index_paths(db_path, staged.changed, opts)?;
+        indexer::remove_paths(db_path, &staged.deleted, opts)?;
+    } else {
+        let paths = indexer::changed_since(source_paths(cli, source), since);
+        indexer::index_paths(db_path, paths, opts)?;
+        if since.is_none() {
+            indexer::write_schema_version(db_path)?;
+        }
+    }
     Ok(())
 }
+
+/// Markdown files staged in git under where `source`, or the config's source glob, starts
+fn staged_paths(config: &toml::Value, source: Option<&str>) -> Result<git::Staged, Report> {
+    let source = source
+        .or_else(|| config.get("source-glob").and_then(|v| v.as_str()))
+        .ok_or_else(|| eyre!("--staged needs a source glob"))?;
+    git::staged(&glob_base(&shellexpand::tilde(source)))
+}
+
+/// Paths matching the source glob, reporting the entries that can't be read
+fn source_paths<'a>(
+    cli: &ArgMatches,
+    source: Option<&str>,
+) -> impl Iterator<Item = std::path::PathBuf> + 'a {
+    let json_errors = cli.is_present("json-errors");
+    profile::time(profile::Phase::Glob, || {
+        glob_files(
+            &cli.value_of("config").unwrap(),
+            source,
+            cli.occurrences_of("v") as i8,
+        )
+    })
+    .expect("Failed to read glob pattern")
+    .into_iter()
+    .filter_map(move |entry| match entry {
+        Ok(path) => Some(path),
+        Err(e) => {
+            LoadError::new(e.path(), LoadErrorKind::Io, e.error()).report(json_errors);
+            None
+        }
+    })
+}
+
+/// Parse every Markdown document matching the source glob, reporting the ones that fail
+fn load_documents(
+    cfg_file: &str,
+    source: Option<&str>,
+    verbosity: i8,
+    json_errors: bool,
+) -> Vec<TikaDocument> {
+    let parse_opts = read_config(cfg_file)
+        .map(|config| parse_options(&config))
+        .unwrap_or_default();
+    let mut docs = Vec::new();
+    for entry in glob_files(cfg_file, source, verbosity).expect("Failed to read glob pattern") {
+        match entry {
+            Ok(path) => match parse_documents(&path, &parse_opts) {
+                Ok(tikadocs) => docs.extend(tikadocs),
+                Err(e) => e.report(json_errors),
+            },
+            Err(e) => LoadError::new(e.path(), LoadErrorKind::Io, e.error()).report(json_errors),
+        }
+    }
+    docs
+}
+
+#[cfg(test)]
+mod saved_query_tests {
+    use super::*;
+
+    fn config() -> toml::Value {
+        r#"
+            [queries]
+            standup = "tags:work --limit 5 --has-field author standup --recency-boost"
+            broken = "tags:work --no-such-flag"
+        "#
+        .parse()
+        .unwrap()
+    }
+
+    #[test]
+    fn runs_with_stored_string_and_flags() {
+        let query_cli = saved_query(&config(), "standup").expect("Failed to parse saved query");
+        let words: Vec<&str> = query_cli.values_of("query").unwrap().collect();
+        assert_eq!(vec!["tags:work", "standup"], words);
+        assert_eq!(Some("5"), query_cli.value_of("limit"));
+        assert_eq!(Some("author"), query_cli.value_of("has-field"));
+        assert!(query_cli.is_present("recency-boost"));
+        assert!(!query_cli.is_present("prefix"));
+    }
+
+    #[test]
+    fn unknown_names_and_flags_are_errors() {
+        assert!(saved_query(&config(), "missing").is_err());
+        assert!(saved_query(&config(), "broken").is_err());
+    }
+}
+
+#[cfg(test)]
+mod fetch_limit_tests {
+    use super::*;
+
+    fn fetch(args: &[&str], field_filters: bool) -> usize {
+        let query_cli = query_subcommand()
+            .get_matches_from_safe(std::iter::once("query").chain(args.iter().copied()))
+            .expect("Failed to parse query");
+        fetch_limit(&query_cli, 10, field_filters)
+    }
+
+    #[test]
+    fn relevance_order_fetches_only_the_limit() {
+        assert_eq!(10, fetch(&["rust"], false));
+        assert_eq!(10, fetch(&["rust", "--sort", "score"], false));
+    }
+
+    #[test]
+    fn other_orders_and_filters_fetch_every_match() {
+        assert_eq!(0, fetch(&["rust", "--sort", "date"], false));
+        assert_eq!(0, fetch(&["rust", "--reverse"], false));
+        assert_eq!(0, fetch(&["rust", "--dedupe-by", "title"], false));
+        assert_eq!(0, fetch(&["rust", "--paginate"], false));
+        assert_eq!(0, fetch(&["rust"], true));
+    }
+}
+
+#[cfg(test)]
+mod implicit_query_tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| String::from(*a)).collect()
+    }
+
+    #[test]
+    fn words_are_a_query_when_configured() {
+        let config: toml::Value = "default-command = \"query\"".parse().unwrap();
+        let query_cli = implicit_query(&config, args(&["rust", "--limit", "5"])).unwrap();
+        let words: Vec<&str> = query_cli.values_of("query").unwrap().collect();
+        assert_eq!(vec!["rust"], words);
+        assert_eq!(Some("5"), query_cli.value_of("limit"));
+    }
+
+    #[test]
+    fn unknown_subcommand_otherwise() {
+        let config: toml::Value = "default-command = \"finder\"".parse().unwrap();
+        let e = implicit_query(&config, args(&["rust"])).unwrap_err();
+        assert!(e.to_string().contains("Unknown subcommand 'rust'"));
+        assert!(implicit_query(
+            &toml::Value::from(toml::value::Table::new()),
+            args(&["rust"])
+        )
+        .is_err());
+    }
+}
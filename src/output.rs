@@ -0,0 +1,821 @@
+use crate::tags::TagGroup;
+use crate::tika_document::TikaDocument;
+use serde::Serialize;
+use std::ffi::OsString;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use termion::style;
+use unicode_width::UnicodeWidthChar;
+
+/// Version of the `QueryResult` JSON shape, bumped only for changes that aren't additive
+pub(crate) const QUERY_RESULT_VERSION: u32 = 1;
+
+/// JSON envelope around the results of `tika query`
+#[derive(Debug, Serialize)]
+pub(crate) struct QueryResult {
+    pub version: u32,
+    pub documents: Vec<TikaDocument>,
+    pub total: usize,
+}
+
+impl QueryResult {
+    pub(crate) fn new(documents: Vec<TikaDocument>) -> QueryResult {
+        QueryResult {
+            version: QUERY_RESULT_VERSION,
+            total: documents.len(),
+            documents,
+        }
+    }
+}
+
+/// JSON envelope around the results of `tika query --group-by tag`, `total` counting each
+/// document once
+#[derive(Debug, Serialize)]
+pub(crate) struct GroupedResult {
+    pub version: u32,
+    pub groups: Vec<TagGroup>,
+    pub total: usize,
+}
+
+impl GroupedResult {
+    pub(crate) fn new(groups: Vec<TagGroup>, total: usize) -> GroupedResult {
+        GroupedResult {
+            version: QUERY_RESULT_VERSION,
+            groups,
+            total,
+        }
+    }
+}
+
+/// Each group's tag on a line of its own, followed by a `line` for each of its documents and a
+/// `+N more` note for any beyond the cap, both indented
+pub(crate) fn grouped_lines<F>(groups: &[TagGroup], line: F) -> Vec<String>
+where
+    F: Fn(&TikaDocument) -> String,
+{
+    let mut lines = Vec::new();
+    for group in groups {
+        lines.push(group.tag.clone());
+        lines.extend(group.documents.iter().map(|doc| format!("  {}", line(doc))));
+        if group.more > 0 {
+            lines.push(format!("  +{} more", group.more));
+        }
+    }
+    lines
+}
+
+/// Kind of outcome a status line reports, marked with an emoji or, in ASCII mode, a bracketed word
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Status {
+    Ok,
+    Err,
+    Warn,
+}
+
+impl Status {
+    pub(crate) fn glyph(self, ascii: bool) -> &'static str {
+        match (self, ascii) {
+            (Status::Ok, false) => "✅",
+            (Status::Err, false) => "❌",
+            (Status::Warn, false) => "⚠️",
+            (Status::Ok, true) => "[OK]",
+            (Status::Err, true) => "[ERR]",
+            (Status::Warn, true) => "[WARN]",
+        }
+    }
+}
+
+/// Whether status lines use ASCII rather than emoji, set once at startup by `--ascii`
+static ASCII: AtomicBool = AtomicBool::new(false);
+
+pub(crate) fn set_ascii(ascii: bool) {
+    ASCII.store(ascii, Ordering::Relaxed);
+}
+
+/// `message` marked with the glyph for `status`, e.g. `✅ The index is up to date`
+pub(crate) fn status(status: Status, message: impl std::fmt::Display) -> String {
+    format!(
+        "{} {}",
+        status.glyph(ASCII.load(Ordering::Relaxed)),
+        message
+    )
+}
+
+/// Write each path to `out` on its own line, or terminated by a NUL byte with `print0` for
+/// `xargs -0` and the like, as `write_lines` does
+pub(crate) fn write_paths<W, I>(mut out: W, paths: I, print0: bool) -> io::Result<()>
+where
+    W: Write,
+    I: IntoIterator<Item = OsString>,
+{
+    let terminator: &[u8] = if print0 { b"\0" } else { b"\n" };
+    for path in paths {
+        let written = out
+            .write_all(path.to_string_lossy().as_bytes())
+            .and_then(|_| out.write_all(terminator))
+            .and_then(|_| out.flush());
+        if !pipe_open(written)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Write each line to `out` as soon as it's produced, flushing it so that consumers see early
+/// results straight away. A reader closing the pipe, e.g. `| head`, quietly ends the output.
+pub(crate) fn write_lines<W, I>(out: W, lines: I) -> io::Result<()>
+where
+    W: Write,
+    I: IntoIterator<Item = String>,
+{
+    write_separated(out, lines, "\n")
+}
+
+/// Like `write_lines`, ending each line with `separator` rather than a newline
+pub(crate) fn write_separated<W, I>(mut out: W, lines: I, separator: &str) -> io::Result<()>
+where
+    W: Write,
+    I: IntoIterator<Item = String>,
+{
+    for line in lines {
+        let written = write!(out, "{}{}", line, separator).and_then(|_| out.flush());
+        if !pipe_open(written)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Write `items` to `out` as one JSON array, `[]` when there are none. Each item is serialized,
+/// written and flushed as soon as it's produced, so only one is held as JSON at a time; a
+/// reader closing the pipe quietly ends the output.
+pub(crate) fn write_json_array<W, I>(mut out: W, items: I) -> io::Result<()>
+where
+    W: Write,
+    I: IntoIterator,
+    I::Item: Serialize,
+{
+    if !pipe_open(write!(out, "["))? {
+        return Ok(());
+    }
+    for (i, item) in items.into_iter().enumerate() {
+        let separator = if i == 0 { "" } else { "," };
+        let written = write!(out, "{}", separator)
+            .and_then(|_| serde_json::to_writer(&mut out, &item).map_err(io::Error::from))
+            .and_then(|_| out.flush());
+        if !pipe_open(written)? {
+            return Ok(());
+        }
+    }
+    pipe_open(writeln!(out, "]").and_then(|_| out.flush())).map(|_| ())
+}
+
+/// Whether output can carry on after a write, which it can't once the reader has gone away
+fn pipe_open(written: io::Result<()>) -> io::Result<bool> {
+    match written {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Wrap each word in `text` that starts with one of the (lowercase) query `terms` in bold, so
+/// stemmed and partial matches are emphasized too. Nothing is styled unless `styled` is set,
+/// i.e. when writing to a terminal.
+pub(crate) fn highlight(text: &str, terms: &[String], styled: bool) -> String {
+    if !styled || terms.is_empty() {
+        return text.to_owned();
+    }
+
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(char::is_alphanumeric) {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = rest
+            .find(|c: char| !c.is_alphanumeric())
+            .unwrap_or_else(|| rest.len());
+        let word = &rest[..end];
+        if word_matches(word, terms) {
+            out.push_str(&format!("{}{}{}", style::Bold, word, style::Reset));
+        } else {
+            out.push_str(word);
+        }
+        rest = &rest[end..];
+    }
+    out.push_str(rest);
+
+    out
+}
+
+/// Whether `word` starts with one of the (lowercase) query `terms`
+fn word_matches(word: &str, terms: &[String]) -> bool {
+    let lower = word.to_lowercase();
+    terms.iter().any(|t| lower.starts_with(t.as_str()))
+}
+
+/// Names of the document's fields containing a word matched by one of the query `terms`, in
+/// the same sense as `highlight`
+pub(crate) fn matched_fields(doc: &TikaDocument, terms: &[String]) -> Vec<String> {
+    let contains = |text: &str| {
+        text.split(|c: char| !c.is_alphanumeric())
+            .any(|word| !word.is_empty() && word_matches(word, terms))
+    };
+    let mut fields = Vec::new();
+    for (name, text) in &[
+        ("title", &doc.title),
+        ("subtitle", &doc.subtitle),
+        ("author", &doc.author),
+        ("filename", &doc.filename),
+    ] {
+        if contains(text) {
+            fields.push(String::from(*name));
+        }
+    }
+    if doc.coauthors.iter().any(|coauthor| contains(coauthor)) {
+        fields.push(String::from("coauthors"));
+    }
+    if doc.tags.iter().any(|tag| contains(tag)) {
+        fields.push(String::from("tags"));
+    }
+    if contains(&doc.body) {
+        fields.push(String::from("body"));
+    }
+    fields
+}
+
+/// Number of words either side of a match kept in a snippet unless asked otherwise
+pub(crate) const DEFAULT_CONTEXT: usize = 8;
+
+/// The first word of `body` matching one of the query `terms`, in the same sense as
+/// `highlight`, with up to `context` words either side of it. Empty when nothing matches.
+pub(crate) fn snippet(body: &str, terms: &[String], context: usize) -> String {
+    let words: Vec<&str> = body.split_whitespace().collect();
+    let hit = words.iter().position(|word| {
+        word.split(|c: char| !c.is_alphanumeric())
+            .any(|part| !part.is_empty() && word_matches(part, terms))
+    });
+    let hit = match hit {
+        Some(hit) => hit,
+        None => return String::new(),
+    };
+    let start = hit.saturating_sub(context);
+    let end = (hit + context + 1).min(words.len());
+
+    let mut out = words[start..end].join(" ");
+    if start > 0 {
+        out.insert_str(0, "… ");
+    }
+    if end < words.len() {
+        out.push_str(" …");
+    }
+    out
+}
+
+/// Human-readable, tab-separated result line: the title followed by the full path. With a
+/// `width`, the line is cut down to that many terminal columns.
+pub(crate) fn plain_line(
+    doc: &TikaDocument,
+    terms: &[String],
+    styled: bool,
+    width: Option<usize>,
+) -> String {
+    let mut line = format!("{}\t{}", doc.title, doc.full_path.to_string_lossy());
+    if !doc.vault.is_empty() {
+        line = format!("{}\t{}", line, doc.vault);
+    }
+    if let Some(width) = width {
+        line = truncate(&line, width);
+    }
+    // Only the title is highlighted
+    match line.find('\t') {
+        Some(end) => format!("{}{}", highlight(&line[..end], terms, styled), &line[end..]),
+        None => highlight(&line, terms, styled),
+    }
+}
+
+/// Column names for `plain_line`, with the vault column when the lines are labeled with one
+pub(crate) fn plain_header(vaults: bool) -> String {
+    if vaults {
+        String::from("title\tpath\tvault")
+    } else {
+        String::from("title\tpath")
+    }
+}
+
+/// A `--separator` as typed, with `\n`, `\t`, `\0` and `\\` escapes turned into the characters
+/// they stand for
+pub(crate) fn unescape_separator(separator: &str) -> String {
+    let mut unescaped = String::new();
+    let mut chars = separator.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('t') => unescaped.push('\t'),
+            Some('0') => unescaped.push('\0'),
+            Some('\\') => unescaped.push('\\'),
+            Some(other) => {
+                unescaped.push('\\');
+                unescaped.push(other);
+            }
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+/// Markdown list item linking to the document, as `- [title](full_path)` or, with `wiki`, as
+/// `- [[title]]`
+pub(crate) fn markdown_line(doc: &TikaDocument, wiki: bool) -> String {
+    if wiki {
+        return format!("- [[{}]]", doc.title);
+    }
+    let title = doc.title.replace('[', "\\[").replace(']', "\\]");
+    let path = doc.full_path.to_string_lossy();
+    // Link destinations with spaces or parentheses have to be wrapped in angle brackets
+    if path.contains(|c: char| c.is_whitespace() || c == '(' || c == ')') {
+        format!("- [{}](<{}>)", title, path)
+    } else {
+        format!("- [{}]({})", title, path)
+    }
+}
+
+/// Columns between tab stops, as most terminals place them
+const TAB_STOP: usize = 8;
+
+/// `line` cut down to at most `width` terminal columns, ending in `…` when anything was cut.
+/// Wide characters such as CJK take two columns, and tabs run to the next tab stop.
+pub(crate) fn truncate(line: &str, width: usize) -> String {
+    let advance = |column: usize, c: char| {
+        if c == '\t' {
+            (column / TAB_STOP + 1) * TAB_STOP
+        } else {
+            column + c.width().unwrap_or(0)
+        }
+    };
+    if line.chars().fold(0, advance) <= width {
+        return line.to_owned();
+    }
+
+    let mut out = String::new();
+    let mut column = 0;
+    for c in line.chars() {
+        let next = advance(column, c);
+        // Leave a column for the ellipsis
+        if next >= width {
+            break;
+        }
+        out.push(c);
+        column = next;
+    }
+    if width > 0 {
+        out.push('…');
+    }
+    out
+}
+
+#[cfg(test)]
+mod highlight_tests {
+    use super::*;
+
+    fn terms() -> Vec<String> {
+        vec![String::from("rust")]
+    }
+
+    #[test]
+    fn tty_brackets_matches_with_escape_codes() {
+        assert_eq!(
+            "Learning \u{1b}[1mRust\u{1b}[m and \u{1b}[1mrustup\u{1b}[m!",
+            highlight("Learning Rust and rustup!", &terms(), true)
+        );
+    }
+
+    #[test]
+    fn piped_output_is_plain() {
+        assert_eq!(
+            "Learning Rust and rustup!",
+            highlight("Learning Rust and rustup!", &terms(), false)
+        );
+    }
+
+    #[test]
+    fn plain_line_has_title_and_path() {
+        let doc = TikaDocument {
+            title: String::from("Rust notes"),
+            full_path: "/notes/rust.md".into(),
+            ..Default::default()
+        };
+        assert_eq!(
+            "Rust notes\t/notes/rust.md",
+            plain_line(&doc, &terms(), false, None)
+        );
+    }
+
+    #[test]
+    fn plain_line_is_labeled_with_vault() {
+        let doc = TikaDocument {
+            title: String::from("Rust notes"),
+            full_path: "/notes/rust.md".into(),
+            vault: String::from("work"),
+            ..Default::default()
+        };
+        assert_eq!(
+            "Rust notes\t/notes/rust.md\twork",
+            plain_line(&doc, &terms(), false, None)
+        );
+    }
+}
+
+#[cfg(test)]
+mod query_result_tests {
+    use super::*;
+
+    #[test]
+    fn envelope_has_version_and_total() {
+        let docs = vec![
+            TikaDocument {
+                title: String::from("One"),
+                ..Default::default()
+            },
+            TikaDocument {
+                title: String::from("Two"),
+                ..Default::default()
+            },
+        ];
+        let json = serde_json::to_value(QueryResult::new(docs)).unwrap();
+        assert_eq!(
+            QUERY_RESULT_VERSION,
+            json["version"].as_u64().unwrap() as u32
+        );
+        assert_eq!(2, json["total"].as_u64().unwrap());
+        assert_eq!(2, json["documents"].as_array().unwrap().len());
+        assert_eq!("Two", json["documents"][1]["title"]);
+    }
+}
+
+#[cfg(test)]
+mod grouped_lines_tests {
+    use super::*;
+
+    #[test]
+    fn tag_then_indented_documents() {
+        let doc = |title: &str| TikaDocument {
+            title: String::from(title),
+            ..Default::default()
+        };
+        let groups = vec![
+            TagGroup {
+                tag: String::from("rust"),
+                documents: vec![doc("one"), doc("two")],
+                more: 3,
+            },
+            TagGroup {
+                tag: String::from("go"),
+                documents: vec![doc("three")],
+                more: 0,
+            },
+        ];
+        assert_eq!(
+            vec!["rust", "  one", "  two", "  +3 more", "go", "  three"],
+            grouped_lines(&groups, |doc| doc.title.clone())
+        );
+    }
+}
+
+#[cfg(test)]
+mod matched_fields_tests {
+    use super::*;
+
+    #[test]
+    fn only_tags() {
+        let doc = TikaDocument {
+            title: String::from("Weekly review"),
+            tags: vec![String::from("planning"), String::from("machine learning")],
+            body: String::from("Went over the backlog"),
+            ..Default::default()
+        };
+        let terms = vec![String::from("learn")];
+        assert_eq!(vec!["tags"], matched_fields(&doc, &terms));
+    }
+
+    #[test]
+    fn several_fields() {
+        let doc = TikaDocument {
+            title: String::from("Rust notes"),
+            body: String::from("Notes about rustc"),
+            ..Default::default()
+        };
+        let terms = vec![String::from("rust")];
+        assert_eq!(vec!["title", "body"], matched_fields(&doc, &terms));
+    }
+}
+
+#[cfg(test)]
+mod markdown_line_tests {
+    use super::*;
+
+    fn doc(title: &str, path: &str) -> TikaDocument {
+        TikaDocument {
+            title: String::from(title),
+            full_path: path.into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn standard_link() {
+        assert_eq!(
+            "- [Rust notes](/notes/rust.md)",
+            markdown_line(&doc("Rust notes", "/notes/rust.md"), false)
+        );
+    }
+
+    #[test]
+    fn awkward_titles_and_paths_are_escaped() {
+        assert_eq!(
+            "- [\\[draft\\] Rust](</notes/rust notes.md>)",
+            markdown_line(&doc("[draft] Rust", "/notes/rust notes.md"), false)
+        );
+    }
+
+    #[test]
+    fn wiki_link() {
+        assert_eq!(
+            "- [[Rust notes]]",
+            markdown_line(&doc("Rust notes", "/notes/rust.md"), true)
+        );
+    }
+}
+
+#[cfg(test)]
+mod truncate_tests {
+    use super::*;
+    use unicode_width::UnicodeWidthStr;
+
+    #[test]
+    fn short_line_is_untouched() {
+        assert_eq!("Rust notes", truncate("Rust notes", 10));
+    }
+
+    #[test]
+    fn long_title_is_cut_to_the_width() {
+        let doc = TikaDocument {
+            title: String::from("A very long title about Rust"),
+            full_path: "/notes/rust.md".into(),
+            ..Default::default()
+        };
+        let line = plain_line(&doc, &[String::from("rust")], false, Some(12));
+        assert_eq!("A very long…", line);
+        assert_eq!(12, line.width());
+    }
+
+    #[test]
+    fn wide_characters_take_two_columns() {
+        // Each of these takes two columns, so only four fit before the ellipsis
+        let line = truncate("日本語のメモについて", 9);
+        assert_eq!("日本語の…", line);
+        assert_eq!(9, line.width());
+        // A wide character that would straddle the last column is dropped whole
+        assert_eq!("日本語…", truncate("日本語のメモについて", 8));
+    }
+
+    #[test]
+    fn tabs_run_to_the_next_tab_stop() {
+        assert_eq!("Rust\t/no…", truncate("Rust\t/notes/rust.md", 12));
+    }
+}
+
+#[cfg(test)]
+mod snippet_tests {
+    use super::*;
+
+    const BODY: &str = "One two three four five six seven kubernetes eight nine ten eleven twelve";
+
+    #[test]
+    fn wider_context_is_longer() {
+        let terms = vec![String::from("kube")];
+        let narrow = snippet(BODY, &terms, 1);
+        let wide = snippet(BODY, &terms, 3);
+        assert_eq!("… seven kubernetes eight …", narrow);
+        assert_eq!("… five six seven kubernetes eight nine ten …", wide);
+        assert!(wide.len() > narrow.len());
+        assert_eq!(BODY, snippet(BODY, &terms, 20));
+    }
+
+    #[test]
+    fn no_match_is_empty() {
+        assert_eq!("", snippet(BODY, &[String::from("rust")], DEFAULT_CONTEXT));
+    }
+}
+
+#[cfg(test)]
+mod status_tests {
+    use super::*;
+
+    #[test]
+    fn ascii_glyphs() {
+        assert_eq!("✅", Status::Ok.glyph(false));
+        assert_eq!("[OK]", Status::Ok.glyph(true));
+        assert_eq!("❌", Status::Err.glyph(false));
+        assert_eq!("[ERR]", Status::Err.glyph(true));
+        assert_eq!("[WARN]", Status::Warn.glyph(true));
+    }
+
+    #[test]
+    fn emoji_by_default() {
+        assert_eq!("✅ Indexed", status(Status::Ok, "Indexed"));
+    }
+}
+
+#[cfg(test)]
+mod write_lines_tests {
+    use super::*;
+
+    /// A pipe whose reader goes away after `open_for` writes, recording what was flushed
+    struct Pipe {
+        open_for: usize,
+        buffered: Vec<u8>,
+        flushed: Vec<String>,
+    }
+
+    impl Pipe {
+        fn new(open_for: usize) -> Pipe {
+            Pipe {
+                open_for,
+                buffered: Vec::new(),
+                flushed: Vec::new(),
+            }
+        }
+    }
+
+    impl Write for Pipe {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.open_for == 0 {
+                return Err(io::Error::from(io::ErrorKind::BrokenPipe));
+            }
+            self.open_for -= 1;
+            self.buffered.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            let buffered = std::mem::take(&mut self.buffered);
+            self.flushed.push(String::from_utf8(buffered).unwrap());
+            Ok(())
+        }
+    }
+
+    fn lines() -> impl Iterator<Item = String> {
+        (1..=3).map(|i| format!("line {}", i))
+    }
+
+    #[test]
+    fn each_line_is_flushed() {
+        let mut pipe = Pipe::new(usize::MAX);
+        write_lines(&mut pipe, lines()).unwrap();
+        assert_eq!(vec!["line 1\n", "line 2\n", "line 3\n"], pipe.flushed);
+    }
+
+    #[test]
+    fn closed_pipe_ends_quietly() {
+        let mut pipe = Pipe::new(1);
+        let mut produced = 0;
+        let counted = lines().inspect(|_| produced += 1);
+        write_lines(&mut pipe, counted).expect("Closed pipe is an error");
+        assert_eq!(Vec::<String>::new(), pipe.flushed);
+        // Nothing more is produced once the reader has gone
+        assert_eq!(1, produced);
+
+        let mut pipe = Pipe::new(2);
+        let paths = vec![OsString::from("/a.md"), OsString::from("/b.md")];
+        write_paths(&mut pipe, paths, false).expect("Closed pipe is an error");
+        assert_eq!(vec!["/a.md\n"], pipe.flushed);
+    }
+
+    #[test]
+    fn separator_and_header() {
+        let mut out = Vec::new();
+        let header = std::iter::once(plain_header(false));
+        write_separated(&mut out, header.chain(lines()), ",").unwrap();
+        assert_eq!(
+            "title\tpath,line 1,line 2,line 3,",
+            String::from_utf8(out).unwrap()
+        );
+
+        assert_eq!("\0", unescape_separator("\\0"));
+        assert_eq!("\n--\n", unescape_separator("\\n--\\n"));
+        assert_eq!("a\\b", unescape_separator("a\\\\b"));
+        assert_eq!("\\x", unescape_separator("\\x"));
+    }
+
+    #[test]
+    fn other_errors_are_reported() {
+        struct Full;
+        impl Write for Full {
+            fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+                Err(io::Error::new(io::ErrorKind::Other, "disk full"))
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+        assert!(write_lines(Full, lines()).is_err());
+    }
+}
+
+#[cfg(test)]
+mod write_json_array_tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn array(count: usize) -> Value {
+        let items = (1..=count).map(|i| json!({ "title": format!("Note {}", i) }));
+        let mut out = Vec::new();
+        write_json_array(&mut out, items).unwrap();
+        serde_json::from_slice(&out).expect("Output isn't JSON")
+    }
+
+    #[test]
+    fn empty_array() {
+        assert_eq!(json!([]), array(0));
+    }
+
+    #[test]
+    fn one_item() {
+        assert_eq!(json!([{"title": "Note 1"}]), array(1));
+    }
+
+    #[test]
+    fn many_items() {
+        let titles: Vec<Value> = array(3)
+            .as_array()
+            .expect("Output isn't an array")
+            .iter()
+            .map(|item| item["title"].clone())
+            .collect();
+        assert_eq!(vec!["Note 1", "Note 2", "Note 3"], titles);
+    }
+
+    #[test]
+    fn items_are_serialized_as_they_are_written() {
+        /// Writer whose reader has gone away after `open` writes
+        struct ClosesAfter {
+            open: usize,
+        }
+
+        impl Write for ClosesAfter {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                if self.open == 0 {
+                    return Err(io::Error::from(io::ErrorKind::BrokenPipe));
+                }
+                self.open -= 1;
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let produced = std::cell::Cell::new(0);
+        let items = (1..=3).map(|i| {
+            produced.set(produced.get() + 1);
+            json!(i)
+        });
+        write_json_array(ClosesAfter { open: 2 }, items).unwrap();
+        assert_eq!(2, produced.get());
+    }
+}
+
+#[cfg(test)]
+mod write_paths_tests {
+    use super::*;
+
+    fn paths() -> Vec<OsString> {
+        vec![
+            OsString::from("/notes/a.md"),
+            OsString::from("/notes/with space.md"),
+        ]
+    }
+
+    #[test]
+    fn one_path_per_line() {
+        let mut out = Vec::new();
+        write_paths(&mut out, paths(), false).unwrap();
+        assert_eq!(
+            "/notes/a.md\n/notes/with space.md\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+
+    #[test]
+    fn print0_terminates_with_nul() {
+        let mut out = Vec::new();
+        write_paths(&mut out, paths(), true).unwrap();
+        assert_eq!(b"/notes/a.md\0/notes/with space.md\0", out.as_slice());
+    }
+}
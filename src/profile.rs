@@ -0,0 +1,140 @@
+//! Wall-clock time spent in each phase of indexing, added up over the run and reported by
+//! `--profile`
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// A phase of indexing that time is accounted to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Phase {
+    /// Matching the source glob
+    Glob,
+    /// Reading files from disk
+    Read,
+    /// Parsing the YAML frontmatter into documents
+    Parse,
+    /// Working over the Markdown bodies, e.g. for summaries and hashtags
+    Markdown,
+    /// Adding documents to the Xapian DB
+    Index,
+    /// Committing the Xapian DB
+    Commit,
+}
+
+impl Phase {
+    const ALL: [Phase; 6] = [
+        Phase::Glob,
+        Phase::Read,
+        Phase::Parse,
+        Phase::Markdown,
+        Phase::Index,
+        Phase::Commit,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            Phase::Glob => "glob",
+            Phase::Read => "read",
+            Phase::Parse => "parse",
+            Phase::Markdown => "markdown",
+            Phase::Index => "index",
+            Phase::Commit => "commit",
+        }
+    }
+}
+
+/// Nanoseconds spent in each phase so far, in the order of `Phase::ALL`
+static NANOS: [AtomicU64; 6] = [
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+];
+
+/// Run `f`, accounting the time it takes to `phase`
+pub(crate) fn time<T>(phase: Phase, f: impl FnOnce() -> T) -> T {
+    let start = Instant::now();
+    let result = f();
+    add(phase, start);
+    result
+}
+
+/// Account the time since `start` to `phase`
+pub(crate) fn add(phase: Phase, start: Instant) {
+    NANOS[phase as usize].fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+}
+
+/// Time spent in each phase so far
+pub(crate) fn totals() -> Vec<(Phase, Duration)> {
+    Phase::ALL
+        .iter()
+        .map(|&phase| {
+            let nanos = NANOS[phase as usize].load(Ordering::Relaxed);
+            (phase, Duration::from_nanos(nanos))
+        })
+        .collect()
+}
+
+/// A line per phase with its time in seconds and its share of the total, then the total
+pub(crate) fn report(totals: &[(Phase, Duration)]) -> String {
+    let total: Duration = totals.iter().map(|(_, time)| *time).sum();
+    let mut lines: Vec<String> = totals
+        .iter()
+        .map(|(phase, time)| {
+            let share = if total.as_nanos() == 0 {
+                0.0
+            } else {
+                100.0 * time.as_secs_f64() / total.as_secs_f64()
+            };
+            format!(
+                "{:<8} {:>9.3}s {:>5.1}%",
+                phase.label(),
+                time.as_secs_f64(),
+                share
+            )
+        })
+        .collect();
+    lines.push(format!("{:<8} {:>9.3}s", "total", total.as_secs_f64()));
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod report_tests {
+    use super::*;
+
+    #[test]
+    fn every_phase_is_reported() {
+        time(Phase::Read, || std::thread::sleep(Duration::from_millis(1)));
+        let totals = totals();
+        assert!(totals[Phase::Read as usize].1 >= Duration::from_millis(1));
+
+        let report = report(&totals);
+        let labels: Vec<&str> = report
+            .lines()
+            .map(|line| line.split_whitespace().next().unwrap())
+            .collect();
+        assert_eq!(
+            vec!["glob", "read", "parse", "markdown", "index", "commit", "total"],
+            labels
+        );
+        for line in report.lines() {
+            let seconds = line.split_whitespace().nth(1).unwrap();
+            let seconds: f64 = seconds.trim_end_matches('s').parse().unwrap();
+            assert!(seconds >= 0.0, "{}", line);
+        }
+    }
+
+    #[test]
+    fn shares_of_the_total() {
+        let totals = vec![
+            (Phase::Glob, Duration::from_millis(250)),
+            (Phase::Index, Duration::from_millis(750)),
+        ];
+        assert_eq!(
+            "glob         0.250s  25.0%\nindex        0.750s  75.0%\ntotal        1.000s",
+            report(&totals)
+        );
+    }
+}
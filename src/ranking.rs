@@ -0,0 +1,620 @@
+use crate::tika_document::TikaDocument;
+use chrono::{DateTime, Utc};
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::path::Path;
+
+/// Default number of days for the recency boost of a document to halve
+pub(crate) const DEFAULT_HALF_LIFE_DAYS: f64 = 30.0;
+
+/// Number of ranks down the matches over which their relevance is taken to halve.
+/// xapian-rusty doesn't expose match weights, so the re-rankers estimate relevance from rank.
+const RANKS_PER_HALVING: f64 = 2.0;
+
+/// Log2 of the estimated relevance of the match at `rank`, multiplied by `factor`. Relevance
+/// halves every `RANKS_PER_HALVING` ranks, so wherever a match is, doubling its score lifts it
+/// over fewer than `RANKS_PER_HALVING` matches: a boost can only beat close competitors.
+fn rank_score(rank: usize, factor: f64) -> f64 {
+    factor.max(0.0).log2() - rank as f64 / RANKS_PER_HALVING
+}
+
+/// Sort `scored` best first, stable so that equally scored documents keep their relevance order
+fn by_score(mut scored: Vec<(f64, TikaDocument)>) -> Vec<TikaDocument> {
+    scored.sort_by(|(a, _), (b, _)| b.partial_cmp(a).unwrap_or(std::cmp::Ordering::Equal));
+    scored.into_iter().map(|(_, doc)| doc).collect()
+}
+
+/// Re-rank `docs`, given in order of text relevance, so that newer documents rank higher: each
+/// `rank_score` is multiplied by `0.5 ^ (age / half_life_days)`. Documents with a date that
+/// can't be parsed rank as if they were infinitely old.
+pub(crate) fn recency_boost(
+    docs: Vec<TikaDocument>,
+    half_life_days: f64,
+    now: DateTime<Utc>,
+    date_formats: &[String],
+) -> Vec<TikaDocument> {
+    by_score(
+        docs.into_iter()
+            .enumerate()
+            .map(|(rank, doc)| {
+                let decay = match doc.parse_date(date_formats) {
+                    Ok(date) => {
+                        let age_days = (now.timestamp() - date.timestamp()).max(0) as f64 / 86400.0;
+                        0.5_f64.powf(age_days / half_life_days)
+                    }
+                    Err(_) => 0.0,
+                };
+                (rank_score(rank, decay), doc)
+            })
+            .collect(),
+    )
+}
+
+/// Re-rank `docs`, given in order of relevance, by their `weight` frontmatter, multiplying each
+/// `rank_score` by it. A weight above `2 ^ (1 / RANKS_PER_HALVING)`, such as 1.5, lifts a note
+/// over the one just above it, and a weight of 2 still over no more than that one. Documents
+/// without a weight keep their order between themselves.
+pub(crate) fn weighted(docs: Vec<TikaDocument>) -> Vec<TikaDocument> {
+    if docs.iter().all(|doc| doc.weight.is_none()) {
+        return docs;
+    }
+    by_score(
+        docs.into_iter()
+            .enumerate()
+            .map(|(rank, doc)| {
+                let weight = f64::from(doc.weight.unwrap_or(1.0));
+                (rank_score(rank, weight), doc)
+            })
+            .collect(),
+    )
+}
+
+/// What makes two matches the same note for `query --dedupe-by`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum DedupeKey {
+    /// The title, ignoring case and surrounding whitespace
+    Title,
+    /// The file name without its extension, whatever folder it's in
+    Stem,
+}
+
+impl DedupeKey {
+    /// The key named `name` on the command line, if any
+    pub(crate) fn from_name(name: &str) -> Option<DedupeKey> {
+        match name {
+            "title" => Some(DedupeKey::Title),
+            "stem" => Some(DedupeKey::Stem),
+            _ => None,
+        }
+    }
+
+    fn of(self, doc: &TikaDocument) -> String {
+        match self {
+            DedupeKey::Title => doc.title.trim().to_lowercase(),
+            DedupeKey::Stem => Path::new(&doc.full_path)
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// Keep only the first of `docs`, the best ranked, with each value of `key`
+pub(crate) fn dedupe_by(docs: Vec<TikaDocument>, key: DedupeKey) -> Vec<TikaDocument> {
+    let mut seen = HashSet::new();
+    docs.into_iter()
+        .filter(|doc| seen.insert(key.of(doc)))
+        .collect()
+}
+
+/// Move the documents whose whole title, or one of its aliases, is the query string (ignoring
+/// case and surrounding whitespace) to the front, keeping the order within each group
+pub(crate) fn promote_exact_titles(docs: Vec<TikaDocument>, query: &str) -> Vec<TikaDocument> {
+    let query = query.trim().to_lowercase();
+    let (mut exact, rest): (Vec<TikaDocument>, Vec<TikaDocument>) =
+        docs.into_iter().partition(|doc| {
+            std::iter::once(&doc.title)
+                .chain(&doc.aliases)
+                .any(|title| title.trim().to_lowercase() == query)
+        });
+    exact.extend(rest);
+    exact
+}
+
+/// Sort `docs` newest first, keeping the text relevance order between documents dated the same.
+/// Documents with a date that can't be parsed go last.
+pub(crate) fn sort_by_date(docs: Vec<TikaDocument>, date_formats: &[String]) -> Vec<TikaDocument> {
+    let mut dated: Vec<(Option<i64>, TikaDocument)> = docs
+        .into_iter()
+        .map(|doc| {
+            (
+                doc.parse_date(date_formats).ok().map(|d| d.timestamp()),
+                doc,
+            )
+        })
+        .collect();
+    dated.sort_by(|(a, _), (b, _)| b.cmp(a));
+    dated.into_iter().map(|(_, doc)| doc).collect()
+}
+
+/// Sort `docs` by the modification time of their files, most recent first, keeping the text
+/// relevance order between documents modified at the same time. Documents without one go last.
+pub(crate) fn sort_by_modified(docs: Vec<TikaDocument>) -> Vec<TikaDocument> {
+    let mut modified: Vec<(Option<i64>, TikaDocument)> = docs
+        .into_iter()
+        .map(|doc| {
+            (
+                DateTime::parse_from_rfc3339(&doc.modified)
+                    .ok()
+                    .map(|t| t.timestamp_nanos()),
+                doc,
+            )
+        })
+        .collect();
+    modified.sort_by(|(a, _), (b, _)| b.cmp(a));
+    modified.into_iter().map(|(_, doc)| doc).collect()
+}
+
+/// Sort `docs` by title alphabetically, ignoring case
+pub(crate) fn sort_by_title(mut docs: Vec<TikaDocument>) -> Vec<TikaDocument> {
+    docs.sort_by_cached_key(|doc| doc.title.to_lowercase());
+    docs
+}
+
+/// Sort `docs` by the number of words in their bodies, longest first, keeping the text relevance
+/// order between documents of the same length
+pub(crate) fn sort_by_words(mut docs: Vec<TikaDocument>) -> Vec<TikaDocument> {
+    docs.sort_by(|a, b| b.words.cmp(&a.words));
+    docs
+}
+
+/// Sort `docs` by when they were indexed, most recent first, keeping the text relevance order
+/// between documents indexed at the same time. Documents indexed before this was recorded go
+/// last.
+pub(crate) fn sort_by_indexed(docs: Vec<TikaDocument>) -> Vec<TikaDocument> {
+    let mut indexed: Vec<(Option<i64>, TikaDocument)> = docs
+        .into_iter()
+        .map(|doc| {
+            (
+                DateTime::parse_from_rfc3339(&doc.indexed)
+                    .ok()
+                    .map(|t| t.timestamp_nanos()),
+                doc,
+            )
+        })
+        .collect();
+    indexed.sort_by(|(a, _), (b, _)| b.cmp(a));
+    indexed.into_iter().map(|(_, doc)| doc).collect()
+}
+
+/// Position of a document in paged results: newest first by date, then by filename, so that
+/// every document has its own place however the index changes between pages. Documents with a
+/// date that can't be parsed go last.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct CursorKey {
+    newest: Reverse<Option<i64>>,
+    filename: String,
+}
+
+impl CursorKey {
+    fn of(doc: &TikaDocument, date_formats: &[String]) -> CursorKey {
+        CursorKey {
+            newest: Reverse(doc.parse_date(date_formats).ok().map(|d| d.timestamp())),
+            filename: doc.filename.clone(),
+        }
+    }
+
+    /// Read back a cursor written by `to_cursor`
+    fn parse(cursor: &str) -> Result<CursorKey, String> {
+        let invalid = || format!("Invalid cursor '{}'", cursor);
+        let (timestamp, filename) = cursor.split_once(':').ok_or_else(invalid)?;
+        let timestamp = match timestamp {
+            "" => None,
+            timestamp => Some(timestamp.parse().map_err(|_| invalid())?),
+        };
+        Ok(CursorKey {
+            newest: Reverse(timestamp),
+            filename: filename.to_string(),
+        })
+    }
+
+    /// As `<unix timestamp>:<filename>`, with an empty timestamp for undated documents
+    fn to_cursor(&self) -> String {
+        let timestamp = self.newest.0.map(|t| t.to_string()).unwrap_or_default();
+        format!("{}:{}", timestamp, self.filename)
+    }
+}
+
+/// The page of up to `limit` of `docs`, or all of them for 0, that comes after the document at
+/// `after`, or the first page without one. Documents are ordered by `CursorKey` and each is
+/// given its `cursor`, the last of which fetches the next page.
+pub(crate) fn page(
+    docs: Vec<TikaDocument>,
+    after: Option<&str>,
+    limit: usize,
+    date_formats: &[String],
+) -> Result<Vec<TikaDocument>, String> {
+    let after = after.map(CursorKey::parse).transpose()?;
+    let mut keyed: Vec<(CursorKey, TikaDocument)> = docs
+        .into_iter()
+        .map(|doc| (CursorKey::of(&doc, date_formats), doc))
+        .filter(|(key, _)| after.as_ref().map_or(true, |after| key > after))
+        .collect();
+    keyed.sort_by(|(a, _), (b, _)| a.cmp(b));
+    if limit > 0 {
+        keyed.truncate(limit);
+    }
+    Ok(keyed
+        .into_iter()
+        .map(|(key, mut doc)| {
+            doc.cursor = key.to_cursor();
+            doc
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod recency_boost_tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn doc(title: &str, date: &str) -> TikaDocument {
+        TikaDocument {
+            title: String::from(title),
+            date: String::from(date),
+            ..Default::default()
+        }
+    }
+
+    fn titles(docs: Vec<TikaDocument>) -> Vec<String> {
+        docs.into_iter().map(|doc| doc.title).collect()
+    }
+
+    #[test]
+    fn newer_ranks_first() {
+        let now = Utc.ymd(2021, 7, 1).and_hms(0, 0, 0);
+        // Equally relevant to the text, the older one just happened to be returned first
+        let docs = vec![
+            doc("older", "2020-07-01T00:00:00+00:00"),
+            doc("newer", "2021-06-30T00:00:00+00:00"),
+        ];
+        assert_eq!(
+            vec!["newer", "older"],
+            titles(recency_boost(docs, DEFAULT_HALF_LIFE_DAYS, now, &[]))
+        );
+    }
+
+    #[test]
+    fn relevance_wins_between_same_age() {
+        let now = Utc.ymd(2021, 7, 1).and_hms(0, 0, 0);
+        let docs = vec![
+            doc("first", "2021-06-30T00:00:00+00:00"),
+            doc("second", "2021-06-30T00:00:00+00:00"),
+            doc("undated", "someday"),
+        ];
+        assert_eq!(
+            vec!["first", "second", "undated"],
+            titles(recency_boost(docs, DEFAULT_HALF_LIFE_DAYS, now, &[]))
+        );
+    }
+}
+
+#[cfg(test)]
+mod weighted_tests {
+    use super::*;
+    use crate::fixtures::FixtureVault;
+
+    fn titles(docs: Vec<TikaDocument>) -> Vec<String> {
+        docs.into_iter().map(|doc| doc.title).collect()
+    }
+
+    #[test]
+    fn heavier_ranks_first() {
+        let mut vault = FixtureVault::new();
+        vault.note("plain.md", &[], "Notes on kubernetes");
+        vault.note("heavy.md", &[("priority", "2.5")], "Notes on kubernetes");
+        vault.note("light.md", &[("weight", "0.5")], "Notes on kubernetes");
+        vault.index();
+
+        // Equally relevant to the text, so returned in the order they were indexed
+        let matches = vault.search("kubernetes");
+        assert_eq!(vec!["plain", "heavy", "light"], titles(matches.clone()));
+        assert_eq!(vec!["heavy", "plain", "light"], titles(weighted(matches)));
+    }
+
+    #[test]
+    fn weights_only_beat_close_competitors() {
+        for weight in &[1.5, 2.0] {
+            let mut docs: Vec<TikaDocument> = (0..30)
+                .map(|rank| TikaDocument {
+                    title: format!("{}", rank),
+                    ..Default::default()
+                })
+                .collect();
+            docs[29].weight = Some(*weight);
+            let position = weighted(docs)
+                .iter()
+                .position(|doc| doc.weight.is_some())
+                .unwrap();
+            assert!(
+                (27..29).contains(&position),
+                "Weight {} moved the last note to {}",
+                weight,
+                position
+            );
+        }
+    }
+
+    #[test]
+    fn slightly_heavier_beats_equally_relevant() {
+        let mut vault = FixtureVault::new();
+        vault.note("plain.md", &[], "Notes on kubernetes");
+        vault.note("favoured.md", &[("weight", "1.5")], "Notes on kubernetes");
+        vault.index();
+
+        let matches = vault.search("kubernetes");
+        assert_eq!(vec!["plain", "favoured"], titles(matches.clone()));
+        assert_eq!(vec!["favoured", "plain"], titles(weighted(matches)));
+    }
+}
+
+#[cfg(test)]
+mod dedupe_by_tests {
+    use super::*;
+
+    fn doc(title: &str, full_path: &str) -> TikaDocument {
+        TikaDocument {
+            title: String::from(title),
+            full_path: full_path.into(),
+            ..Default::default()
+        }
+    }
+
+    fn paths(docs: Vec<TikaDocument>) -> Vec<String> {
+        docs.into_iter()
+            .map(|doc| doc.full_path.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    fn docs() -> Vec<TikaDocument> {
+        vec![
+            doc("Standup", "/notes/work/standup.md"),
+            doc("Retro", "/notes/work/retro.md"),
+            doc("standup ", "/notes/archive/standup-2020.md"),
+            doc("Retro notes", "/notes/archive/retro.md"),
+        ]
+    }
+
+    #[test]
+    fn best_ranked_per_title() {
+        assert_eq!(
+            vec![
+                "/notes/work/standup.md",
+                "/notes/work/retro.md",
+                "/notes/archive/retro.md"
+            ],
+            paths(dedupe_by(docs(), DedupeKey::Title))
+        );
+    }
+
+    #[test]
+    fn best_ranked_per_stem() {
+        assert_eq!(
+            vec![
+                "/notes/work/standup.md",
+                "/notes/work/retro.md",
+                "/notes/archive/standup-2020.md"
+            ],
+            paths(dedupe_by(docs(), DedupeKey::Stem))
+        );
+    }
+}
+
+#[cfg(test)]
+mod promote_exact_titles_tests {
+    use super::*;
+
+    fn doc(title: &str, body: &str) -> TikaDocument {
+        TikaDocument {
+            title: String::from(title),
+            body: String::from(body),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn exact_title_is_promoted_over_body_mentions() {
+        // As ranked by text relevance, which favors the many body mentions
+        let docs = vec![
+            doc(
+                "Meeting notes",
+                "Weekly review, weekly review, weekly review",
+            ),
+            doc("Reviews", "A weekly review"),
+            doc("weekly Review ", "Template"),
+        ];
+        let titles: Vec<String> = promote_exact_titles(docs, "Weekly review")
+            .into_iter()
+            .map(|doc| doc.title)
+            .collect();
+        assert_eq!(vec!["weekly Review ", "Meeting notes", "Reviews"], titles);
+    }
+
+    #[test]
+    fn alias_counts_as_a_title() {
+        let docs = vec![
+            doc("Other", "k8s"),
+            TikaDocument {
+                aliases: vec![String::from("k8s")],
+                ..doc("Kubernetes", "")
+            },
+        ];
+        assert_eq!("Kubernetes", promote_exact_titles(docs, "K8s")[0].title);
+    }
+}
+
+#[cfg(test)]
+mod sort_by_date_tests {
+    use super::*;
+
+    fn doc(title: &str, date: &str) -> TikaDocument {
+        TikaDocument {
+            title: String::from(title),
+            date: String::from(date),
+            ..Default::default()
+        }
+    }
+
+    fn docs() -> Vec<TikaDocument> {
+        vec![
+            doc("middle", "2021-03-01T00:00:00+00:00"),
+            doc("undated", "someday"),
+            doc("oldest", "2020-01-01T00:00:00+00:00"),
+            doc("newest", "2021-06-30T00:00:00+00:00"),
+        ]
+    }
+
+    fn titles(docs: Vec<TikaDocument>) -> Vec<String> {
+        docs.into_iter().map(|doc| doc.title).collect()
+    }
+
+    #[test]
+    fn newest_first() {
+        assert_eq!(
+            vec!["newest", "middle", "oldest", "undated"],
+            titles(sort_by_date(docs(), &[]))
+        );
+    }
+
+    #[test]
+    fn reversed_is_oldest_first() {
+        // As `--sort date --reverse` does
+        let mut sorted = sort_by_date(docs(), &[]);
+        sorted.reverse();
+        assert_eq!(
+            vec!["undated", "oldest", "middle", "newest"],
+            titles(sorted)
+        );
+    }
+}
+
+#[cfg(test)]
+mod sort_by_modified_tests {
+    use super::*;
+
+    fn doc(title: &str, modified: &str) -> TikaDocument {
+        TikaDocument {
+            title: String::from(title),
+            modified: String::from(modified),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn most_recently_modified_first() {
+        let docs = vec![
+            doc("unknown", ""),
+            doc("earlier", "2021-06-22T12:00:00.000000001+00:00"),
+            doc("later", "2021-06-22T12:00:00.000000002+00:00"),
+        ];
+        let titles: Vec<String> = sort_by_modified(docs)
+            .into_iter()
+            .map(|doc| doc.title)
+            .collect();
+        assert_eq!(vec!["later", "earlier", "unknown"], titles);
+    }
+}
+
+#[cfg(test)]
+mod browse_sort_tests {
+    use super::*;
+    use crate::fixtures::FixtureVault;
+    use crate::xapian_utils::match_all;
+
+    fn titles(docs: Vec<TikaDocument>) -> Vec<String> {
+        docs.into_iter().map(|doc| doc.title).collect()
+    }
+
+    #[test]
+    fn all_by_title_is_alphabetical() {
+        let mut vault = FixtureVault::new();
+        for name in &["mango", "Apple", "cherry", "banana"] {
+            vault.note(&format!("{}.md", name), &[], "Fruit");
+        }
+        vault.index();
+
+        let all = vault.run(match_all().unwrap());
+        assert_eq!(
+            vec!["Apple", "banana", "cherry", "mango"],
+            titles(sort_by_title(all))
+        );
+    }
+
+    #[test]
+    fn by_words_and_by_indexed() {
+        let doc = |title: &str, words: usize, indexed: &str| TikaDocument {
+            title: String::from(title),
+            words,
+            indexed: String::from(indexed),
+            ..Default::default()
+        };
+        let docs = || {
+            vec![
+                doc("short", 3, "2021-06-22T12:00:00+00:00"),
+                doc("unknown", 0, ""),
+                doc("long", 300, "2021-06-21T12:00:00+00:00"),
+            ]
+        };
+        assert_eq!(
+            vec!["long", "short", "unknown"],
+            titles(sort_by_words(docs()))
+        );
+        assert_eq!(
+            vec!["short", "long", "unknown"],
+            titles(sort_by_indexed(docs()))
+        );
+    }
+}
+
+#[cfg(test)]
+mod page_tests {
+    use super::*;
+    use crate::fixtures::FixtureVault;
+    use crate::xapian_utils::match_all;
+
+    fn filenames(docs: &[TikaDocument]) -> Vec<&str> {
+        docs.iter().map(|doc| doc.filename.as_str()).collect()
+    }
+
+    #[test]
+    fn next_page_continues_from_cursor() {
+        let mut vault = FixtureVault::new();
+        for (name, date) in &[
+            ("a.md", "2021-06-20T12:00:00+00:00"),
+            ("b.md", "2021-06-22T12:00:00+00:00"),
+            ("c.md", "2021-06-21T12:00:00+00:00"),
+            ("d.md", "2021-06-22T12:00:00+00:00"),
+            ("e.md", "2021-06-21T12:00:00+00:00"),
+        ] {
+            vault.note(name, &[("date", date)], "Body");
+        }
+        vault.index();
+        let all = || vault.run(match_all().unwrap());
+
+        let first = page(all(), None, 3, &[]).unwrap();
+        assert_eq!(vec!["b.md", "d.md", "c.md"], filenames(&first));
+        let next = page(all(), Some(&first[2].cursor), 3, &[]).unwrap();
+        assert_eq!(vec!["e.md", "a.md"], filenames(&next));
+        let last = page(all(), Some(&next[1].cursor), 3, &[]).unwrap();
+        assert!(last.is_empty());
+    }
+
+    #[test]
+    fn cursor_round_trips() {
+        for cursor in &["1624363200:note.md", ":undated.md", "1:with:colon.md"] {
+            assert_eq!(*cursor, CursorKey::parse(cursor).unwrap().to_cursor());
+        }
+        assert!(CursorKey::parse("no-colon").is_err());
+        assert!(CursorKey::parse("soon:note.md").is_err());
+    }
+}
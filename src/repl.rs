@@ -0,0 +1,98 @@
+use crate::output::QueryResult;
+use crate::xapian_utils;
+use color_eyre::Report;
+use std::io::{BufRead, Write};
+
+/// Answer each query read from `input`, one per line, with a JSON `QueryResult` line on `out`
+/// until EOF. The DB is opened once and reopened before each query to pick up index changes.
+/// Queries that fail to parse are answered with an `{"error": ...}` object instead. Returns the
+/// number of queries answered.
+pub(crate) fn repl<R, W>(
+    db_paths: &[&str],
+    input: R,
+    mut out: W,
+    include_drafts: bool,
+    limit: usize,
+) -> Result<usize, Report>
+where
+    R: BufRead,
+    W: Write,
+{
+    let mut db = xapian_utils::open_dbs(db_paths)?;
+    let mut count = 0;
+    for line in input.lines() {
+        let mut qstr = line?;
+        if qstr.trim().is_empty() {
+            continue;
+        }
+        // Add a trailing ` ;` to the query to hint to Nom that it has a "full" string
+        qstr.push_str(" ;");
+
+        let response = match xapian_utils::parse_user_query(&qstr) {
+            Ok(mut query) => {
+                if !include_drafts {
+                    query = xapian_utils::exclude_drafts(query)?;
+                }
+                db.reopen()?;
+                let matches = xapian_utils::query_open_db(&mut db, query, limit)?;
+                serde_json::to_string(&QueryResult::new(matches))?
+            }
+            Err(e) => serde_json::json!({ "error": xapian_utils::friendly_error(&e) }).to_string(),
+        };
+        writeln!(out, "{}", response)?;
+        out.flush()?;
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+#[cfg(test)]
+mod repl_tests {
+    use super::*;
+    use crate::indexer::index_paths;
+    use std::fs;
+    use std::io::Cursor;
+
+    #[test]
+    fn one_result_set_per_query() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let db_path = dir.path().join("db");
+        let db_path = db_path.to_str().unwrap();
+        let mut paths = Vec::new();
+        for (title, body) in &[("first", "alpha"), ("second", "alpha beta")] {
+            let path = dir.path().join(format!("{}.md", title));
+            fs::write(
+                &path,
+                format!(
+                    "---\ndate: 2021-06-22T12:48:16-0400\ntags: test\ntitle: {}\n---\n{}\n",
+                    title, body
+                ),
+            )
+            .unwrap();
+            paths.push(path);
+        }
+        index_paths(db_path, paths, &Default::default()).expect("Failed to index");
+
+        let mut out = Vec::new();
+        let count = repl(
+            &[db_path],
+            Cursor::new("alpha\nbeta\n"),
+            &mut out,
+            false,
+            xapian_utils::DEFAULT_LIMIT,
+        )
+        .expect("REPL failed");
+        assert_eq!(2, count);
+
+        let totals: Vec<u64> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|line| {
+                let result: serde_json::Value = serde_json::from_str(line).unwrap();
+                result["total"].as_u64().unwrap()
+            })
+            .collect();
+        assert_eq!(vec![2, 1], totals);
+    }
+}
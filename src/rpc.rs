@@ -0,0 +1,270 @@
+//! Line-delimited JSON-RPC 2.0 over stdin and stdout for `tika rpc`, so that an editor can keep
+//! one tika process, and its open index, around rather than running the CLI for each action
+
+use crate::indexer::{self, IndexOptions};
+use crate::output::QueryResult;
+use crate::tags::tag_counts;
+use crate::xapian_utils::{self, friendly_error};
+use color_eyre::Report;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+use xapian_rusty::Database;
+
+// Error codes from the JSON-RPC 2.0 spec, and the one it leaves for tika's own failures
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const SERVER_ERROR: i64 = -32000;
+
+#[derive(Deserialize)]
+struct Request {
+    jsonrpc: String,
+    /// Missing for notifications, which get no response
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Deserialize)]
+struct SearchParams {
+    query: String,
+    limit: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct GetParams {
+    id: String,
+}
+
+#[derive(Deserialize)]
+struct IndexFileParams {
+    path: PathBuf,
+}
+
+/// A JSON-RPC error object
+#[derive(Debug, PartialEq)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> RpcError {
+        RpcError {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<Report> for RpcError {
+    fn from(e: Report) -> RpcError {
+        RpcError::new(SERVER_ERROR, friendly_error(&e))
+    }
+}
+
+/// Answers requests against the DBs at `db_paths`, held open between them
+pub(crate) struct Server<'a> {
+    db_paths: &'a [&'a str],
+    /// The DB `index_file` writes to, when there's just one
+    write_db_path: Option<&'a str>,
+    opts: &'a IndexOptions,
+    include_drafts: bool,
+    db: Database,
+}
+
+impl<'a> Server<'a> {
+    pub(crate) fn new(
+        db_paths: &'a [&'a str],
+        write_db_path: Option<&'a str>,
+        opts: &'a IndexOptions,
+        include_drafts: bool,
+    ) -> Result<Server<'a>, Report> {
+        Ok(Server {
+            db_paths,
+            write_db_path,
+            opts,
+            include_drafts,
+            db: xapian_utils::open_dbs(db_paths)?,
+        })
+    }
+
+    /// The response to one request line, or `None` for a notification
+    pub(crate) fn handle_line(&mut self, line: &str) -> Option<Value> {
+        let request: Request = match serde_json::from_str::<Value>(line) {
+            Err(e) => {
+                return Some(error_response(
+                    Value::Null,
+                    RpcError::new(PARSE_ERROR, e.to_string()),
+                ))
+            }
+            Ok(value) => match serde_json::from_value(value) {
+                Ok(request) => request,
+                Err(e) => {
+                    let e = RpcError::new(INVALID_REQUEST, e.to_string());
+                    return Some(error_response(Value::Null, e));
+                }
+            },
+        };
+        let id = request.id.clone();
+        let result = if request.jsonrpc != "2.0" {
+            Err(RpcError::new(
+                INVALID_REQUEST,
+                "Only JSON-RPC 2.0 is supported",
+            ))
+        } else {
+            self.call(&request.method, request.params)
+        };
+        let id = id?;
+        Some(match result {
+            Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+            Err(e) => error_response(id, e),
+        })
+    }
+
+    fn call(&mut self, method: &str, params: Value) -> Result<Value, RpcError> {
+        match method {
+            "search" => {
+                let SearchParams { query, limit } = parse_params(params)?;
+                let mut query = xapian_utils::parse_user_query(&format!("{} ;", query))?;
+                if !self.include_drafts {
+                    query = xapian_utils::exclude_drafts(query)?;
+                }
+                let limit = limit.unwrap_or(xapian_utils::DEFAULT_LIMIT);
+                let docs = xapian_utils::query_open_db(&mut self.db, query, limit)?;
+                Ok(json!(QueryResult::new(docs)))
+            }
+            "get" => {
+                let GetParams { id } = parse_params(params)?;
+                let query = xapian_utils::parse_user_query(&format!("id:{} ;", id))?;
+                let doc = xapian_utils::query_open_db(&mut self.db, query, 1)?
+                    .pop()
+                    .ok_or_else(|| {
+                        RpcError::new(SERVER_ERROR, format!("No document with id {}", id))
+                    })?;
+                Ok(json!(doc))
+            }
+            "index_file" => {
+                let IndexFileParams { path } = parse_params(params)?;
+                let db_path = self.write_db_path.ok_or_else(|| {
+                    RpcError::new(SERVER_ERROR, "index_file needs a single vault")
+                })?;
+                indexer::index_file(db_path, &path, self.opts)?;
+                // Reopen to see the newly indexed document
+                self.db = xapian_utils::open_dbs(self.db_paths)?;
+                Ok(json!({ "path": path }))
+            }
+            "tags" => {
+                let counts = tag_counts(xapian_utils::documents(self.db_paths)?);
+                Ok(counts
+                    .into_iter()
+                    .map(|(tag, count)| json!({"tag": tag, "count": count}))
+                    .collect())
+            }
+            _ => Err(RpcError::new(
+                METHOD_NOT_FOUND,
+                format!("No method named '{}'", method),
+            )),
+        }
+    }
+}
+
+fn parse_params<T: DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    serde_json::from_value(params).map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))
+}
+
+fn error_response(id: Value, e: RpcError) -> Value {
+    json!({"jsonrpc": "2.0", "id": id, "error": {"code": e.code, "message": e.message}})
+}
+
+/// Answer each request line read from `input` with a response line on `out`, until `input`
+/// ends. Blank lines are skipped.
+pub(crate) fn serve<R: BufRead, W: Write>(
+    server: &mut Server,
+    input: R,
+    mut out: W,
+) -> io::Result<()> {
+    for line in input.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(response) = server.handle_line(&line) {
+            writeln!(out, "{}", response)?;
+            out.flush()?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod serve_tests {
+    use super::*;
+    use crate::fixtures::FixtureVault;
+
+    fn responses(vault: &FixtureVault, requests: &str) -> Vec<Value> {
+        let db_path = vault.db_path();
+        let db_paths = [db_path.as_str()];
+        let opts = IndexOptions::default();
+        let mut server = Server::new(&db_paths, Some(&db_path), &opts, false).unwrap();
+        let mut out = Vec::new();
+        serve(&mut server, requests.as_bytes(), &mut out).expect("Failed to serve");
+        String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).expect("Response isn't JSON"))
+            .collect()
+    }
+
+    #[test]
+    fn search_returns_matches() {
+        let mut vault = FixtureVault::new();
+        vault.note("kubernetes.md", &[], "Rolling out the cluster upgrade");
+        vault.note("groceries.md", &[], "Eggs and milk");
+        vault.index();
+
+        let responses = responses(
+            &vault,
+            r#"{"jsonrpc": "2.0", "id": 1, "method": "search", "params": {"query": "cluster"}}"#,
+        );
+        assert_eq!(1, responses.len());
+        let response = &responses[0];
+        assert_eq!("2.0", response["jsonrpc"]);
+        assert_eq!(1, response["id"]);
+        assert!(response.get("error").is_none());
+        assert_eq!(1, response["result"]["total"]);
+        assert_eq!("kubernetes", response["result"]["documents"][0]["title"]);
+    }
+
+    #[test]
+    fn errors_and_notifications() {
+        let mut vault = FixtureVault::new();
+        vault.note("note.md", &[], "Body");
+        vault.index();
+
+        let responses = responses(
+            &vault,
+            "not json\n\
+             {\"jsonrpc\": \"2.0\", \"id\": 2, \"method\": \"nope\"}\n\
+             {\"jsonrpc\": \"2.0\", \"id\": 3, \"method\": \"get\", \"params\": {}}\n\
+             {\"jsonrpc\": \"2.0\", \"method\": \"search\", \"params\": {\"query\": \"body\"}}\n",
+        );
+        let codes: Vec<(Value, Value)> = responses
+            .iter()
+            .map(|r| (r["id"].clone(), r["error"]["code"].clone()))
+            .collect();
+        assert_eq!(
+            vec![
+                (Value::Null, json!(PARSE_ERROR)),
+                (json!(2), json!(METHOD_NOT_FOUND)),
+                (json!(3), json!(INVALID_PARAMS)),
+            ],
+            codes
+        );
+    }
+}
@@ -0,0 +1,169 @@
+use crate::tika_document::TikaDocument;
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// Count how many documents use each tag, most used first and then alphabetically
+pub(crate) fn tag_counts<I>(docs: I) -> Vec<(String, usize)>
+where
+    I: IntoIterator<Item = TikaDocument>,
+{
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for doc in docs {
+        for tag in doc.tags {
+            *counts.entry(tag).or_insert(0) += 1;
+        }
+    }
+
+    let mut counts: Vec<(String, usize)> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    counts
+}
+
+/// Move the `pinned` tags to the front of `counts`, in the order they're pinned, leaving the
+/// rest in their order. Pinned tags no document uses are left out.
+pub(crate) fn pin_tags(counts: Vec<(String, usize)>, pinned: &[String]) -> Vec<(String, usize)> {
+    let (mut first, rest): (Vec<_>, Vec<_>) = counts
+        .into_iter()
+        .partition(|(tag, _)| pinned.contains(tag));
+    first.sort_by_key(|(tag, _)| pinned.iter().position(|p| p == tag));
+    first.extend(rest);
+    first
+}
+
+/// The documents under one tag when grouping query results by tag
+#[derive(Debug, Serialize)]
+pub(crate) struct TagGroup {
+    pub tag: String,
+    pub documents: Vec<TikaDocument>,
+    /// How many more documents have the tag than are listed, beyond `--limit-per-tag`
+    #[serde(skip_serializing_if = "is_zero")]
+    pub more: usize,
+}
+
+fn is_zero(n: &usize) -> bool {
+    *n == 0
+}
+
+/// Group `docs` under each of their tags, a document with several tags appearing in each of
+/// their groups and untagged ones in none. Groups with the most documents come first, then
+/// alphabetically, and keep the order of `docs`. When non-zero, at most `limit_per_tag`
+/// documents are listed in each group, the rest only counted.
+pub(crate) fn group_by_tag(docs: &[TikaDocument], limit_per_tag: usize) -> Vec<TagGroup> {
+    let mut grouped: HashMap<&str, Vec<&TikaDocument>> = HashMap::new();
+    for doc in docs {
+        for tag in &doc.tags {
+            grouped.entry(tag).or_insert_with(Vec::new).push(doc);
+        }
+    }
+
+    let mut grouped: Vec<(&str, Vec<&TikaDocument>)> = grouped.into_iter().collect();
+    grouped.sort_by(|a, b| b.1.len().cmp(&a.1.len()).then_with(|| a.0.cmp(b.0)));
+    grouped
+        .into_iter()
+        .map(|(tag, documents)| {
+            let listed = match limit_per_tag {
+                0 => documents.len(),
+                limit => limit.min(documents.len()),
+            };
+            TagGroup {
+                tag: String::from(tag),
+                documents: documents[..listed].iter().map(|&doc| doc.clone()).collect(),
+                more: documents.len() - listed,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tag_counts_tests {
+    use super::*;
+
+    fn doc(tags: &[&str]) -> TikaDocument {
+        TikaDocument {
+            tags: tags.iter().map(|t| String::from(*t)).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn most_used_first() {
+        let docs = vec![doc(&["rust", "tika"]), doc(&["rust"]), doc(&["go"])];
+        assert_eq!(
+            vec![
+                (String::from("rust"), 2),
+                (String::from("go"), 1),
+                (String::from("tika"), 1)
+            ],
+            tag_counts(docs)
+        );
+    }
+}
+
+#[cfg(test)]
+mod pin_tags_tests {
+    use super::*;
+
+    #[test]
+    fn pinned_tags_come_first_whatever_their_counts() {
+        let counts = vec![
+            (String::from("rust"), 5),
+            (String::from("active"), 2),
+            (String::from("go"), 2),
+            (String::from("inbox"), 1),
+        ];
+        let pinned = vec![
+            String::from("inbox"),
+            String::from("unused"),
+            String::from("active"),
+        ];
+        assert_eq!(
+            vec![
+                (String::from("inbox"), 1),
+                (String::from("active"), 2),
+                (String::from("rust"), 5),
+                (String::from("go"), 2)
+            ],
+            pin_tags(counts, &pinned)
+        );
+    }
+}
+
+#[cfg(test)]
+mod group_by_tag_tests {
+    use super::*;
+
+    fn doc(title: &str, tags: &[&str]) -> TikaDocument {
+        TikaDocument {
+            title: String::from(title),
+            tags: tags.iter().map(|t| String::from(*t)).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn groups_are_capped() {
+        let mut docs: Vec<TikaDocument> = (1..=10)
+            .map(|i| doc(&format!("big {}", i), &["big"]))
+            .collect();
+        docs.push(doc("small", &["small", "big"]));
+        docs.push(doc("untagged", &[]));
+
+        let groups = group_by_tag(&docs, 5);
+        let summary: Vec<(&str, Vec<&str>, usize)> = groups
+            .iter()
+            .map(|g| {
+                let titles = g.documents.iter().map(|d| d.title.as_str()).collect();
+                (g.tag.as_str(), titles, g.more)
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                ("big", vec!["big 1", "big 2", "big 3", "big 4", "big 5"], 6),
+                ("small", vec!["small"], 0),
+            ],
+            summary
+        );
+
+        assert_eq!(11, group_by_tag(&docs, 0)[0].documents.len());
+    }
+}
@@ -1,10 +1,14 @@
-use chrono::{DateTime, FixedOffset};
+use crate::output::{status, Status};
+use crate::profile::{self, Phase};
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime, TimeZone};
 use color_eyre::Report;
 use eyre::{eyre, Result};
 use serde::{de, Deserialize, Deserializer, Serialize};
-use std::io::{Error, ErrorKind};
+use std::io::Write;
+use std::path::Path;
+use std::time::Instant;
 use std::{ffi::OsString, fmt, fs, io, marker::PhantomData};
-use yaml_rust::YamlEmitter;
+use yaml_rust::{Yaml, YamlEmitter, YamlLoader};
 
 /// Representation for a given Markdown + FrontMatter file; Example:
 /// ---
@@ -17,56 +21,417 @@ use yaml_rust::YamlEmitter;
 ///
 /// Some note here formatted with Markdown syntax
 ///
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
 pub struct TikaDocument {
     /// Inherent metadata about the document
     #[serde(default)]
     pub filename: String,
     #[serde(default)]
     pub full_path: OsString,
+    /// Hash of the frontmatter and body, a handle on the document that survives renames and
+    /// changes whenever its contents do
+    #[serde(default)]
+    pub id: String,
+    /// Name of the vault the document was indexed from, if vaults are configured
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub vault: String,
+    /// Modification time of the file when it was read, as RFC 3339 in UTC
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub modified: String,
+    /// When the document was last indexed, as RFC 3339 in UTC
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub indexed: String,
 
     /// FrontMatter-derived metadata about the document
     #[serde(default)]
     pub author: String,
-    /// RFC 3339 based timestamp
+    /// Other authors of the document, from a list-valued `author` after its first entry
+    #[serde(default, deserialize_with = "string_or_list_string")]
+    pub coauthors: Vec<String>,
+    /// RFC 3339 based timestamp, or one of the configured date formats
+    #[serde(deserialize_with = "string_or_timestamp")]
     pub date: String,
 
     #[serde(deserialize_with = "string_or_list_string")]
     pub tags: Vec<String>,
 
     pub title: String,
+    /// Other titles for the document, from `aliases` or from a list-valued `title` after its
+    /// first entry
+    #[serde(default, deserialize_with = "string_or_list_string")]
+    pub aliases: Vec<String>,
 
     #[serde(default)]
     pub subtitle: String,
 
+    /// Work-in-progress documents are left out of results unless asked for
+    #[serde(default)]
+    pub draft: bool,
+
+    /// Task state such as `todo`, `doing` or `done`, for `query --status`
+    #[serde(default)]
+    pub status: String,
+
+    /// Where a note clipped from the web came from, read from the `source-url-key` frontmatter
+    /// key, `source` unless configured
+    #[serde(default)]
+    pub source_url: String,
+
+    /// How much to favour the document in query results, as a multiplier of its relevance; read
+    /// from `weight` or `priority`, and 1 when neither is set
+    #[serde(default, alias = "priority", skip_serializing_if = "Option::is_none")]
+    pub weight: Option<f32>,
+
+    /// Plain text of the first paragraph of the body, for cheap previews
+    #[serde(default)]
+    pub summary: String,
+
+    /// Plain text of the start of the body, cut down to `preview-chars` when indexing
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub preview: String,
+
+    /// Number of words in the body, kept in a metadata-only index too
+    #[serde(default)]
+    pub words: usize,
+
+    /// Whether the body links to any local, non-Markdown files such as images
+    #[serde(default)]
+    pub has_attachments: bool,
+
     /// The Markdown-formatted body of the document
     #[serde(default)]
     pub body: String,
+
+    /// Fields that matched the query, only filled in for `query --explain`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub matched_fields: Vec<String>,
+
+    /// The words of the body around its first match, only filled in for `query --snippets`
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub snippet: String,
+
+    /// Where the document falls in paged results, only filled in for `query --paginate`
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub cursor: String,
+
+    /// Size and modification time of the file, only filled in for `query --with-file-meta`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_meta: Option<FileMeta>,
+}
+
+/// On-disk metadata of a document's file, `None` when the file can't be read, e.g. because it
+/// was deleted after being indexed
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileMeta {
+    /// Size in bytes
+    pub size: Option<u64>,
+    /// Last modification time, as RFC 3339 in UTC
+    pub mtime: Option<String>,
+}
+
+impl FileMeta {
+    pub(crate) fn read<P: AsRef<Path>>(path: P) -> FileMeta {
+        match fs::metadata(path) {
+            Ok(meta) => FileMeta {
+                size: Some(meta.len()),
+                mtime: meta
+                    .modified()
+                    .ok()
+                    .map(|t| chrono::DateTime::<chrono::Utc>::from(t).to_rfc3339()),
+            },
+            Err(_) => FileMeta::default(),
+        }
+    }
 }
 
 impl TikaDocument {
-    pub(crate) fn date_str(&self) -> Result<String, Report> {
-        if let Ok(t) = self.parse_date() {
+    /// The document as stored in the index, from which `from_stored` reconstructs it exactly
+    pub(crate) fn to_stored(&self) -> Result<String, Report> {
+        Ok(serde_json::to_string(self)?)
+    }
+
+    /// Reconstruct a document from the data stored for it in the index
+    pub(crate) fn from_stored(data: &str) -> Result<TikaDocument, Report> {
+        Ok(serde_json::from_str(data)?)
+    }
+
+    /// Whether the stored `field` has a non-empty value, e.g. a non-blank author or any tags
+    pub(crate) fn has_field(&self, field: &str) -> Result<bool, Report> {
+        let present = match field {
+            "filename" => !self.filename.is_empty(),
+            "fullpath" | "full_path" => !self.full_path.is_empty(),
+            "id" => !self.id.is_empty(),
+            "vault" => !self.vault.is_empty(),
+            "author" => !self.author.is_empty(),
+            "coauthors" => !self.coauthors.is_empty(),
+            "date" => !self.date.is_empty(),
+            "tag" | "tags" => !self.tags.is_empty(),
+            "title" => !self.title.is_empty(),
+            "aliases" => !self.aliases.is_empty(),
+            "subtitle" => !self.subtitle.is_empty(),
+            "summary" => !self.summary.is_empty(),
+            "status" => !self.status.is_empty(),
+            "source_url" => !self.source_url.is_empty(),
+            "weight" | "priority" => self.weight.is_some(),
+            "body" => !self.body.trim().is_empty(),
+            _ => return Err(eyre!("Unknown field '{}'", field)),
+        };
+        Ok(present)
+    }
+
+    pub(crate) fn date_str(&self, formats: &[String]) -> Result<String, Report> {
+        if let Ok(t) = self.parse_date(formats) {
             let ret = t.with_timezone(&chrono::Utc).to_rfc3339();
             return Ok(ret);
         }
-        Err(eyre!("❌ Failed to convert path to date '{}'", &self.date))
+        Err(eyre!(status(
+            Status::Err,
+            format!("Failed to convert path to date '{}'", &self.date)
+        )))
+    }
+    /// A warning when the date falls on a different calendar day once normalized to UTC, as it
+    /// is indexed, so e.g. `date:2021-06-22` won't match a note written late that evening
+    pub(crate) fn utc_day_shift(&self, formats: &[String]) -> Option<String> {
+        let date = self.parse_date(formats).ok()?;
+        let utc = date.with_timezone(&chrono::Utc);
+        if date.date().naive_local() == utc.date().naive_utc() {
+            return None;
+        }
+        Some(status(
+            Status::Warn,
+            format!(
+                "{} is dated {}, which is {} in UTC",
+                self.filename,
+                date.date().naive_local(),
+                utc.date().naive_utc()
+            ),
+        ))
     }
-    pub(crate) fn parse_date(&self) -> Result<DateTime<FixedOffset>, Report> {
+    /// Parse the date as RFC 3339 or `%Y-%m-%dT%T%z`, falling back to each of the given
+    /// `chrono` formats in order. Formats without a timezone are taken to be UTC, and formats
+    /// without a time to be midnight.
+    pub(crate) fn parse_date(&self, formats: &[String]) -> Result<DateTime<FixedOffset>, Report> {
         if let Ok(rfc3339) = DateTime::parse_from_rfc3339(&self.date) {
             return Ok(rfc3339);
         } else if let Ok(s) = DateTime::parse_from_str(&self.date, &String::from("%Y-%m-%dT%T%z")) {
             return Ok(s);
         }
-        eprintln!("❌ Failed to convert path to str '{}'", &self.filename);
-        Err(eyre!(
-            "❌ Failed to convert path to str '{}'",
-            &self.filename
-        ))
+        let utc = FixedOffset::east(0);
+        for fmt in formats {
+            if let Ok(t) = DateTime::parse_from_str(&self.date, fmt) {
+                return Ok(t);
+            } else if let Ok(t) = NaiveDateTime::parse_from_str(&self.date, fmt) {
+                return Ok(utc.from_utc_datetime(&t));
+            } else if let Ok(d) = NaiveDate::parse_from_str(&self.date, fmt) {
+                return Ok(utc.from_utc_datetime(&d.and_hms(0, 0, 0)));
+            }
+        }
+        Err(eyre!(status(
+            Status::Err,
+            format!("Failed to convert path to str '{}'", &self.filename)
+        )))
+    }
+}
+
+/// Keep the documents that have a value for every field in `has` and none of those in `lacks`
+pub(crate) fn filter_fields(
+    docs: Vec<TikaDocument>,
+    has: &[&str],
+    lacks: &[&str],
+) -> Result<Vec<TikaDocument>, Report> {
+    let mut kept = Vec::new();
+    for doc in docs {
+        let mut keep = true;
+        for field in has {
+            keep &= doc.has_field(field)?;
+        }
+        for field in lacks {
+            keep &= !doc.has_field(field)?;
+        }
+        if keep {
+            kept.push(doc);
+        }
+    }
+    Ok(kept)
+}
+
+/// What kind of problem stopped a document from loading
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum LoadErrorKind {
+    Yaml,
+    Date,
+    Io,
+}
+
+/// A document that couldn't be loaded, reported as a JSON object with `--json-errors`
+#[derive(Debug, Serialize)]
+pub(crate) struct LoadError {
+    pub file: String,
+    pub kind: LoadErrorKind,
+    pub message: String,
+}
+
+impl LoadError {
+    pub(crate) fn new(path: &Path, kind: LoadErrorKind, message: impl fmt::Display) -> LoadError {
+        LoadError {
+            file: path.display().to_string(),
+            kind,
+            message: message.to_string(),
+        }
+    }
+
+    /// Print the error to stderr, as a JSON object when `json` is set
+    pub(crate) fn report(&self, json: bool) {
+        // Nothing more can be done if stderr is gone
+        let _ = self.write(&mut io::stderr(), json);
+    }
+
+    pub(crate) fn write<W: Write>(&self, out: &mut W, json: bool) -> io::Result<()> {
+        if json {
+            writeln!(out, "{}", serde_json::to_string(self)?)
+        } else {
+            writeln!(out, "{}", status(Status::Err, self))
+        }
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Failed to load file {}: {}", self.file, self.message)
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+/// A YAML scalar such as a string, number or boolean, taken as its string representation
+struct Scalar(String);
+
+impl<'de> Deserialize<'de> for Scalar {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct ScalarVisitor;
+
+        impl<'de> de::Visitor<'de> for ScalarVisitor {
+            type Value = Scalar;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("string, number or boolean")
+            }
+
+            fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                Ok(Scalar(value.to_owned()))
+            }
+
+            fn visit_i64<E>(self, value: i64) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                Ok(Scalar(value.to_string()))
+            }
+
+            fn visit_u64<E>(self, value: u64) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                Ok(Scalar(value.to_string()))
+            }
+
+            fn visit_f64<E>(self, value: f64) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                Ok(Scalar(value.to_string()))
+            }
+
+            fn visit_bool<E>(self, value: bool) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                Ok(Scalar(value.to_string()))
+            }
+        }
+
+        deserializer.deserialize_any(ScalarVisitor)
+    }
+}
+
+/// Support Deserializing a date given as a string or as a YAML timestamp, such as an unquoted
+/// `2021-06-22` or `2021-06-22 12:48:16 -4`, which is normalized to RFC 3339. Other strings,
+/// which may be in one of the configured date formats, are kept as they are.
+fn string_or_timestamp<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct StringOrTimestamp;
+
+    impl<'de> de::Visitor<'de> for StringOrTimestamp {
+        type Value = String;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("date string or YAML timestamp")
+        }
+
+        fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+        where
+            E: de::Error,
+        {
+            if DateTime::parse_from_rfc3339(value).is_ok() {
+                return Ok(value.to_owned());
+            }
+            Ok(yaml_timestamp(value).map_or_else(|| value.to_owned(), |t| t.to_rfc3339()))
+        }
     }
+
+    deserializer.deserialize_any(StringOrTimestamp)
+}
+
+/// Parse a YAML 1.1 timestamp: a date alone, taken as midnight UTC, or a date and a time
+/// separated by `T` or spaces, with optional fractional seconds and an optional `Z` or
+/// `±HH[:MM]` zone, UTC without one
+fn yaml_timestamp(value: &str) -> Option<DateTime<FixedOffset>> {
+    let utc = FixedOffset::east(0);
+    let is_space = |c: char| c == ' ' || c == '\t';
+    let split = match value.find(|c: char| c == 'T' || c == 't' || is_space(c)) {
+        Some(split) => split,
+        None => {
+            let date = NaiveDate::parse_from_str(value, "%Y-%m-%d").ok()?;
+            return Some(utc.from_utc_datetime(&date.and_hms(0, 0, 0)));
+        }
+    };
+    let date = NaiveDate::parse_from_str(&value[..split], "%Y-%m-%d").ok()?;
+    let rest = value[split + 1..].trim_start_matches(is_space);
+    let clock_end = rest
+        .find(|c: char| !(c.is_ascii_digit() || c == ':' || c == '.'))
+        .unwrap_or(rest.len());
+    let time = NaiveTime::parse_from_str(&rest[..clock_end], "%H:%M:%S%.f").ok()?;
+    let offset = match rest[clock_end..].trim_start_matches(is_space) {
+        "" | "Z" => utc,
+        zone => {
+            let sign = match zone.as_bytes()[0] {
+                b'+' => 1,
+                b'-' => -1,
+                _ => return None,
+            };
+            let (hours, minutes) = zone[1..].split_once(':').unwrap_or((&zone[1..], "00"));
+            if hours.is_empty() || hours.len() > 2 || minutes.len() != 2 {
+                return None;
+            }
+            let hours: i32 = hours.parse().ok()?;
+            let minutes: i32 = minutes.parse().ok()?;
+            FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))?
+        }
+    };
+    offset.from_local_datetime(&date.and_time(time)).single()
 }
 
-/// Support Deserializing a string into a list of string of length 1
+/// Support Deserializing a string into a list of string of length 1, and a map into a list of
+/// `key:value` strings
 fn string_or_list_string<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
 where
     D: Deserializer<'de>,
@@ -88,45 +453,1310 @@ where
             Ok(vec![value.to_owned()])
         }
 
-        fn visit_seq<S>(self, visitor: S) -> Result<Self::Value, S::Error>
+        // Numbers and booleans, on their own or in a list, are kept as their string form
+        fn visit_i64<E>(self, value: i64) -> Result<Self::Value, E>
+        where
+            E: de::Error,
+        {
+            Ok(vec![value.to_string()])
+        }
+
+        fn visit_u64<E>(self, value: u64) -> Result<Self::Value, E>
+        where
+            E: de::Error,
+        {
+            Ok(vec![value.to_string()])
+        }
+
+        fn visit_f64<E>(self, value: f64) -> Result<Self::Value, E>
+        where
+            E: de::Error,
+        {
+            Ok(vec![value.to_string()])
+        }
+
+        fn visit_bool<E>(self, value: bool) -> Result<Self::Value, E>
+        where
+            E: de::Error,
+        {
+            Ok(vec![value.to_string()])
+        }
+
+        fn visit_seq<S>(self, mut visitor: S) -> Result<Self::Value, S::Error>
         where
             S: de::SeqAccess<'de>,
         {
-            Deserialize::deserialize(de::value::SeqAccessDeserializer::new(visitor))
+            let mut tags = Vec::new();
+            while let Some(Scalar(tag)) = visitor.next_element()? {
+                tags.push(tag);
+            }
+            Ok(tags)
+        }
+
+        // Structured tags such as `{project: tika}` are flattened to `project:tika`
+        fn visit_map<M>(self, mut visitor: M) -> Result<Self::Value, M::Error>
+        where
+            M: de::MapAccess<'de>,
+        {
+            let mut tags = Vec::new();
+            while let Some((Scalar(key), Scalar(value))) = visitor.next_entry()? {
+                tags.push(format!("{}:{}", key, value));
+            }
+            Ok(tags)
         }
     }
 
     deserializer.deserialize_any(StringOrVec(PhantomData))
 }
 
-pub(crate) fn parse_file(path: &std::path::PathBuf) -> Result<TikaDocument, io::Error> {
-    let full_path = path.to_str().unwrap();
-    let s = fs::read_to_string(full_path)?;
+/// Settings controlling how Markdown files are read into documents
+#[derive(Debug)]
+pub(crate) struct ParseOptions {
+    /// Read each frontmatter section of a file as its own document
+    pub split_sections: bool,
+    /// Frontmatter keys to read as one of the document's fields instead, as `(key, field)`
+    /// pairs, e.g. `created_at` as `date`
+    pub field_map: Vec<(String, String)>,
+    /// Where to take the date of a document without one from, trying each in order; with none
+    /// such documents fail to load
+    pub default_date: Vec<DateSource>,
+    /// Title documents without one after the first non-empty line of their body
+    pub title_from_first_line: bool,
+    /// Add `#hashtags` written in the body to the document's tags
+    pub inline_hashtags: bool,
+    /// Read plain text files, which have no frontmatter, as documents titled after the file
+    pub plaintext: bool,
+    /// Read Org-mode files, taking the title, date and tags from their `#+TITLE:`, `#+DATE:`
+    /// and `#+FILETAGS:` keywords
+    pub org: bool,
+    /// Trim and lowercase tags, dropping empty and repeated ones
+    pub normalize_tags: bool,
+    /// Frontmatter key read as the document's `source_url`
+    pub source_url_key: String,
+}
 
-    let (yaml, content) = frontmatter::parse_and_find_content(&s).unwrap();
+impl Default for ParseOptions {
+    fn default() -> Self {
+        ParseOptions {
+            split_sections: false,
+            field_map: Vec::new(),
+            default_date: vec![DateSource::Created, DateSource::Mtime, DateSource::Now],
+            title_from_first_line: false,
+            inline_hashtags: false,
+            plaintext: false,
+            org: false,
+            normalize_tags: false,
+            source_url_key: String::from("source"),
+        }
+    }
+}
+
+/// A fallback for the date of a document whose frontmatter has no `date`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum DateSource {
+    /// The frontmatter `created` key
+    Created,
+    /// The modification time of the file
+    Mtime,
+    /// The time the document is read
+    Now,
+}
+
+impl DateSource {
+    /// The source named `name` in the `default-date` config, if any
+    pub(crate) fn from_name(name: &str) -> Option<DateSource> {
+        match name {
+            "created" => Some(DateSource::Created),
+            "mtime" => Some(DateSource::Mtime),
+            "now" => Some(DateSource::Now),
+            _ => None,
+        }
+    }
+}
+
+/// Parse a file holding a single Markdown document, with the default options
+#[cfg(test)]
+pub(crate) fn parse_file(path: &std::path::PathBuf) -> Result<TikaDocument, LoadError> {
+    let s = fs::read_to_string(path).map_err(|e| LoadError::new(path, LoadErrorKind::Io, e))?;
+    parse_contents(path, &s, &ParseOptions::default())
+}
+
+/// Parse a Markdown file, as one document per frontmatter section when `split_sections` is set.
+/// Documents from a file with several sections have `#N` appended to their full path and
+/// filename, counting from 1.
+pub(crate) fn parse_documents(
+    path: &std::path::PathBuf,
+    opts: &ParseOptions,
+) -> Result<Vec<TikaDocument>, LoadError> {
+    let s = profile::time(Phase::Read, || fs::read_to_string(path))
+        .map_err(|e| LoadError::new(path, LoadErrorKind::Io, e))?;
+    if opts.plaintext && is_plaintext(path) {
+        return Ok(vec![parse_plaintext(path, &s, opts)]);
+    }
+    if opts.org && is_org(path) {
+        return Ok(vec![parse_org(path, &s, opts)]);
+    }
+    let starts = if opts.split_sections {
+        section_starts(&s)
+    } else {
+        vec![0]
+    };
+    if starts.len() < 2 {
+        return Ok(vec![parse_contents(path, &s, opts)?]);
+    }
+
+    let ends = starts
+        .iter()
+        .skip(1)
+        .copied()
+        .chain(std::iter::once(s.len()));
+    starts
+        .iter()
+        .zip(ends)
+        .enumerate()
+        .map(|(i, (&start, end))| {
+            let mut doc = parse_contents(path, &s[start..end], opts)?;
+            doc.filename = format!("{}#{}", doc.filename, i + 1);
+            doc.full_path.push(format!("#{}", i + 1));
+            Ok(doc)
+        })
+        .collect()
+}
+
+/// Offsets where each section of a file with several frontmatter+body sections starts. After
+/// the first frontmatter block, a `---` line only starts a section when the lines up to the
+/// next `---` line are YAML with a `title`; otherwise it's a horizontal rule in the body.
+fn section_starts(s: &str) -> Vec<usize> {
+    let mut fences = Vec::new();
+    let mut offset = 0;
+    for line in s.split_inclusive('\n') {
+        if line.trim_end() == "---" {
+            fences.push((offset, offset + line.len()));
+        }
+        offset += line.len();
+    }
+
+    let mut starts = vec![0];
+    if fences.first().map(|&(start, _)| start) != Some(0) {
+        return starts;
+    }
+    // The first two fences delimit the first frontmatter block
+    let mut i = 2;
+    while i + 1 < fences.len() {
+        let yaml = &s[fences[i].1..fences[i + 1].0];
+        let is_frontmatter = YamlLoader::load_from_str(yaml)
+            .ok()
+            .and_then(|docs| docs.into_iter().next())
+            .map_or(false, |doc| !doc["title"].is_badvalue());
+        if is_frontmatter {
+            starts.push(fences[i].0);
+            i += 2;
+        } else {
+            i += 1;
+        }
+    }
+    starts
+}
+
+/// Parse the frontmatter and body in `s`, read from the file at `path`, reading the frontmatter
+/// keys in the field map as the fields they're mapped to and filling in a missing date
+fn parse_contents(
+    path: &std::path::PathBuf,
+    s: &str,
+    opts: &ParseOptions,
+) -> Result<TikaDocument, LoadError> {
+    let full_path = path.to_str().unwrap();
+    let start = Instant::now();
+    let (yaml, content) = frontmatter::parse_and_find_content(s)
+        .map_err(|e| LoadError::new(path, LoadErrorKind::Yaml, e))?;
     match yaml {
-        Some(yaml) => {
+        Some(mut yaml) => {
+            rename_keys(&mut yaml, &opts.field_map);
+            let source_url_key = (opts.source_url_key.clone(), String::from("source_url"));
+            rename_keys(&mut yaml, &[source_url_key]);
+            default_date(&mut yaml, path, &opts.default_date);
+            if opts.title_from_first_line {
+                default_title(&mut yaml, content);
+            }
+            split_list(&mut yaml, "title", "aliases");
+            split_list(&mut yaml, "author", "coauthors");
             let mut out_str = String::new();
             {
                 let mut emitter = YamlEmitter::new(&mut out_str);
-                emitter.dump(&yaml).unwrap(); // dump the YAML object to a String
+                // dump the YAML object to a String
+                emitter
+                    .dump(&yaml)
+                    .map_err(|e| LoadError::new(path, LoadErrorKind::Yaml, format!("{:?}", e)))?;
             }
 
-            let mut doc: TikaDocument = serde_yaml::from_str(&out_str).unwrap();
+            let mut doc: TikaDocument = serde_yaml::from_str(&out_str)
+                .map_err(|e| LoadError::new(path, LoadErrorKind::Yaml, e))?;
+            profile::add(Phase::Parse, start);
+            let start = Instant::now();
             // TODO Is this check necessary?
             if doc.filename == *"" {
                 doc.filename = String::from(path.file_name().unwrap().to_str().unwrap());
             }
 
             doc.full_path = OsString::from(full_path);
+            doc.id = content_id(s);
+            doc.modified = FileMeta::read(path).mtime.unwrap_or_default();
+
+            if opts.inline_hashtags {
+                for tag in hashtags(content) {
+                    if !doc.tags.contains(&tag) {
+                        doc.tags.push(tag);
+                    }
+                }
+            }
+            for tag in sidecar_tags(path) {
+                if !doc.tags.contains(&tag) {
+                    doc.tags.push(tag);
+                }
+            }
+            if opts.normalize_tags {
+                doc.tags = normalize_tags(doc.tags);
+            }
 
+            doc.summary = summarize(content);
+            doc.words = content.split_whitespace().count();
+            doc.has_attachments = !asset_links(content).is_empty();
             doc.body = content.to_string();
+            profile::add(Phase::Markdown, start);
 
             Ok(doc)
         }
-        None => Err(Error::new(
-            ErrorKind::Other,
-            format!("Failed to process file {}", path.display()),
+        None => Err(LoadError::new(
+            path,
+            LoadErrorKind::Yaml,
+            "No frontmatter found",
         )),
     }
 }
+
+/// Tags kept beside the file at `path` in a `<name>.tags` sidecar, separated by newlines or
+/// commas; none when there's no sidecar
+fn sidecar_tags(path: &Path) -> Vec<String> {
+    let mut sidecar = path.as_os_str().to_owned();
+    sidecar.push(".tags");
+    match fs::read_to_string(sidecar) {
+        Ok(contents) => contents
+            .split(|c| c == '\n' || c == ',')
+            .map(str::trim)
+            .filter(|tag| !tag.is_empty())
+            .map(String::from)
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// `tags` trimmed and lowercased, without empty tags or repeats, in the order first seen
+pub(crate) fn normalize_tags(tags: Vec<String>) -> Vec<String> {
+    let mut normalized: Vec<String> = Vec::with_capacity(tags.len());
+    for tag in tags {
+        let tag = tag.trim().to_lowercase();
+        if !tag.is_empty() && !normalized.contains(&tag) {
+            normalized.push(tag);
+        }
+    }
+    normalized
+}
+
+/// Extensions of the plain text files read with `plaintext`
+const PLAINTEXT_EXTENSIONS: [&str; 2] = ["txt", "text"];
+
+fn is_plaintext(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map_or(false, |ext| {
+            PLAINTEXT_EXTENSIONS
+                .iter()
+                .any(|known| ext.eq_ignore_ascii_case(known))
+        })
+}
+
+/// Read the plain text `s`, from the file at `path`, as a document with its metadata inferred:
+/// titled after the file, dated by its modification time and with all of `s` as the body
+fn parse_plaintext(path: &std::path::PathBuf, s: &str, opts: &ParseOptions) -> TikaDocument {
+    let modified = FileMeta::read(path).mtime.unwrap_or_default();
+    let date = if modified.is_empty() {
+        chrono::Utc::now().to_rfc3339()
+    } else {
+        modified.clone()
+    };
+    TikaDocument {
+        filename: path.file_name().unwrap().to_string_lossy().into_owned(),
+        full_path: path.as_os_str().to_owned(),
+        id: content_id(s),
+        modified,
+        date,
+        tags: if opts.inline_hashtags {
+            hashtags(s)
+        } else {
+            Vec::new()
+        },
+        title: path.file_stem().unwrap().to_string_lossy().into_owned(),
+        summary: summarize(s),
+        words: s.split_whitespace().count(),
+        body: s.to_string(),
+        ..Default::default()
+    }
+}
+
+fn is_org(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map_or(false, |ext| ext.eq_ignore_ascii_case("org"))
+}
+
+/// Read the Org-mode `s`, from the file at `path`, as a document. The `#+KEYWORD:` lines heading
+/// the file stand in for frontmatter: `TITLE`, `DATE` and `FILETAGS` (`:work:meetings:`) are
+/// read as the title, date and tags, and the rest of the file is the body. Without a title the
+/// document is titled after the file, and without a date it's dated by its modification time.
+fn parse_org(path: &std::path::PathBuf, s: &str, opts: &ParseOptions) -> TikaDocument {
+    let mut keywords: Vec<(String, &str)> = Vec::new();
+    let mut body_start = 0;
+    for line in s.split_inclusive('\n') {
+        let trimmed = line.trim();
+        let keyword = trimmed
+            .strip_prefix("#+")
+            .and_then(|keyword| keyword.split_once(':'));
+        if let Some((key, value)) = keyword {
+            keywords.push((key.to_uppercase(), value.trim()));
+        } else if !trimmed.is_empty() {
+            // Anything else, e.g. a heading or `#+BEGIN_SRC`, starts the body
+            break;
+        }
+        body_start += line.len();
+    }
+    let content = &s[body_start..];
+    let keyword = |name: &str| {
+        keywords
+            .iter()
+            .find(|(key, value)| key == name && !value.is_empty())
+            .map(|(_, value)| *value)
+    };
+
+    let modified = FileMeta::read(path).mtime.unwrap_or_default();
+    let date = match keyword("DATE") {
+        Some(date) => org_date(date).unwrap_or_else(|| String::from(date)),
+        None if modified.is_empty() => chrono::Utc::now().to_rfc3339(),
+        None => modified.clone(),
+    };
+    let mut tags: Vec<String> = keyword("FILETAGS")
+        .unwrap_or_default()
+        .split(|c: char| c == ':' || c.is_whitespace())
+        .filter(|tag| !tag.is_empty())
+        .map(String::from)
+        .collect();
+    for tag in sidecar_tags(path) {
+        if !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
+    if opts.normalize_tags {
+        tags = normalize_tags(tags);
+    }
+
+    TikaDocument {
+        filename: path.file_name().unwrap().to_string_lossy().into_owned(),
+        full_path: path.as_os_str().to_owned(),
+        id: content_id(s),
+        modified,
+        date,
+        tags,
+        title: keyword("TITLE")
+            .map(String::from)
+            .unwrap_or_else(|| path.file_stem().unwrap().to_string_lossy().into_owned()),
+        summary: summarize(content),
+        words: content.split_whitespace().count(),
+        body: content.to_string(),
+        ..Default::default()
+    }
+}
+
+/// An Org timestamp such as `<2021-06-22 Tue 12:48>` or `[2021-06-22]` as RFC 3339, taking it
+/// to be UTC as dates without a timezone are
+fn org_date(date: &str) -> Option<String> {
+    let mut parts = date
+        .trim_matches(|c| matches!(c, '<' | '>' | '[' | ']'))
+        .split_whitespace();
+    let day = NaiveDate::parse_from_str(parts.next()?, "%Y-%m-%d").ok()?;
+    let time = parts
+        .find_map(|part| chrono::NaiveTime::parse_from_str(part, "%H:%M").ok())
+        .unwrap_or_else(|| chrono::NaiveTime::from_hms(0, 0, 0));
+    let utc = FixedOffset::east(0).from_utc_datetime(&day.and_time(time));
+    Some(utc.to_rfc3339())
+}
+
+/// Deterministic ID of a document's contents, as the 128-bit FNV-1a hash in hex
+pub(crate) fn content_id(contents: &str) -> String {
+    const OFFSET_BASIS: u128 = 0x6c62272e07bb014262b821756295c58d;
+    const PRIME: u128 = 0x0000000001000000000000000000013b;
+    let hash = contents.bytes().fold(OFFSET_BASIS, |hash, byte| {
+        (hash ^ byte as u128).wrapping_mul(PRIME)
+    });
+    format!("{:032x}", hash)
+}
+
+/// Rename each `(key, field)` pair's key to the field, unless the field is already set
+fn rename_keys(yaml: &mut Yaml, field_map: &[(String, String)]) {
+    let hash = match yaml {
+        Yaml::Hash(hash) => hash,
+        _ => return,
+    };
+    for (key, field) in field_map {
+        let field = Yaml::String(field.clone());
+        if hash.contains_key(&field) {
+            continue;
+        }
+        if let Some(value) = hash.remove(&Yaml::String(key.clone())) {
+            hash.insert(field, value);
+        }
+    }
+}
+
+/// Set a missing `date` from the first of the `sources` that has one
+fn default_date(yaml: &mut Yaml, path: &Path, sources: &[DateSource]) {
+    let hash = match yaml {
+        Yaml::Hash(hash) => hash,
+        _ => return,
+    };
+    let key = Yaml::String(String::from("date"));
+    if hash.contains_key(&key) {
+        return;
+    }
+    let date = sources.iter().find_map(|source| match source {
+        DateSource::Created => hash.get(&Yaml::String(String::from("created"))).cloned(),
+        DateSource::Mtime => FileMeta::read(path).mtime.map(Yaml::String),
+        DateSource::Now => Some(Yaml::String(chrono::Utc::now().to_rfc3339())),
+    });
+    if let Some(date) = date {
+        hash.insert(key, date);
+    }
+}
+
+/// Set a missing `title` to the first non-empty line of `content`, without any `#` heading
+/// marker
+fn default_title(yaml: &mut Yaml, content: &str) {
+    let hash = match yaml {
+        Yaml::Hash(hash) => hash,
+        _ => return,
+    };
+    let key = Yaml::String(String::from("title"));
+    if hash.contains_key(&key) {
+        return;
+    }
+    let first_line = content.lines().map(str::trim).find(|line| !line.is_empty());
+    if let Some(line) = first_line {
+        let title = line.trim_start_matches('#').trim_start();
+        hash.insert(key, Yaml::String(String::from(title)));
+    }
+}
+
+/// Keep the first entry of a list-valued `key` as its value, moving the rest to `rest_key`, e.g.
+/// extra titles to `aliases`
+fn split_list(yaml: &mut Yaml, key: &str, rest_key: &str) {
+    let hash = match yaml {
+        Yaml::Hash(hash) => hash,
+        _ => return,
+    };
+    let key = Yaml::String(String::from(key));
+    let mut values = match hash.get(&key) {
+        Some(Yaml::Array(values)) => values.clone().into_iter(),
+        _ => return,
+    };
+    hash.insert(
+        key,
+        values.next().unwrap_or_else(|| Yaml::String(String::new())),
+    );
+
+    let rest_key = Yaml::String(String::from(rest_key));
+    let mut rest = match hash.remove(&rest_key) {
+        Some(Yaml::Array(rest)) => rest,
+        Some(value) => vec![value],
+        None => Vec::new(),
+    };
+    rest.extend(values);
+    hash.insert(rest_key, Yaml::Array(rest));
+}
+
+/// Plain text of the first non-empty paragraph of the Markdown `content`, skipping any leading H1
+pub(crate) fn summarize(content: &str) -> String {
+    content
+        .lines()
+        .map(str::trim)
+        .skip_while(|line| line.is_empty() || line.starts_with("# "))
+        .take_while(|line| !line.is_empty())
+        .flat_map(str::split_whitespace)
+        .map(strip_emphasis)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Plain text of the Markdown `content` with its whitespace collapsed, cut down to at most
+/// `max_chars` characters ending in `…` when anything was cut. Headings, quote and list markers,
+/// emphasis, inline code marks and link targets are dropped, as are images and code blocks.
+pub(crate) fn preview(content: &str, max_chars: usize) -> String {
+    let mut words = Vec::new();
+    let mut in_fence = false;
+    for line in content.lines() {
+        let line = line.trim();
+        if line.starts_with("```") || line.starts_with("~~~") {
+            in_fence = !in_fence;
+            continue;
+        }
+        if in_fence {
+            continue;
+        }
+        let line = line
+            .trim_start_matches(|c: char| c == '#' || c == '>')
+            .trim_start();
+        let line = ["- ", "* ", "+ "]
+            .iter()
+            .find_map(|marker| line.strip_prefix(marker))
+            .unwrap_or(line);
+        let line = match line.split_once(". ") {
+            Some((n, item)) if !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()) => item,
+            _ => line,
+        };
+        for word in link_texts(line).split_whitespace() {
+            let word: String = strip_emphasis(word)
+                .chars()
+                .filter(|&c| !"`~[]".contains(c))
+                .collect();
+            if !word.is_empty() {
+                words.push(word);
+            }
+        }
+    }
+
+    let text = words.join(" ");
+    if text.chars().count() <= max_chars {
+        return text;
+    }
+    let mut cut: String = text.chars().take(max_chars.saturating_sub(1)).collect();
+    cut.truncate(cut.trim_end().len());
+    if max_chars > 0 {
+        cut.push('…');
+    }
+    cut
+}
+
+/// `text` with each Markdown link replaced by its text, and images left out
+fn link_texts(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(open) = rest.find('[') {
+        let image = rest[..open].ends_with('!');
+        out.push_str(&rest[..if image { open - 1 } else { open }]);
+        let inner = &rest[open + 1..];
+        let link = inner.find("](").and_then(|close| {
+            inner[close + 2..]
+                .find(')')
+                .map(|end| (close, close + 2 + end))
+        });
+        match link {
+            Some((close, end)) => {
+                if !image {
+                    out.push_str(&inner[..close]);
+                }
+                rest = &inner[end + 1..];
+            }
+            None => {
+                out.push_str(if image { "![" } else { "[" });
+                rest = inner;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Inline `#hashtags` in the Markdown `content`, without the `#`, skipping headings and code
+/// fences. A tag starts at a `#` opening a word and runs over letters, digits, `-`, `_` and `/`;
+/// purely numeric ones such as issue numbers are ignored.
+pub(crate) fn hashtags(content: &str) -> Vec<String> {
+    let mut tags: Vec<String> = Vec::new();
+    let mut in_fence = false;
+    for line in content.lines() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_fence = !in_fence;
+            continue;
+        }
+        if in_fence || trimmed.starts_with('#') && trimmed.trim_start_matches('#').starts_with(' ')
+        {
+            continue;
+        }
+        for word in line.split_whitespace() {
+            let tag: String = match word.strip_prefix('#') {
+                Some(rest) => rest
+                    .chars()
+                    .take_while(|&c| c.is_alphanumeric() || c == '-' || c == '_' || c == '/')
+                    .collect(),
+                None => continue,
+            };
+            let tag = tag.trim_end_matches(|c| c == '-' || c == '_' || c == '/');
+            if !tag.is_empty()
+                && !tag.chars().all(|c| c.is_ascii_digit())
+                && !tags.iter().any(|t| t == tag)
+            {
+                tags.push(String::from(tag));
+            }
+        }
+    }
+    tags
+}
+
+/// Targets of Markdown links and images in `content` that point at local files other than
+/// Markdown notes, e.g. `![diagram](assets/diagram.png)`
+pub(crate) fn asset_links(content: &str) -> Vec<&str> {
+    content
+        .split("](")
+        .skip(1)
+        .filter_map(|rest| rest.split(|c| c == ')' || c == ' ').next())
+        .filter(|target| {
+            !target.is_empty()
+                && !target.starts_with('#')
+                && !target.contains("://")
+                && !target.starts_with("mailto:")
+                && !target.ends_with(".md")
+        })
+        .collect()
+}
+
+/// Drop `*`/`_` emphasis markers from a word, keeping underscores inside it as in `snake_case`
+fn strip_emphasis(word: &str) -> String {
+    let word: String = word.chars().filter(|&c| c != '*').collect();
+    let word = word.trim_start_matches('_');
+    let end = word
+        .trim_end_matches(|c: char| c.is_ascii_punctuation())
+        .len();
+    let (text, punctuation) = word.split_at(end);
+    format!(
+        "{}{}",
+        text.trim_end_matches('_'),
+        punctuation.replace('_', "")
+    )
+}
+
+#[cfg(test)]
+mod filter_fields_tests {
+    use super::*;
+
+    fn docs() -> Vec<TikaDocument> {
+        vec![
+            TikaDocument {
+                title: String::from("Authored"),
+                author: String::from("Steve"),
+                ..Default::default()
+            },
+            TikaDocument {
+                title: String::from("Anonymous"),
+                tags: vec![String::from("rust")],
+                ..Default::default()
+            },
+        ]
+    }
+
+    fn titles(docs: Vec<TikaDocument>) -> Vec<String> {
+        docs.into_iter().map(|doc| doc.title).collect()
+    }
+
+    #[test]
+    fn has_field() {
+        assert_eq!(
+            vec!["Authored"],
+            titles(filter_fields(docs(), &["author"], &[]).unwrap())
+        );
+        assert_eq!(
+            vec!["Authored", "Anonymous"],
+            titles(filter_fields(docs(), &["title"], &[]).unwrap())
+        );
+    }
+
+    #[test]
+    fn lacks_field() {
+        assert_eq!(
+            vec!["Anonymous"],
+            titles(filter_fields(docs(), &[], &["author"]).unwrap())
+        );
+        assert_eq!(
+            vec!["Authored"],
+            titles(filter_fields(docs(), &["author"], &["tags"]).unwrap())
+        );
+    }
+
+    #[test]
+    fn unknown_field() {
+        assert!(filter_fields(docs(), &["colour"], &[]).is_err());
+    }
+}
+
+#[cfg(test)]
+mod asset_links_tests {
+    use super::*;
+
+    #[test]
+    fn only_local_files() {
+        let content = "![diagram](assets/diagram.png \"Diagram\") see [spec](../spec.pdf), \
+                       [other note](other.md), [site](https://example.com) and [top](#top)";
+        assert_eq!(
+            vec!["assets/diagram.png", "../spec.pdf"],
+            asset_links(content)
+        );
+    }
+}
+
+#[cfg(test)]
+mod preview_tests {
+    use super::*;
+
+    const BODY: &str = "# Weekly review\n\n\
+        Went over the **roadmap** with _the team_, see [the plan](plans/q3.md).\n\
+        ![whiteboard](assets/board.png)\n\n\
+        ```sh\nmake release\n```\n\n\
+        - Ship `tika` 0.2\n\
+        1. Write   the docs\n\
+        > Quoted [[wiki link]]\n";
+
+    #[test]
+    fn markdown_is_stripped() {
+        assert_eq!(
+            "Weekly review Went over the roadmap with the team, see the plan. Ship tika 0.2 \
+             Write the docs Quoted wiki link",
+            preview(BODY, 280)
+        );
+    }
+
+    #[test]
+    fn cut_to_the_length() {
+        let short = preview(BODY, 20);
+        assert_eq!("Weekly review Went…", short);
+        assert!(short.chars().count() <= 20);
+        assert_eq!("", preview(BODY, 0));
+    }
+}
+
+#[cfg(test)]
+mod hashtags_tests {
+    use super::*;
+
+    #[test]
+    fn hashtags_in_prose() {
+        assert_eq!(
+            vec!["rust", "machine-learning", "work/q3"],
+            hashtags(
+                "# Heading\n## #not-a-tag heading\nLearning #rust, and #machine-learning.\n\
+                 Filed under #work/q3 and #rust again, see issue #42 or a#b\n"
+            )
+        );
+    }
+
+    #[test]
+    fn code_fences_are_skipped() {
+        assert_eq!(
+            vec!["kept"],
+            hashtags("```sh\n#comment\n```\n#kept\n~~~\n#include <stdio.h>\n~~~\n")
+        );
+    }
+}
+
+#[cfg(test)]
+mod string_or_timestamp_tests {
+    use super::*;
+
+    fn date(frontmatter: &str) -> String {
+        let mut f = tempfile::Builder::new()
+            .suffix(".md")
+            .tempfile()
+            .expect("Failed to create temp file");
+        write!(
+            f,
+            "---\n{}\ntags: test\ntitle: Dated\n---\nBody\n",
+            frontmatter
+        )
+        .unwrap();
+        parse_file(&f.path().to_path_buf())
+            .expect("Failed to parse file")
+            .date
+    }
+
+    #[test]
+    fn quoted_string() {
+        assert_eq!(
+            "2021-06-22T12:48:16-04:00",
+            date("date: '2021-06-22T12:48:16-04:00'")
+        );
+    }
+
+    #[test]
+    fn unquoted_yaml_timestamp() {
+        assert_eq!(
+            "2021-06-22T12:48:16-04:00",
+            date("date: 2021-06-22T12:48:16-04:00")
+        );
+    }
+
+    #[test]
+    fn date_only_yaml_timestamp() {
+        assert_eq!("2021-06-22T00:00:00+00:00", date("date: 2021-06-22"));
+    }
+
+    #[test]
+    fn space_separated_yaml_timestamp() {
+        assert_eq!(
+            "2021-06-22T12:48:16-04:00",
+            date("date: 2021-06-22 12:48:16 -4")
+        );
+        assert_eq!(
+            "2001-12-15T02:59:43.100+00:00",
+            date("date: 2001-12-15 2:59:43.10")
+        );
+    }
+
+    #[test]
+    fn configured_formats_are_kept() {
+        assert_eq!(
+            "2021-06-22T12:48:16-0400",
+            date("date: 2021-06-22T12:48:16-0400")
+        );
+        assert_eq!("22/06/2021", date("date: 22/06/2021"));
+    }
+
+    #[test]
+    fn other_values_are_an_error() {
+        let mut f = tempfile::NamedTempFile::new().expect("Failed to create temp file");
+        write!(
+            f,
+            "---\ndate: [2021]\ntags: test\ntitle: Dated\n---\nBody\n"
+        )
+        .unwrap();
+        let err = parse_file(&f.path().to_path_buf()).expect_err("List date parsed");
+        assert_eq!(LoadErrorKind::Yaml, err.kind);
+    }
+}
+
+#[cfg(test)]
+mod string_or_list_string_tests {
+    use super::*;
+
+    fn tags(yaml: &str) -> Vec<String> {
+        let doc: TikaDocument =
+            serde_yaml::from_str(&format!("date: ''\ntitle: ''\n{}", yaml)).unwrap();
+        doc.tags
+    }
+
+    #[test]
+    fn numeric_tag() {
+        assert_eq!(vec!["2021", "rust"], tags("tags:\n  - 2021\n  - rust\n"));
+        assert_eq!(vec!["2021"], tags("tags: 2021\n"));
+    }
+
+    #[test]
+    fn boolean_tag() {
+        assert_eq!(vec!["true"], tags("tags:\n  - true\n"));
+        assert_eq!(vec!["true"], tags("tags: true\n"));
+    }
+
+    #[test]
+    fn map_of_tags() {
+        assert_eq!(
+            vec!["project:tika", "area:search"],
+            tags("tags: {project: tika, area: search}\n")
+        );
+        assert_eq!(vec!["year:2021"], tags("tags:\n  year: 2021\n"));
+    }
+}
+
+#[cfg(test)]
+mod parse_date_tests {
+    use super::*;
+
+    fn doc(date: &str) -> TikaDocument {
+        TikaDocument {
+            date: String::from(date),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn builtin_formats() {
+        assert_eq!(
+            "2021-06-22T16:48:16+00:00",
+            doc("2021-06-22T12:48:16-0400").date_str(&[]).unwrap()
+        );
+        assert_eq!(
+            "2021-06-22T16:48:16+00:00",
+            doc("2021-06-22T12:48:16-04:00").date_str(&[]).unwrap()
+        );
+    }
+
+    #[test]
+    fn custom_date_only_format() {
+        let formats = vec![String::from("%Y-%m-%d"), String::from("%d/%m/%Y")];
+        assert_eq!(
+            "2021-06-22T00:00:00+00:00",
+            doc("22/06/2021").date_str(&formats).unwrap()
+        );
+        assert!(doc("22/06/2021").date_str(&[]).is_err());
+    }
+
+    #[test]
+    fn custom_long_format() {
+        let formats = vec![String::from("%B %e, %Y")];
+        assert_eq!(
+            "2021-06-02T00:00:00+00:00",
+            doc("June  2, 2021").date_str(&formats).unwrap()
+        );
+    }
+
+    #[test]
+    fn utc_day_shift_is_warned_about() {
+        let late = TikaDocument {
+            filename: String::from("late.md"),
+            ..doc("2021-06-22T23:30:00-0400")
+        };
+        assert_eq!(
+            Some(String::from(
+                "⚠️ late.md is dated 2021-06-22, which is 2021-06-23 in UTC"
+            )),
+            late.utc_day_shift(&[])
+        );
+        assert_eq!(None, doc("2021-06-22T12:48:16-0400").utc_day_shift(&[]));
+        assert_eq!(None, doc("2021-06-22T23:30:00Z").utc_day_shift(&[]));
+    }
+}
+
+#[cfg(test)]
+mod parse_file_tests {
+    use super::*;
+
+    #[test]
+    fn tags_survive_storage_round_trip() {
+        let mut f = tempfile::Builder::new()
+            .suffix(".md")
+            .tempfile()
+            .expect("Failed to create temp file");
+        write!(
+            f,
+            "---\ndate: 2021-06-22T12:48:16-0400\ntags:\n- rust\n- machine learning\n- tika\ntitle: Tags\n---\nBody\n"
+        )
+        .unwrap();
+
+        let doc = parse_file(&f.path().to_path_buf()).expect("Failed to parse file");
+        assert_eq!(vec!["rust", "machine learning", "tika"], doc.tags);
+
+        // Documents are stored in the DB as JSON and deserialized again on retrieval
+        let stored = serde_json::to_string(&doc).unwrap();
+        let retrieved: TikaDocument = serde_json::from_str(&stored).unwrap();
+        assert_eq!(doc, retrieved);
+        assert_eq!(vec!["rust", "machine learning", "tika"], retrieved.tags);
+    }
+
+    #[test]
+    fn id_follows_the_contents() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let note = |name: &str, body: &str| {
+            let path = dir.path().join(name);
+            fs::write(
+                &path,
+                format!(
+                    "---\ndate: 2021-06-22T12:48:16-0400\ntags: test\ntitle: Id\n---\n{}\n",
+                    body
+                ),
+            )
+            .unwrap();
+            parse_file(&path).expect("Failed to parse file").id
+        };
+
+        let id = note("a.md", "Body");
+        assert_eq!(32, id.len());
+        assert_eq!(id, note("a.md", "Body"));
+        assert_eq!(id, note("renamed.md", "Body"));
+        assert_ne!(id, note("a.md", "Edited body"));
+    }
+
+    #[test]
+    fn summary_is_first_paragraph() {
+        let mut f = tempfile::Builder::new()
+            .suffix(".md")
+            .tempfile()
+            .expect("Failed to create temp file");
+        write!(
+            f,
+            "---\ndate: 2021-06-22T12:48:16-0400\ntags: test\ntitle: Summary\n---\n# Summary\n\n\
+             Some **bold** and _emphasized_ text,\nwith a snake_case word.\n\nSecond paragraph.\n"
+        )
+        .unwrap();
+
+        let doc = parse_file(&f.path().to_path_buf()).expect("Failed to parse file");
+        assert_eq!(
+            "Some bold and emphasized text, with a snake_case word.",
+            doc.summary
+        );
+    }
+
+    #[test]
+    fn title_list_is_split_into_aliases() {
+        let mut f = tempfile::Builder::new()
+            .suffix(".md")
+            .tempfile()
+            .expect("Failed to create temp file");
+        write!(
+            f,
+            "---\ndate: 2021-06-22T12:48:16-0400\ntags: test\ntitle:\n- Primary\n- Second\n- Third\n---\nBody\n"
+        )
+        .unwrap();
+
+        let doc = parse_file(&f.path().to_path_buf()).expect("Failed to parse file");
+        assert_eq!("Primary", doc.title);
+        assert_eq!(vec!["Second", "Third"], doc.aliases);
+
+        let retrieved: TikaDocument =
+            serde_json::from_str(&serde_json::to_string(&doc).unwrap()).unwrap();
+        assert_eq!(doc, retrieved);
+    }
+
+    #[test]
+    fn title_list_keeps_existing_aliases() {
+        let mut yaml =
+            yaml_rust::YamlLoader::load_from_str("title: [Primary, Second]\naliases: Original")
+                .unwrap()
+                .remove(0);
+        split_list(&mut yaml, "title", "aliases");
+        assert_eq!(Some("Primary"), yaml["title"].as_str());
+        assert_eq!(
+            vec![Some("Original"), Some("Second")],
+            yaml["aliases"]
+                .as_vec()
+                .unwrap()
+                .iter()
+                .map(Yaml::as_str)
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn author_list_is_split_into_coauthors() {
+        let mut f = tempfile::Builder::new()
+            .suffix(".md")
+            .tempfile()
+            .expect("Failed to create temp file");
+        write!(
+            f,
+            "---\nauthor: [Alice, Bob]\ndate: 2021-06-22T12:48:16-0400\ntags: test\ntitle: Standup\n---\nBody\n"
+        )
+        .unwrap();
+
+        let doc = parse_file(&f.path().to_path_buf()).expect("Failed to parse file");
+        assert_eq!("Alice", doc.author);
+        assert_eq!(vec!["Bob"], doc.coauthors);
+    }
+
+    #[test]
+    fn sections_start_at_frontmatter_with_a_title() {
+        let s = "---\ntitle: Monday\n---\nBefore a rule\n---\nAfter it\n---\ntitle: Tuesday\n---\nMore\n";
+        let tuesday = s.find("---\ntitle: Tuesday").unwrap();
+        assert_eq!(vec![0, tuesday], section_starts(s));
+        assert_eq!(
+            vec![0],
+            section_starts("No frontmatter\n---\ntitle: X\n---\n")
+        );
+    }
+
+    #[test]
+    fn mapped_keys_fill_in_fields() {
+        let mut f = tempfile::Builder::new()
+            .suffix(".md")
+            .tempfile()
+            .expect("Failed to create temp file");
+        write!(
+            f,
+            "---\ncreated_at: 2021-06-22T12:48:16-0400\ntags: test\nname: Imported\ntitle: Kept\n---\nBody\n"
+        )
+        .unwrap();
+
+        let opts = ParseOptions {
+            field_map: vec![
+                (String::from("created_at"), String::from("date")),
+                (String::from("name"), String::from("title")),
+            ],
+            ..Default::default()
+        };
+        let doc = parse_documents(&f.path().to_path_buf(), &opts)
+            .expect("Failed to parse file")
+            .remove(0);
+        assert_eq!("2021-06-22T12:48:16-0400", doc.date);
+        // A field set under its own name wins
+        assert_eq!("Kept", doc.title);
+    }
+
+    fn parse_undated(
+        frontmatter: &str,
+        default_date: Vec<DateSource>,
+    ) -> Result<TikaDocument, LoadError> {
+        let mut f = tempfile::Builder::new()
+            .suffix(".md")
+            .tempfile()
+            .expect("Failed to create temp file");
+        write!(
+            f,
+            "---\n{}tags: test\ntitle: Undated\n---\nBody\n",
+            frontmatter
+        )
+        .unwrap();
+
+        let opts = ParseOptions {
+            default_date,
+            ..Default::default()
+        };
+        parse_documents(&f.path().to_path_buf(), &opts).map(|mut docs| docs.remove(0))
+    }
+
+    fn assert_recent(date: &str) {
+        let date = DateTime::parse_from_rfc3339(date).unwrap();
+        let age = chrono::Utc::now().signed_duration_since(date);
+        assert!(age < chrono::Duration::minutes(1), "dated {} ago", age);
+    }
+
+    #[test]
+    fn missing_date_falls_back_to_created() {
+        let doc = parse_undated(
+            "created: 2021-06-22T12:48:16-0400\n",
+            ParseOptions::default().default_date,
+        )
+        .expect("Failed to parse file");
+        assert_eq!("2021-06-22T12:48:16-0400", doc.date);
+    }
+
+    #[test]
+    fn missing_date_falls_back_to_mtime() {
+        let doc =
+            parse_undated("", ParseOptions::default().default_date).expect("Failed to parse file");
+        assert_recent(&doc.date);
+
+        let doc = parse_undated(
+            "created: 2021-06-22T12:48:16-0400\n",
+            vec![DateSource::Mtime, DateSource::Created],
+        )
+        .expect("Failed to parse file");
+        assert_recent(&doc.date);
+    }
+
+    #[test]
+    fn missing_date_falls_back_to_now() {
+        let doc = parse_undated("", vec![DateSource::Created, DateSource::Now])
+            .expect("Failed to parse file");
+        assert_recent(&doc.date);
+    }
+
+    #[test]
+    fn missing_date_fails_without_fallbacks() {
+        let err = parse_undated("created: 2021-06-22T12:48:16-0400\n", Vec::new())
+            .expect_err("Undated file parsed");
+        assert_eq!(LoadErrorKind::Yaml, err.kind);
+    }
+
+    #[test]
+    fn present_date_is_kept() {
+        let doc = parse_undated(
+            "created: 2020-01-01T00:00:00Z\ndate: 2021-06-22T12:48:16-0400\n",
+            ParseOptions::default().default_date,
+        )
+        .expect("Failed to parse file");
+        assert_eq!("2021-06-22T12:48:16-0400", doc.date);
+    }
+
+    #[test]
+    fn first_line_becomes_the_title() {
+        let mut f = tempfile::Builder::new()
+            .suffix(".md")
+            .tempfile()
+            .expect("Failed to create temp file");
+        write!(
+            f,
+            "---\ndate: 2021-06-22T12:48:16-0400\ntags: test\n---\n\n  Groceries for the week  \nEggs\n"
+        )
+        .unwrap();
+        let path = f.path().to_path_buf();
+
+        assert_eq!(
+            LoadErrorKind::Yaml,
+            parse_file(&path).expect_err("Untitled file parsed").kind
+        );
+
+        let opts = ParseOptions {
+            title_from_first_line: true,
+            ..Default::default()
+        };
+        let doc = parse_documents(&path, &opts)
+            .expect("Failed to parse file")
+            .remove(0);
+        assert_eq!("Groceries for the week", doc.title);
+    }
+
+    #[test]
+    fn first_line_title_skips_heading_marker() {
+        let mut yaml = yaml_rust::YamlLoader::load_from_str("tags: test")
+            .unwrap()
+            .remove(0);
+        default_title(&mut yaml, "# Heading\nBody");
+        assert_eq!(Some("Heading"), yaml["title"].as_str());
+
+        let mut yaml = yaml_rust::YamlLoader::load_from_str("title: Kept")
+            .unwrap()
+            .remove(0);
+        default_title(&mut yaml, "First line");
+        assert_eq!(Some("Kept"), yaml["title"].as_str());
+    }
+
+    #[test]
+    fn broken_yaml_is_reported_as_json() {
+        let mut f = tempfile::Builder::new()
+            .suffix(".md")
+            .tempfile()
+            .expect("Failed to create temp file");
+        write!(f, "---\ntitle: [unclosed\n---\nBody\n").unwrap();
+
+        let err = parse_file(&f.path().to_path_buf()).expect_err("Broken YAML parsed");
+        assert_eq!(LoadErrorKind::Yaml, err.kind);
+
+        let mut out = Vec::new();
+        err.write(&mut out, true).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!("yaml", json["kind"]);
+        assert_eq!(f.path().to_str().unwrap(), json["file"]);
+        assert!(!json["message"].as_str().unwrap().is_empty());
+    }
+}
+
+#[cfg(test)]
+mod file_meta_tests {
+    use super::*;
+
+    #[test]
+    fn size_and_mtime_of_a_file() {
+        let mut f = tempfile::NamedTempFile::new().expect("Failed to create temp file");
+        let contents = "---\ntitle: Meta\n---\nBody\n";
+        write!(f, "{}", contents).unwrap();
+
+        let meta = FileMeta::read(f.path());
+        assert_eq!(Some(contents.len() as u64), meta.size);
+        let mtime = DateTime::parse_from_rfc3339(&meta.mtime.unwrap()).unwrap();
+        let age = chrono::Utc::now().signed_duration_since(mtime);
+        assert!(age < chrono::Duration::minutes(1), "modified {} ago", age);
+    }
+
+    #[test]
+    fn missing_file_is_null() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let meta = FileMeta::read(dir.path().join("deleted.md"));
+        assert_eq!(FileMeta::default(), meta);
+        assert_eq!(
+            r#"{"size":null,"mtime":null}"#,
+            serde_json::to_string(&meta).unwrap()
+        );
+    }
+}
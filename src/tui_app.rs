@@ -1,9 +1,21 @@
+use crate::clipboard::{Clipboard, SystemClipboard};
+use crate::editor;
+use crate::fuzzy;
+use crate::indexer;
+use crate::tags::{pin_tags, tag_counts};
 use crate::tika_document::TikaDocument;
 use crate::util::event::{Event, Events};
 use crate::xapian_utils;
 use color_eyre::Report;
-use std::io::{stdout, Write};
-use termion::{event::Key, raw::IntoRawMode, screen::AlternateScreen};
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::io::{self, stdout, Stdout, Write};
+use std::time::Duration;
+use termion::{
+    event::Key,
+    raw::{IntoRawMode, RawTerminal},
+    screen::{AlternateScreen, ToAlternateScreen, ToMainScreen},
+};
 use tui::{
     backend::TermionBackend,
     layout::{Constraint, Direction, Layout},
@@ -16,8 +28,28 @@ use tui::{
 // no method named `width` found for struct `std::string::String` in the current scope
 use unicode_width::UnicodeWidthStr;
 
+/// What the finder's list is currently showing
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Mode {
+    /// Documents matching the query
+    Search,
+    /// All tags, selecting one filters the search on it
+    Tags,
+}
+
+/// Which part of the highlighted match to copy to the clipboard
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum CopyField {
+    Path,
+    Title,
+}
+
 /// TerminalApp holds the state of the application
 pub(crate) struct TerminalApp {
+    /// Whether the list shows query matches or tags
+    pub(crate) mode: Mode,
+    /// All tags with their document counts, for browsing in `Mode::Tags`
+    pub(crate) tags: Vec<(String, usize)>,
     /// Current value of the input box
     pub(crate) input: String,
     /// Preview window
@@ -30,6 +62,12 @@ pub(crate) struct TerminalApp {
     pub(crate) errout: String,
     /// Display the parsed query for debugging purposes
     pub(crate) query: String,
+    /// Paths opened in the editor during this session, shown dimmed
+    pub(crate) opened: HashSet<String>,
+    /// Waiting for the user to confirm deleting the highlighted match
+    pub(crate) confirm_delete: bool,
+    /// Number the first ten matches, and let their digit keys open them
+    pub(crate) quick_open: bool,
 }
 
 impl TerminalApp {
@@ -43,6 +81,62 @@ impl TerminalApp {
         ret
     }
 
+    /// Remember that `path` was opened in the editor
+    pub fn mark_opened(&mut self, path: String) {
+        self.opened.insert(path);
+    }
+
+    /// List entries for the query matches, dimming the ones already opened, and numbering the
+    /// first ten for their digit keys in quick-open mode
+    pub fn match_items(&self) -> Vec<ListItem<'static>> {
+        self.matches
+            .iter()
+            .enumerate()
+            .map(|(i, m)| {
+                let number = match i {
+                    0..=9 if self.quick_open => format!("{}: ", i),
+                    _ => String::new(),
+                };
+                let content = if m.vault.is_empty() {
+                    vec![Spans::from(Span::raw(format!("{}{}", number, m.title)))]
+                } else {
+                    vec![Spans::from(Span::raw(format!(
+                        "{}[{}] {}",
+                        number, m.vault, m.title
+                    )))]
+                };
+                let item = ListItem::new(content);
+                match m.full_path.to_str() {
+                    Some(path) if self.opened.contains(path) => item.style(dimmed_style()),
+                    _ => item,
+                }
+            })
+            .collect()
+    }
+
+    /// The text copying `field` of the highlighted match would put on the clipboard
+    pub fn copy_text(&self, field: CopyField) -> Option<String> {
+        let doc = self.state.selected().and_then(|i| self.matches.get(i))?;
+        match field {
+            CopyField::Path => Some(doc.full_path.to_string_lossy().into_owned()),
+            CopyField::Title => Some(doc.title.clone()),
+        }
+    }
+
+    /// Copy `field` of the highlighted match to `clipboard`, returning a line to show the user
+    /// whether it worked, or `None` with no match highlighted
+    pub fn copy_selected(
+        &self,
+        field: CopyField,
+        clipboard: &mut impl Clipboard,
+    ) -> Option<String> {
+        let text = self.copy_text(field)?;
+        Some(match clipboard.set_text(text.clone()) {
+            Ok(()) => format!("Copied {}", text),
+            Err(e) => format!("Couldn't copy to the clipboard: {}", e),
+        })
+    }
+
     pub fn get_selected_contents(&mut self) -> String {
         if let Some(i) = self.state.selected() {
             return self.matches[i].body.clone();
@@ -50,10 +144,61 @@ impl TerminalApp {
         String::from("")
     }
 
+    /// Switch to searching, pinning the query to the highlighted tag
+    pub fn select_tag(&mut self) {
+        if let Some((tag, _)) = self.state.selected().and_then(|i| self.tags.get(i)) {
+            self.input = if tag.contains(char::is_whitespace) {
+                format!("tag:\"{}\" ", tag)
+            } else {
+                format!("tag:{} ", tag)
+            };
+        }
+        self.mode = Mode::Search;
+        self.state.select(None);
+    }
+
+    /// Footer text counting what the list shows, e.g. `37 matches`. The finder only fetches
+    /// `xapian_utils::DEFAULT_LIMIT` matches, so a full list may be short of the real count.
+    pub fn footer(&self) -> String {
+        match self.mode {
+            Mode::Search if self.matches.len() >= xapian_utils::DEFAULT_LIMIT => {
+                format!("{}+ matches", xapian_utils::DEFAULT_LIMIT)
+            }
+            Mode::Search => match self.matches.len() {
+                0 => String::from("No matches"),
+                1 => String::from("1 match"),
+                n => format!("{} matches", n),
+            },
+            Mode::Tags => match self.tags.len() {
+                1 => String::from("1 tag"),
+                n => format!("{} tags", n),
+            },
+        }
+    }
+
+    /// Highlight match `i`, returning whether there is one, e.g. for its digit key
+    pub fn select_index(&mut self, i: usize) -> bool {
+        if self.mode != Mode::Search || i >= self.matches.len() {
+            return false;
+        }
+        self.state.select(Some(i));
+        true
+    }
+
+    fn list_len(&self) -> usize {
+        match self.mode {
+            Mode::Search => self.matches.len(),
+            Mode::Tags => self.tags.len(),
+        }
+    }
+
     pub fn next(&mut self) {
+        if self.list_len() == 0 {
+            return;
+        }
         let i = match self.state.selected() {
             Some(i) => {
-                if i >= self.matches.len() - 1 {
+                if i >= self.list_len() - 1 {
                     0
                 } else {
                     i + 1
@@ -65,10 +210,13 @@ impl TerminalApp {
     }
 
     pub fn previous(&mut self) {
+        if self.list_len() == 0 {
+            return;
+        }
         let i = match self.state.selected() {
             Some(i) => {
                 if i == 0 {
-                    self.matches.len() - 1
+                    self.list_len() - 1
                 } else {
                     i - 1
                 }
@@ -79,49 +227,142 @@ impl TerminalApp {
     }
 }
 
+fn dimmed_style() -> Style {
+    Style::default()
+        .fg(Color::DarkGray)
+        .add_modifier(Modifier::DIM)
+}
+
 impl Default for TerminalApp {
     fn default() -> TerminalApp {
         TerminalApp {
+            mode: Mode::Search,
+            tags: Vec::new(),
             input: String::new(),
             output: String::new(),
             matches: Vec::new(),
             state: ListState::default(),
             errout: String::new(),
             query: String::new(),
+            opened: HashSet::new(),
+            confirm_delete: false,
+            quick_open: false,
         }
     }
 }
 
+thread_local! {
+    /// Raw mode held while the finder runs, so a panic can leave it before being reported
+    static RAW_MODE: RefCell<Option<RawTerminal<Stdout>>> = RefCell::new(None);
+}
+
+/// Leave raw mode and the alternate screen, if the finder is holding the terminal in them
+fn restore_terminal() {
+    let raw = RAW_MODE.with(|raw| raw.try_borrow_mut().ok().and_then(|mut raw| raw.take()));
+    if let Some(raw) = raw {
+        // Dropping raw mode puts back the terminal settings from before the finder started
+        drop(raw);
+        let mut out = stdout();
+        let _ = write!(out, "{}{}", ToMainScreen, termion::cursor::Show);
+        let _ = out.flush();
+    }
+}
+
+/// Run `f` on the finder's raw mode handle
+fn with_raw_mode(f: impl FnOnce(&RawTerminal<Stdout>) -> io::Result<()>) -> io::Result<()> {
+    RAW_MODE.with(|raw| raw.borrow().as_ref().map_or(Ok(()), f))
+}
+
+/// Calls `restore` when dropped, whether the finder returns, fails or unwinds from a panic
+pub(crate) struct RestoreGuard<F: FnMut()> {
+    restore: F,
+}
+
+impl<F: FnMut()> Drop for RestoreGuard<F> {
+    fn drop(&mut self) {
+        (self.restore)();
+    }
+}
+
+/// Restore the terminal before the installed panic hook reports a panic, so the report and
+/// backtrace are readable and the shell is usable afterwards
 pub fn setup_panic() {
-    std::panic::set_hook(Box::new(move |x| {
-        stdout()
-            .into_raw_mode()
-            .unwrap()
-            .suspend_raw_mode()
-            .unwrap();
-        write!(
-            stdout().into_raw_mode().unwrap(),
-            "{}",
-            termion::screen::ToMainScreen
-        )
-        .unwrap();
-        write!(stdout(), "{:?}", x).unwrap();
+    let report = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        restore_terminal();
+        report(info);
     }));
 }
 
-/// Interactive query interface
-pub fn interactive_query() -> Result<Vec<String>, Report> {
+/// Settings controlling the interactive finder
+#[derive(Debug, Default)]
+pub(crate) struct FinderOptions {
+    /// Start by browsing tags rather than searching
+    pub browse_tags: bool,
+    /// Include documents marked `draft: true` in the matches
+    pub include_drafts: bool,
+    /// Let Ctrl-d delete the highlighted match from disk and the index, after asking
+    pub allow_delete: bool,
+    /// Suggest documents matching a looser query when the query matches nothing
+    pub suggest_on_empty: bool,
+    /// Tags listed first when browsing tags, in this order, ahead of the most used ones
+    pub pinned_tags: Vec<String>,
+    /// Match the query against titles as a subsequence, as fzf does, rather than searching the
+    /// index
+    pub fuzzy: bool,
+    /// Number the first ten matches and open one straight away when its digit is pressed,
+    /// rather than typing digits into the query
+    pub quick_open: bool,
+    /// How long Ctrl-d keeps retrying while another process holds the DB's write lock
+    pub lock_timeout: Duration,
+}
+
+/// Whether stdin and stdout are both a terminal, as the finder needs
+pub(crate) fn has_terminal() -> bool {
+    termion::is_tty(&io::stdin()) && termion::is_tty(&stdout())
+}
+
+/// Interactive query interface. `vault_names` are the names of the vaults indexed in each of
+/// `db_paths`, empty when vaults aren't used, for deleting matches from the right DB.
+pub(crate) fn interactive_query(
+    db_paths: &[&str],
+    vault_names: &[&str],
+    opts: &FinderOptions,
+) -> Result<Vec<String>, Report> {
     // TODO create DB in main and pass it through to query_db
-    let mut tui = tui::Terminal::new(TermionBackend::new(AlternateScreen::from(
-        stdout().into_raw_mode().unwrap(),
-    )))
-    .unwrap();
+    // Hold on to raw mode separately from the backend so it can be suspended for the editor, and
+    // restored by the panic hook
+    let raw = stdout().into_raw_mode()?;
+    RAW_MODE.with(|held| held.replace(Some(raw)));
+    let _restore = RestoreGuard {
+        restore: restore_terminal,
+    };
+    let mut tui = tui::Terminal::new(TermionBackend::new(AlternateScreen::from(stdout()))).unwrap();
 
     // Setup event handlers
     let events = Events::new();
 
     // Create default app state
-    let mut app = TerminalApp::default();
+    let mut app = TerminalApp {
+        quick_open: opts.quick_open,
+        ..Default::default()
+    };
+    let mut clipboard = SystemClipboard::default();
+    if opts.browse_tags {
+        app.tags = pin_tags(
+            tag_counts(xapian_utils::documents(db_paths)?),
+            &opts.pinned_tags,
+        );
+        app.mode = Mode::Tags;
+    }
+    // Fuzzy matching ranks every document, so they're read once up front
+    let documents: Vec<TikaDocument> = if opts.fuzzy {
+        xapian_utils::documents(db_paths)?
+            .filter(|doc| opts.include_drafts || !doc.draft)
+            .collect()
+    } else {
+        Vec::new()
+    };
 
     loop {
         // Draw UI
@@ -134,7 +375,7 @@ pub fn interactive_query() -> Result<Vec<String>, Report> {
                         Constraint::Min(1),
                         Constraint::Length(2),
                         Constraint::Length(2),
-                        Constraint::Length(2),
+                        Constraint::Length(1),
                     ]
                     .as_ref(),
                 )
@@ -147,15 +388,18 @@ pub fn interactive_query() -> Result<Vec<String>, Report> {
                 .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
                 .split(panes[0]);
 
-            // Output area where match titles are displayed
-            let matches: Vec<ListItem> = app
-                .matches
-                .iter()
-                .map(|m| {
-                    let content = vec![Spans::from(Span::raw(format!("{}", m.title)))];
-                    ListItem::new(content)
-                })
-                .collect();
+            // Output area where match titles, or tags when browsing them, are displayed
+            let matches: Vec<ListItem> = match app.mode {
+                Mode::Search => app.match_items(),
+                Mode::Tags => app
+                    .tags
+                    .iter()
+                    .map(|(tag, count)| {
+                        let content = vec![Spans::from(Span::raw(format!("{} ({})", tag, count)))];
+                        ListItem::new(content)
+                    })
+                    .collect(),
+            };
             let matches = List::new(matches)
                 .block(Block::default().borders(Borders::LEFT))
                 .highlight_style(selected_style)
@@ -168,18 +412,27 @@ pub fn interactive_query() -> Result<Vec<String>, Report> {
                 .wrap(Wrap { trim: true });
             f.render_widget(paragraph, content[1]);
 
-            // Input area where queries are entered
+            // Input area where queries are entered, query parsing errors are displayed in its
+            // border
             let input = Paragraph::new(app.input.as_ref())
                 .style(Style::default().fg(Color::Yellow))
-                .block(Block::default().borders(Borders::NONE));
+                .block(
+                    Block::default()
+                        .borders(Borders::TOP)
+                        .border_style(Style::default().fg(Color::Red))
+                        .title(Span::styled(
+                            app.errout.as_str(),
+                            Style::default().fg(Color::Red),
+                        )),
+                );
             f.render_widget(input, panes[1]);
 
             // Make the cursor visible and ask tui-rs to put it at the specified
             // coordinates after rendering
             f.set_cursor(
-                // Put cursor past the end of the input text
+                // Put cursor past the end of the input text, below the border
                 panes[1].x + app.input.width() as u16,
-                panes[1].y,
+                panes[1].y + 1,
             );
 
             // Area to display the parsed Xapian::Query.get_description()
@@ -188,23 +441,101 @@ pub fn interactive_query() -> Result<Vec<String>, Report> {
                 .block(Block::default().borders(Borders::NONE));
             f.render_widget(query, panes[2]);
 
-            // Area where errors are displayed, query parsing errors, etc
-            let errout = Paragraph::new(app.errout.as_ref())
-                .style(Style::default().fg(Color::Red))
-                .block(Block::default().borders(Borders::NONE));
-            f.render_widget(errout, panes[3]);
+            // Footer counting the matches, updated as the query is typed
+            let footer = Paragraph::new(app.footer()).style(dimmed_style());
+            f.render_widget(footer, panes[3]);
         })?;
 
-        // Handle input
+        // Handle input; ticks and resizes only redraw, and drawing reflows the panes to the
+        // current terminal size
         if let Event::Input(input) = events.next()? {
             match input {
+                Key::Char('y') if app.confirm_delete => {
+                    app.confirm_delete = false;
+                    app.errout.clear();
+                    let selected = app.state.selected().and_then(|i| app.matches.get(i));
+                    let db_path = selected.and_then(|doc| {
+                        db_paths
+                            .iter()
+                            .zip(vault_names)
+                            .find(|(_, name)| **name == doc.vault)
+                            .map(|(db_path, _)| *db_path)
+                    });
+                    if let (Some(doc), Some(db_path)) = (selected, db_path) {
+                        indexer::delete_file(db_path, doc, opts.lock_timeout)?;
+                        app.state.select(None);
+                        app.output.clear();
+                    }
+                    // Fall through to rerun the query without the deleted match
+                }
+                _ if app.confirm_delete => {
+                    app.confirm_delete = false;
+                    app.errout.clear();
+                    continue;
+                }
+                Key::Ctrl('d') if opts.allow_delete && app.mode == Mode::Search => {
+                    if let Some(path) = app.get_selected().pop() {
+                        app.errout = format!("Delete {}? y/n", path);
+                        app.confirm_delete = true;
+                    }
+                    continue;
+                }
+                Key::Char('\n') if app.mode == Mode::Tags => {
+                    app.select_tag();
+                }
                 Key::Char('\n') => {
                     // Select choice
                     break;
                 }
+                Key::Ctrl('t') => {
+                    if app.tags.is_empty() {
+                        app.tags = pin_tags(
+                            tag_counts(xapian_utils::documents(db_paths)?),
+                            &opts.pinned_tags,
+                        );
+                    }
+                    app.mode = Mode::Tags;
+                    app.state.select(None);
+                    continue;
+                }
+                Key::Ctrl('o') if app.mode == Mode::Search => {
+                    // Open the highlighted match without leaving the finder
+                    if let Some(path) = app.get_selected().pop() {
+                        write!(tui.backend_mut(), "{}", ToMainScreen)?;
+                        tui.backend_mut().flush()?;
+                        with_raw_mode(|raw| raw.suspend_raw_mode())?;
+                        let opened = editor::open_all(&[path.clone()], editor::DEFAULT_OPEN_CAP);
+                        with_raw_mode(|raw| raw.activate_raw_mode())?;
+                        write!(tui.backend_mut(), "{}", ToAlternateScreen)?;
+                        // The editor drew over the screen, so redraw all of it
+                        tui.clear()?;
+                        opened?;
+                        app.mark_opened(path);
+                    }
+                    continue;
+                }
+                Key::Ctrl('y') | Key::Alt('y') if app.mode == Mode::Search => {
+                    let field = match input {
+                        Key::Ctrl(_) => CopyField::Path,
+                        _ => CopyField::Title,
+                    };
+                    if let Some(message) = app.copy_selected(field, &mut clipboard) {
+                        app.errout = message;
+                    }
+                    continue;
+                }
                 Key::Ctrl('c') => {
                     break;
                 }
+                Key::Char(c) if app.quick_open && c.is_ascii_digit() => {
+                    let i = c.to_digit(10).unwrap_or_default() as usize;
+                    if app.select_index(i) {
+                        // Open the choice, as Enter does
+                        break;
+                    }
+                    continue;
+                }
+                Key::Char(_) if app.mode == Mode::Tags => {}
                 Key::Char(c) => {
                     app.input.push(c);
                 }
@@ -222,17 +553,43 @@ pub fn interactive_query() -> Result<Vec<String>, Report> {
                 _ => {}
             }
 
+            if app.mode == Mode::Tags {
+                continue;
+            }
+
+            if opts.fuzzy {
+                app.matches = fuzzy::rank(&documents, &app.input, xapian_utils::DEFAULT_LIMIT);
+                continue;
+            }
+
             let mut inp: String = app.input.to_owned();
             // Add a trailing ` ;` to the query to hint to Nom that it has a "full" string
             inp.push_str(&" ;");
 
-            match xapian_utils::parse_user_query(&inp) {
+            // Match the word being typed as a prefix, so results keep up with typing
+            match xapian_utils::parse_prefix_query(&inp) {
                 Ok(mut query) => {
+                    if !opts.include_drafts {
+                        query = xapian_utils::exclude_drafts(query)?;
+                    }
                     app.query = query.get_description();
-                    app.matches = xapian_utils::query_db(query)?;
+                    app.matches =
+                        xapian_utils::query_db(db_paths, query, xapian_utils::DEFAULT_LIMIT)?;
+                    app.errout.clear();
+                    if app.matches.is_empty() && opts.suggest_on_empty && !app.input.is_empty() {
+                        let suggestions = xapian_utils::suggestions(
+                            db_paths,
+                            &inp,
+                            opts.include_drafts,
+                            xapian_utils::SUGGESTIONS,
+                        )?;
+                        if let Some(message) = xapian_utils::no_matches_message(&suggestions) {
+                            app.errout = message;
+                        }
+                    }
                 }
                 Err(e) => {
-                    app.errout = e.to_string();
+                    app.errout = xapian_utils::friendly_error(&e);
                 }
             };
         }
@@ -242,3 +599,257 @@ pub fn interactive_query() -> Result<Vec<String>, Report> {
 
     Ok(app.get_selected())
 }
+
+#[cfg(test)]
+mod select_tag_tests {
+    use super::*;
+
+    fn app() -> TerminalApp {
+        TerminalApp {
+            mode: Mode::Tags,
+            tags: vec![
+                (String::from("rust"), 2),
+                (String::from("machine learning"), 1),
+            ],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn selected_tag_is_pinned_into_the_query() {
+        let mut app = app();
+        app.next();
+        app.select_tag();
+        assert_eq!(Mode::Search, app.mode);
+        assert_eq!("tag:rust ", app.input);
+        assert_eq!(None, app.state.selected());
+    }
+
+    #[test]
+    fn multi_word_tag_is_quoted() {
+        let mut app = app();
+        app.next();
+        app.next();
+        app.select_tag();
+        assert_eq!(Mode::Search, app.mode);
+        assert_eq!("tag:\"machine learning\" ", app.input);
+    }
+}
+
+#[cfg(test)]
+mod match_items_tests {
+    use super::*;
+
+    fn app() -> TerminalApp {
+        TerminalApp {
+            matches: vec![
+                TikaDocument {
+                    title: String::from("Opened"),
+                    full_path: "/notes/opened.md".into(),
+                    ..Default::default()
+                },
+                TikaDocument {
+                    title: String::from("Unopened"),
+                    full_path: "/notes/unopened.md".into(),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn opened_path_is_recorded_and_dimmed() {
+        let mut app = app();
+        app.mark_opened(String::from("/notes/opened.md"));
+        assert!(app.opened.contains("/notes/opened.md"));
+
+        let items = app.match_items();
+        assert_eq!(
+            ListItem::new(vec![Spans::from(Span::raw("Opened"))]).style(dimmed_style()),
+            items[0]
+        );
+        assert_eq!(
+            ListItem::new(vec![Spans::from(Span::raw("Unopened"))]),
+            items[1]
+        );
+    }
+}
+
+#[cfg(test)]
+mod select_index_tests {
+    use super::*;
+
+    fn app() -> TerminalApp {
+        TerminalApp {
+            matches: vec![TikaDocument::default(); 5],
+            quick_open: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn digit_selects_its_match() {
+        let mut app = app();
+        assert!(app.select_index(3));
+        assert_eq!(Some(3), app.state.selected());
+    }
+
+    #[test]
+    fn out_of_range_is_a_no_op() {
+        let mut app = app();
+        app.next();
+        assert!(!app.select_index(7));
+        assert_eq!(Some(0), app.state.selected());
+    }
+
+    #[test]
+    fn first_ten_matches_are_numbered() {
+        let mut app = app();
+        app.matches[0].title = String::from("First");
+        assert_eq!(
+            ListItem::new(vec![Spans::from(Span::raw("0: First"))]),
+            app.match_items()[0]
+        );
+    }
+}
+
+#[cfg(test)]
+mod footer_tests {
+    use super::*;
+
+    fn app(matches: usize) -> TerminalApp {
+        TerminalApp {
+            matches: vec![TikaDocument::default(); matches],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn counts_the_matches() {
+        assert_eq!("No matches", app(0).footer());
+        assert_eq!("1 match", app(1).footer());
+        assert_eq!("37 matches", app(37).footer());
+        let full = app(xapian_utils::DEFAULT_LIMIT);
+        assert_eq!(
+            format!("{}+ matches", xapian_utils::DEFAULT_LIMIT),
+            full.footer()
+        );
+    }
+
+    #[test]
+    fn counts_tags_when_browsing_them() {
+        let app = TerminalApp {
+            mode: Mode::Tags,
+            tags: vec![(String::from("rust"), 2), (String::from("go"), 1)],
+            ..Default::default()
+        };
+        assert_eq!("2 tags", app.footer());
+    }
+}
+
+#[cfg(test)]
+mod copy_selected_tests {
+    use super::*;
+
+    /// Records what was copied, or fails like a missing clipboard when `fail` is set
+    #[derive(Default)]
+    struct FakeClipboard {
+        copied: Vec<String>,
+        fail: bool,
+    }
+
+    impl Clipboard for FakeClipboard {
+        fn set_text(&mut self, text: String) -> Result<(), String> {
+            if self.fail {
+                return Err(String::from("no display"));
+            }
+            self.copied.push(text);
+            Ok(())
+        }
+    }
+
+    fn app() -> TerminalApp {
+        TerminalApp {
+            matches: vec![
+                TikaDocument {
+                    title: String::from("First"),
+                    full_path: "/notes/first.md".into(),
+                    ..Default::default()
+                },
+                TikaDocument {
+                    title: String::from("Second"),
+                    full_path: "/notes/second.md".into(),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn copies_the_highlighted_match() {
+        let mut app = app();
+        app.next();
+        app.next();
+        assert_eq!(
+            Some(String::from("/notes/second.md")),
+            app.copy_text(CopyField::Path)
+        );
+        assert_eq!(
+            Some(String::from("Second")),
+            app.copy_text(CopyField::Title)
+        );
+
+        let mut clipboard = FakeClipboard::default();
+        assert_eq!(
+            Some(String::from("Copied Second")),
+            app.copy_selected(CopyField::Title, &mut clipboard)
+        );
+        assert_eq!(vec!["Second"], clipboard.copied);
+    }
+
+    #[test]
+    fn nothing_to_copy_without_a_highlighted_match() {
+        let mut clipboard = FakeClipboard::default();
+        assert_eq!(None, app().copy_selected(CopyField::Path, &mut clipboard));
+        assert!(clipboard.copied.is_empty());
+    }
+
+    #[test]
+    fn clipboard_failure_is_reported() {
+        let mut app = app();
+        app.next();
+        let mut clipboard = FakeClipboard {
+            fail: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            Some(String::from("Couldn't copy to the clipboard: no display")),
+            app.copy_selected(CopyField::Path, &mut clipboard)
+        );
+    }
+}
+
+#[cfg(test)]
+mod restore_guard_tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[test]
+    fn restores_on_return_and_on_panic() {
+        let restored = Cell::new(0);
+        let restore = || restored.set(restored.get() + 1);
+        {
+            let _guard = RestoreGuard { restore };
+        }
+        assert_eq!(1, restored.get());
+
+        let unwound = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _guard = RestoreGuard { restore };
+            panic!("finder crashed");
+        }));
+        assert!(unwound.is_err());
+        assert_eq!(2, restored.get());
+    }
+}
@@ -1,12 +1,13 @@
-use glob::{glob, Paths};
-use std::{fs, io, io::Read, path::Path};
+use crate::xapian_utils::DEFAULT_DB_NAME;
+use glob::{MatchOptions, Pattern, PatternError};
+use std::collections::HashSet;
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+use std::{fmt, fs, io, io::Read};
 use toml::Value as tomlVal;
 
-pub(crate) fn glob_files(
-    cfg_file: &str,
-    source: Option<&str>,
-    verbosity: i8,
-) -> Result<Paths, Box<dyn std::error::Error>> {
+/// Read and parse the TOML config file
+pub(crate) fn read_config(cfg_file: &str) -> Result<tomlVal, Box<dyn std::error::Error>> {
     let cfg_fh = fs::OpenOptions::new()
         .read(true)
         .write(false)
@@ -15,15 +16,343 @@ pub(crate) fn glob_files(
     let mut buf_reader = io::BufReader::new(cfg_fh);
     let mut contents = String::new();
     buf_reader.read_to_string(&mut contents)?;
-    let toml_contents = contents.parse::<tomlVal>().unwrap();
 
-    let source_glob = toml_contents
-        .get("source-glob")
-        .expect("Failed to find 'source-glob' heading in toml config")
-        .as_str()
-        .expect("Error taking source-glob value as string");
+    Ok(contents.parse::<tomlVal>()?)
+}
+
+/// A named collection of documents with its own index
+#[derive(Debug, PartialEq)]
+pub(crate) struct Vault {
+    pub name: String,
+    pub source_glob: String,
+    pub db_path: String,
+    /// Words left out of this vault's index, on top of the top-level ones
+    pub stopwords: Vec<String>,
+}
+
+/// Lowercased words from the `stopwords` list and the `stopwords-file` (one word per line) of a
+/// config table
+pub(crate) fn stopwords(table: &tomlVal) -> Result<Vec<String>, io::Error> {
+    let mut words: Vec<String> = table
+        .get("stopwords")
+        .and_then(|v| v.as_array())
+        .map(|words| {
+            words
+                .iter()
+                .filter_map(|w| w.as_str().map(str::to_lowercase))
+                .collect()
+        })
+        .unwrap_or_default();
+    if let Some(file) = table.get("stopwords-file").and_then(|v| v.as_str()) {
+        let contents = fs::read_to_string(shellexpand::tilde(file).as_ref())?;
+        words.extend(
+            contents
+                .lines()
+                .map(str::trim)
+                .filter(|w| !w.is_empty())
+                .map(str::to_lowercase),
+        );
+    }
+    Ok(words)
+}
+
+/// Directory holding the indexes: the `--index-dir` flag, else the `index-dir` config, else
+/// `tika` under `$XDG_CACHE_HOME` or `~/.cache`
+pub(crate) fn index_dir(flag: Option<&str>, config: &tomlVal) -> PathBuf {
+    if let Some(dir) = flag.or_else(|| config.get("index-dir").and_then(|v| v.as_str())) {
+        return PathBuf::from(shellexpand::tilde(dir).as_ref());
+    }
+    let cache = std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .unwrap_or_else(|| PathBuf::from(shellexpand::tilde("~/.cache").as_ref()));
+    cache.join("tika")
+}
+
+/// Create the index directory `dir` and any missing parents, failing if it exists but isn't a
+/// writable directory
+pub(crate) fn ensure_index_dir(dir: &Path) -> Result<(), String> {
+    if dir.exists() && !dir.is_dir() {
+        return Err(format!(
+            "Index directory '{}' exists but isn't a directory",
+            dir.display()
+        ));
+    }
+    fs::create_dir_all(dir)
+        .map_err(|e| format!("Can't create index directory '{}': {}", dir.display(), e))?;
+    tempfile::tempfile_in(dir)
+        .map(|_| ())
+        .map_err(|e| format!("Index directory '{}' isn't writable: {}", dir.display(), e))
+}
+
+/// Vaults defined by `[[vault]]` entries in the config, in config order; a vault without a
+/// `db-path` keeps its index in `index_dir`
+pub(crate) fn vaults(config: &tomlVal, index_dir: &Path) -> Result<Vec<Vault>, io::Error> {
+    config
+        .get("vault")
+        .and_then(|v| v.as_array())
+        .map(|entries| {
+            entries
+                .iter()
+                .filter_map(|entry| {
+                    let name = entry.get("name")?.as_str()?;
+                    let source_glob = entry.get("source-glob")?.as_str()?;
+                    let db_path = entry
+                        .get("db-path")
+                        .and_then(|v| v.as_str())
+                        .map(String::from)
+                        .unwrap_or_else(|| {
+                            let db = index_dir.join(format!("{}-{}", DEFAULT_DB_NAME, name));
+                            db.to_string_lossy().into_owned()
+                        });
+                    Some(stopwords(entry).map(|stopwords| Vault {
+                        name: String::from(name),
+                        source_glob: String::from(source_glob),
+                        db_path,
+                        stopwords,
+                    }))
+                })
+                .collect()
+        })
+        .unwrap_or_else(|| Ok(Vec::new()))
+}
+
+/// Pick the vaults named by `selected`, or every vault for `all`
+pub(crate) fn select_vaults(vaults: Vec<Vault>, selected: &str) -> Result<Vec<Vault>, String> {
+    if selected == "all" {
+        return Ok(vaults);
+    }
+    let picked: Vec<Vault> = vaults.into_iter().filter(|v| v.name == selected).collect();
+    if picked.is_empty() {
+        return Err(format!("No vault named '{}' in the config", selected));
+    }
+    Ok(picked)
+}
+
+/// Saved queries from the `[queries]` config table as `(name, definition)` pairs, sorted by name
+pub(crate) fn saved_queries(config: &tomlVal) -> Vec<(String, String)> {
+    config
+        .get("queries")
+        .and_then(|v| v.as_table())
+        .map(|queries| {
+            queries
+                .iter()
+                .filter_map(|(name, q)| Some((name.clone(), String::from(q.as_str()?))))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Split a saved query definition into arguments at whitespace outside double quotes, keeping
+/// the quotes so phrases reach the query parser intact
+pub(crate) fn split_args(definition: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut arg = String::new();
+    let mut quoted = false;
+    for c in definition.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                arg.push(c);
+            }
+            c if c.is_whitespace() && !quoted => {
+                if !arg.is_empty() {
+                    args.push(std::mem::take(&mut arg));
+                }
+            }
+            c => arg.push(c),
+        }
+    }
+    if !arg.is_empty() {
+        args.push(arg);
+    }
+    args
+}
+
+/// The point in time `since` names, as `-i --changed-since` takes it: a `YYYY-MM-DD` day from
+/// its start in the timezone of `now`, an RFC 3339 timestamp, or a span back from `now` such as
+/// `30m`, `1h`, `2d` or `1w`
+pub(crate) fn parse_since<Tz: chrono::TimeZone>(
+    since: &str,
+    now: chrono::DateTime<Tz>,
+) -> Result<SystemTime, String> {
+    if let Ok(day) = chrono::NaiveDate::parse_from_str(since, "%Y-%m-%d") {
+        return now
+            .timezone()
+            .from_local_datetime(&day.and_hms(0, 0, 0))
+            .earliest()
+            .map(SystemTime::from)
+            .ok_or_else(|| format!("'{}' has no midnight in the local timezone", since));
+    }
+    if let Ok(t) = chrono::DateTime::parse_from_rfc3339(since) {
+        return Ok(SystemTime::from(t));
+    }
+    let unit_at = since.len() - since.chars().last().map_or(0, char::len_utf8);
+    let span = since[..unit_at]
+        .parse::<i64>()
+        .ok()
+        .and_then(|n| match &since[unit_at..] {
+            "m" => Some(chrono::Duration::minutes(n)),
+            "h" => Some(chrono::Duration::hours(n)),
+            "d" => Some(chrono::Duration::days(n)),
+            "w" => Some(chrono::Duration::weeks(n)),
+            _ => None,
+        })
+        .ok_or_else(|| {
+            format!(
+                "Invalid time '{}', expected YYYY-MM-DD, RFC 3339 or a span like 1h",
+                since
+            )
+        })?;
+    Ok(SystemTime::from(now - span))
+}
+
+/// A path that couldn't be read while walking the source directories
+#[derive(Debug)]
+pub(crate) struct WalkError {
+    path: PathBuf,
+    error: io::Error,
+}
+
+impl WalkError {
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub(crate) fn error(&self) -> &io::Error {
+        &self.error
+    }
+}
+
+impl fmt::Display for WalkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.error)
+    }
+}
+
+impl std::error::Error for WalkError {}
 
-    let source = source.unwrap_or(source_glob);
+/// Files matching the glob `pattern`, in sorted order. Symlinks to files are always matched, but
+/// symlinked directories are only walked into when `follow_symlinks` is set; each directory is
+/// walked at most once, so symlink cycles end.
+pub(crate) fn walk_glob(
+    pattern: &str,
+    follow_symlinks: bool,
+) -> Result<Vec<Result<PathBuf, WalkError>>, PatternError> {
+    let compiled = Pattern::new(pattern)?;
+    let options = MatchOptions {
+        require_literal_separator: true,
+        ..MatchOptions::new()
+    };
+
+    let base = glob_base(pattern);
+    let mut found = Vec::new();
+    if base.is_file() {
+        if compiled.matches_path_with(&base, options) {
+            found.push(Ok(base));
+        }
+        return Ok(found);
+    }
+    let mut visited = HashSet::new();
+    walk_dir(
+        &base,
+        &compiled,
+        options,
+        follow_symlinks,
+        &mut visited,
+        &mut found,
+    );
+    Ok(found)
+}
+
+/// The longest leading part of the glob `pattern` without wildcards, where walking it starts
+pub(crate) fn glob_base(pattern: &str) -> PathBuf {
+    let mut base = PathBuf::new();
+    for component in Path::new(pattern).components() {
+        let literal = match component {
+            Component::Normal(part) => !part.to_string_lossy().contains(&['*', '?', '['][..]),
+            _ => true,
+        };
+        if !literal {
+            break;
+        }
+        base.push(component);
+    }
+    base
+}
+
+fn walk_dir(
+    dir: &Path,
+    pattern: &Pattern,
+    options: MatchOptions,
+    follow_symlinks: bool,
+    visited: &mut HashSet<PathBuf>,
+    found: &mut Vec<Result<PathBuf, WalkError>>,
+) {
+    let walk_error = |path: &Path, error| {
+        Err(WalkError {
+            path: path.to_path_buf(),
+            error,
+        })
+    };
+    // Patterns starting with a wildcard are relative to the current directory
+    let fs_dir = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+    match fs::canonicalize(fs_dir) {
+        Ok(canonical) => {
+            if !visited.insert(canonical) {
+                return;
+            }
+        }
+        Err(e) => return found.push(walk_error(fs_dir, e)),
+    }
+    let mut entries: Vec<PathBuf> = match fs::read_dir(fs_dir) {
+        Ok(entries) => entries
+            .filter_map(Result::ok)
+            .map(|e| dir.join(e.file_name()))
+            .collect(),
+        Err(e) => return found.push(walk_error(fs_dir, e)),
+    };
+    entries.sort();
+
+    for path in entries {
+        let is_link = match fs::symlink_metadata(&path) {
+            Ok(meta) => meta.file_type().is_symlink(),
+            Err(e) => {
+                found.push(walk_error(&path, e));
+                continue;
+            }
+        };
+        if path.is_dir() {
+            if follow_symlinks || !is_link {
+                walk_dir(&path, pattern, options, follow_symlinks, visited, found);
+            }
+        } else if pattern.matches_path_with(&path, options) {
+            found.push(Ok(path));
+        }
+    }
+}
+
+/// Files matching the source glob, or the `source-glob` in the config, sorted by their
+/// canonical path so that indexing runs in the same order every time. Entries that couldn't be
+/// read come first.
+pub(crate) fn glob_files(
+    cfg_file: &str,
+    source: Option<&str>,
+    verbosity: i8,
+) -> Result<Vec<Result<PathBuf, WalkError>>, Box<dyn std::error::Error>> {
+    let toml_contents = read_config(cfg_file)?;
+
+    let source = source.unwrap_or_else(|| {
+        toml_contents
+            .get("source-glob")
+            .expect("Failed to find 'source-glob' heading in toml config")
+            .as_str()
+            .expect("Error taking source-glob value as string")
+    });
     let glob_path = Path::new(&source);
     let glob_str = shellexpand::tilde(glob_path.to_str().unwrap());
 
@@ -31,11 +360,302 @@ pub(crate) fn glob_files(
         println!("Sourcing Markdown documents matching : {}", glob_str);
     }
 
-    return Ok(glob(&glob_str).expect("Failed to read glob pattern"));
+    let follow_symlinks = toml_contents
+        .get("follow-symlinks")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false);
+
+    let mut found = walk_glob(&glob_str, follow_symlinks).expect("Failed to read glob pattern");
+    found.sort_by_cached_key(|entry| {
+        entry
+            .as_ref()
+            .ok()
+            .map(|path| fs::canonicalize(path).unwrap_or_else(|_| path.clone()))
+    });
+    Ok(found)
+}
+
+#[cfg(test)]
+mod glob_files_tests {
+    use super::*;
+
+    #[test]
+    fn sorted_by_canonical_path() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let notes = dir.path().join("notes");
+        fs::create_dir_all(notes.join("a")).unwrap();
+        for name in &["b.md", "a-c.md", "a/b.md", "A.md"] {
+            fs::write(notes.join(name), "note").unwrap();
+        }
+        let cfg_file = dir.path().join("tika.toml");
+        fs::write(
+            &cfg_file,
+            format!("source-glob = \"{}/**/*.md\"\n", notes.display()),
+        )
+        .unwrap();
+
+        let glob = || -> Vec<PathBuf> {
+            glob_files(cfg_file.to_str().unwrap(), None, 0)
+                .unwrap()
+                .into_iter()
+                .map(Result::unwrap)
+                .map(|path| path.strip_prefix(&notes).unwrap().to_path_buf())
+                .collect()
+        };
+        let first = glob();
+        assert_eq!(
+            vec![
+                PathBuf::from("A.md"),
+                PathBuf::from("a/b.md"),
+                PathBuf::from("a-c.md"),
+                PathBuf::from("b.md"),
+            ],
+            first
+        );
+        assert_eq!(first, glob());
+    }
+}
+
+#[cfg(test)]
+mod walk_glob_tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    /// `notes/a.md`, and `notes/linked` linking to `shared/` holding `b.md` and a link back up
+    fn vault() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        fs::create_dir(dir.path().join("notes")).unwrap();
+        fs::create_dir(dir.path().join("shared")).unwrap();
+        fs::write(dir.path().join("notes/a.md"), "a").unwrap();
+        fs::write(dir.path().join("notes/skip.txt"), "skip").unwrap();
+        fs::write(dir.path().join("shared/b.md"), "b").unwrap();
+        symlink(dir.path().join("shared"), dir.path().join("notes/linked")).unwrap();
+        symlink(dir.path(), dir.path().join("shared/cycle")).unwrap();
+        dir
+    }
+
+    fn walk(dir: &Path, follow_symlinks: bool) -> Vec<PathBuf> {
+        let pattern = format!("{}/notes/**/*.md", dir.display());
+        walk_glob(&pattern, follow_symlinks)
+            .unwrap()
+            .into_iter()
+            .map(Result::unwrap)
+            .map(|path| path.strip_prefix(dir).unwrap().to_path_buf())
+            .collect()
+    }
+
+    #[test]
+    fn follows_symlinked_directories() {
+        let dir = vault();
+        assert_eq!(
+            vec![
+                PathBuf::from("notes/a.md"),
+                PathBuf::from("notes/linked/b.md")
+            ],
+            walk(dir.path(), true)
+        );
+    }
+
+    #[test]
+    fn relative_components_are_walked() {
+        let dir = vault();
+        let pattern = format!("{}/shared/../notes/*.md", dir.path().display());
+        assert_eq!(dir.path().join("shared/../notes"), glob_base(&pattern));
+        let found: Vec<PathBuf> = walk_glob(&pattern, false)
+            .unwrap()
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(vec![dir.path().join("shared/../notes/a.md")], found);
+    }
+
+    #[test]
+    fn base_stops_at_the_first_wildcard() {
+        assert_eq!(
+            PathBuf::from("/notes/work"),
+            glob_base("/notes/work/**/*.md")
+        );
+        assert_eq!(PathBuf::from("notes"), glob_base("notes/[ab]*/x.md"));
+        // Relative patterns are walked from the current directory
+        assert_eq!(PathBuf::new(), glob_base("*.md"));
+        assert_eq!(PathBuf::from("notes/work"), glob_base("notes/work/*.md"));
+        assert_eq!(PathBuf::from("../notes"), glob_base("../notes/**/*.md"));
+    }
+
+    #[test]
+    fn skips_symlinked_directories() {
+        let dir = vault();
+        assert_eq!(vec![PathBuf::from("notes/a.md")], walk(dir.path(), false));
+    }
+}
+
+#[cfg(test)]
+mod vaults_tests {
+    use super::*;
+
+    fn config() -> tomlVal {
+        r#"
+            [[vault]]
+            name = "work"
+            source-glob = "~/work/**/*.md"
+
+            [[vault]]
+            name = "personal"
+            source-glob = "~/notes/**/*.md"
+            db-path = "/tmp/personal-db"
+            stopwords = ["Meeting", "todo"]
+        "#
+        .parse()
+        .unwrap()
+    }
+
+    fn all() -> Vec<Vault> {
+        vaults(&config(), Path::new("/var/cache/tika")).unwrap()
+    }
+
+    #[test]
+    fn db_path_defaults_to_vault_name() {
+        let vaults = all();
+        assert_eq!("/var/cache/tika/mydb-work", vaults[0].db_path);
+        assert_eq!("/tmp/personal-db", vaults[1].db_path);
+    }
+
+    #[test]
+    fn per_vault_stopwords() {
+        let vaults = all();
+        assert!(vaults[0].stopwords.is_empty());
+        assert_eq!(vec!["meeting", "todo"], vaults[1].stopwords);
+    }
+
+    #[test]
+    fn stopwords_file() {
+        let mut f = tempfile::NamedTempFile::new().expect("Failed to create temp file");
+        io::Write::write_all(&mut f, b"note\n\n  Standup \n").unwrap();
+        let config: tomlVal = format!(
+            "stopwords = [\"todo\"]\nstopwords-file = \"{}\"",
+            f.path().display()
+        )
+        .parse()
+        .unwrap();
+        assert_eq!(vec!["todo", "note", "standup"], stopwords(&config).unwrap());
+    }
+
+    #[test]
+    fn select_one_or_all() {
+        assert_eq!(2, select_vaults(all(), "all").unwrap().len());
+        let picked = select_vaults(all(), "personal").unwrap();
+        assert_eq!(
+            vec!["personal"],
+            picked.iter().map(|v| &v.name).collect::<Vec<_>>()
+        );
+        assert!(select_vaults(all(), "missing").is_err());
+    }
+}
+
+#[cfg(test)]
+mod parse_since_tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn days_timestamps_and_spans() {
+        let now = Utc.ymd(2021, 6, 22).and_hms(12, 0, 0);
+        let at = |t: chrono::DateTime<Utc>| Ok(SystemTime::from(t));
+        assert_eq!(
+            at(Utc.ymd(2021, 6, 1).and_hms(0, 0, 0)),
+            parse_since("2021-06-01", now)
+        );
+        assert_eq!(
+            at(Utc.ymd(2021, 6, 20).and_hms(8, 30, 0)),
+            parse_since("2021-06-20T10:30:00+02:00", now)
+        );
+        assert_eq!(at(now - chrono::Duration::hours(1)), parse_since("1h", now));
+        assert_eq!(at(now - chrono::Duration::days(2)), parse_since("2d", now));
+        assert!(parse_since("soon", now).is_err());
+        assert!(parse_since("1y", now).is_err());
+    }
+}
+
+#[cfg(test)]
+mod index_dir_tests {
+    use super::*;
+    use crate::indexer::index_paths;
+
+    #[test]
+    fn flag_wins_over_config() {
+        let config: tomlVal = r#"index-dir = "/srv/tika""#.parse().unwrap();
+        assert_eq!(PathBuf::from("/srv/tika"), index_dir(None, &config));
+        assert_eq!(
+            PathBuf::from("/tmp/idx"),
+            index_dir(Some("/tmp/idx"), &config)
+        );
+    }
+
+    #[test]
+    fn missing_nested_dir_is_created() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let nested = dir.path().join("cache/tika/indexes");
+        ensure_index_dir(&nested).expect("Failed to create index dir");
+        assert!(nested.is_dir());
+
+        let note = dir.path().join("note.md");
+        fs::write(
+            &note,
+            "---\ndate: 2021-06-22T12:48:16-0400\ntags: test\ntitle: Note\n---\nBody\n",
+        )
+        .unwrap();
+        let db_path = nested.join(DEFAULT_DB_NAME);
+        let indexed = index_paths(db_path.to_str().unwrap(), vec![note], &Default::default());
+        assert_eq!(1, indexed.expect("Failed to index"));
+    }
+
+    #[test]
+    fn file_in_the_way_is_an_error() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let file = dir.path().join("index");
+        fs::write(&file, "").unwrap();
+        let err = ensure_index_dir(&file).unwrap_err();
+        assert!(err.contains("isn't a directory"), "{}", err);
+    }
+}
+
+#[cfg(test)]
+mod saved_queries_tests {
+    use super::*;
+
+    #[test]
+    fn definitions_by_name() {
+        let config: tomlVal = r#"
+            [queries]
+            standup = "tags:work --recency-boost"
+            reading = 'title:"to read"'
+        "#
+        .parse()
+        .unwrap();
+        assert_eq!(
+            vec![
+                (String::from("reading"), String::from(r#"title:"to read""#)),
+                (
+                    String::from("standup"),
+                    String::from("tags:work --recency-boost")
+                ),
+            ],
+            saved_queries(&config)
+        );
+    }
+
+    #[test]
+    fn split_keeps_quoted_phrases() {
+        assert_eq!(
+            vec!["title:\"to  read\"", "--limit", "5", "rust"],
+            split_args("  title:\"to  read\" --limit 5\trust ")
+        );
+    }
 }
 
 pub(crate) mod event {
 
+    use std::cell::Cell;
     use std::io;
     use std::sync::mpsc;
     use std::thread;
@@ -47,12 +667,18 @@ pub(crate) mod event {
     pub enum Event<I> {
         Input(I),
         Tick,
+        /// The terminal size changed since the last tick
+        Resize,
     }
 
-    /// A small event handler that wrap termion input and tick events. Each event
-    /// type is handled in its own thread and returned to a common `Receiver`
+    /// A small event handler that wrap termion input, tick and resize events. Each event
+    /// type is handled in its own thread and returned to a common `Receiver`. Input is read one
+    /// key at a time, only once the previous key has been handled, so that a program run while
+    /// handling a key (e.g. an editor) gets the keys typed in the meantime.
     pub struct Events {
         rx: mpsc::Receiver<Event<Key>>,
+        handled_tx: mpsc::Sender<()>,
+        input_pending: Cell<bool>,
         input_handle: thread::JoinHandle<()>,
         tick_handle: thread::JoinHandle<()>,
     }
@@ -77,6 +703,7 @@ pub(crate) mod event {
 
         pub fn with_config(config: Config) -> Events {
             let (tx, rx) = mpsc::channel();
+            let (handled_tx, handled_rx) = mpsc::channel();
             let input_handle = {
                 let tx = tx.clone();
                 thread::spawn(move || {
@@ -87,28 +714,77 @@ pub(crate) mod event {
                                 eprintln!("{}", err);
                                 return;
                             }
+                            // Wait for the key to be handled before reading the next one
+                            if handled_rx.recv().is_err() {
+                                return;
+                            }
                         }
                     }
                 })
             };
             let tick_handle = {
-                thread::spawn(move || loop {
-                    if let Err(err) = tx.send(Event::Tick) {
-                        eprintln!("{}", err);
-                        break;
+                thread::spawn(move || {
+                    let mut size = termion::terminal_size().ok();
+                    loop {
+                        let event = if resized(&mut size, termion::terminal_size().ok()) {
+                            Event::Resize
+                        } else {
+                            Event::Tick
+                        };
+                        if let Err(err) = tx.send(event) {
+                            eprintln!("{}", err);
+                            break;
+                        }
+                        thread::sleep(config.tick_rate);
                     }
-                    thread::sleep(config.tick_rate);
                 })
             };
             Events {
                 rx,
+                handled_tx,
+                input_pending: Cell::new(false),
                 input_handle,
                 tick_handle,
             }
         }
 
+        /// Wait for the next event; asking for it means the previous key has been handled
         pub fn next(&self) -> Result<Event<Key>, mpsc::RecvError> {
-            self.rx.recv()
+            if self.input_pending.replace(false) {
+                // The input thread is gone if this fails, and `recv` reports it below
+                let _ = self.handled_tx.send(());
+            }
+            let event = self.rx.recv()?;
+            if let Event::Input(_) = event {
+                self.input_pending.set(true);
+            }
+            Ok(event)
+        }
+    }
+
+    /// Whether the terminal size changed from the last one seen, remembering the new size
+    fn resized(last: &mut Option<(u16, u16)>, now: Option<(u16, u16)>) -> bool {
+        if now.is_none() || *last == now {
+            return false;
+        }
+        *last = now;
+        true
+    }
+
+    #[cfg(test)]
+    mod resized_tests {
+        use super::*;
+
+        #[test]
+        fn only_changes_are_resizes() {
+            let mut size = Some((80, 24));
+            assert!(!resized(&mut size, Some((80, 24))));
+            assert!(resized(&mut size, Some((120, 40))));
+            assert_eq!(Some((120, 40)), size);
+            assert!(!resized(&mut size, Some((120, 40))));
+            // Failing to read the size isn't a resize
+            assert!(!resized(&mut size, None));
+            assert_eq!(Some((120, 40)), size);
         }
     }
 }
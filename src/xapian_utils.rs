@@ -1,4 +1,7 @@
-use crate::tika_document::TikaDocument;
+use crate::indexer::schema_version;
+use crate::tika_document::{content_id, TikaDocument};
+use crate::util::split_args;
+use chrono::{NaiveDate, TimeZone};
 use color_eyre::Report;
 use eyre::{eyre, Result};
 #[allow(unused)]
@@ -11,14 +14,58 @@ use nom::{
     sequence::{delimited, pair, separated_pair, tuple},
     {alt, branch::alt, complete, delimited, named, tag, take_until, value}, // {IResult},
 };
+use serde::Deserialize;
+use std::collections::HashMap;
 use std::convert::From;
+use std::ffi::OsString;
 use std::fmt;
+use std::path::{Path, PathBuf};
 use std::str;
 use xapian_rusty::FeatureFlag::{
     FlagBoolean, FlagBooleanAnyCase, FlagLovehate, FlagPartial, FlagPhrase, FlagPureNot,
     FlagSpellingCorrection, FlagWildcard,
 };
-use xapian_rusty::{Database, Query, QueryParser, Stem, XapianOp, DB_CREATE_OR_OVERWRITE};
+use xapian_rusty::{
+    get_xapian_err_type, Database, MSet, MSetIterator, Query, QueryParser, Stem, XError, XapianOp,
+    DB_CREATE_OR_OVERWRITE,
+};
+
+/// Name of the Xapian DB within the index directory; a vault's DB is named `mydb-<vault>`
+pub const DEFAULT_DB_NAME: &str = "mydb";
+
+/// Xapian's error code for a `DatabaseLockError`, another process holding the DB's write lock
+pub const DATABASE_LOCK_ERROR: i8 = -7;
+
+/// Xapian's error code for a `DatabaseOpeningError`, a DB that couldn't be opened
+pub const DATABASE_OPENING_ERROR: i8 = -11;
+
+/// Xapian's error code for a `QueryParserError`, a query string that couldn't be parsed
+pub const QUERY_PARSER_ERROR: i8 = -20;
+
+/// Xapian's error code for a `WildcardError`, a wildcard expanding to too many terms
+pub const WILDCARD_ERROR: i8 = -23;
+
+/// Xapian's error code for a `DatabaseNotFoundError`, a DB path with no DB at it
+pub const DATABASE_NOT_FOUND_ERROR: i8 = -24;
+
+/// Value slot holding each document's date as seconds since the epoch
+pub const DATE_SLOT: u32 = 0;
+
+/// Number of matches returned by a query unless asked otherwise
+// TODO set this based on terminal height?
+pub const DEFAULT_LIMIT: usize = 100;
+
+/// Number of titles suggested when a query matches nothing
+pub const SUGGESTIONS: usize = 3;
+
+/// Value slot holding 1 for draft documents and 0 otherwise
+pub const DRAFT_SLOT: u32 = 1;
+
+/// Value slot holding 1 for documents linking to local attachments and 0 otherwise
+pub const ATTACHMENTS_SLOT: u32 = 2;
+
+/// Prefix of the terms identifying each directory a document is in, see `dir_id`
+pub const DIR_PREFIX: &str = "XDIR";
 
 // The most helpful write-up on using Nom that I've seen so far:
 //   https://iximiuz.com/en/posts/rust-writing-parsers-with-nom/
@@ -310,7 +357,23 @@ mod quoted_tests {
 }
 
 fn tagged(input: Span) -> IResult<Span> {
-    recognize(tuple((word, tag(":"), alt((quoted, word)), multispace0)))(input)
+    recognize(tuple((
+        word,
+        tag(":"),
+        alt((quoted, tag_value)),
+        multispace0,
+    )))(input)
+}
+
+/// A word after a tag, which may also hold the `.` and `/` of a domain or path, e.g.
+/// `github.com` in `source_url:github.com`
+fn tag_value(input: Span) -> IResult<Span> {
+    recognize(many1(alt((
+        recognize(alphanumeric1),
+        recognize(tag("_")),
+        recognize(tag(".")),
+        recognize(tag("/")),
+    ))))(input)
 }
 
 #[cfg(test)]
@@ -364,20 +427,59 @@ pub enum XapianTag {
     Date,
     Filename,
     Fullpath,
+    Id,
+    SourceUrl,
+    Status,
     Title,
     Subtitle,
+    Summary,
     Tag,
 }
 
 impl XapianTag {
+    const ALL: [XapianTag; 11] = [
+        XapianTag::Author,
+        XapianTag::Date,
+        XapianTag::Filename,
+        XapianTag::Fullpath,
+        XapianTag::Id,
+        XapianTag::SourceUrl,
+        XapianTag::Status,
+        XapianTag::Title,
+        XapianTag::Subtitle,
+        XapianTag::Summary,
+        XapianTag::Tag,
+    ];
+
+    /// Name of the tag as written in queries
+    fn name(self) -> &'static str {
+        match self {
+            XapianTag::Author => "author",
+            XapianTag::Date => "date",
+            XapianTag::Filename => "filename",
+            XapianTag::Fullpath => "fullpath",
+            XapianTag::Id => "id",
+            XapianTag::SourceUrl => "source_url",
+            XapianTag::Status => "status",
+            XapianTag::Title => "title",
+            XapianTag::Subtitle => "subtitle",
+            XapianTag::Summary => "summary",
+            XapianTag::Tag => "tag",
+        }
+    }
+
     fn to_xapian<'a>(self) -> &'a str {
         match self {
             XapianTag::Author => "A",
             XapianTag::Date => "D",
             XapianTag::Filename => "F",
             XapianTag::Fullpath => "F",
+            XapianTag::Id => "XID",
+            XapianTag::SourceUrl => "XSOURCE",
+            XapianTag::Status => "XSTATUS",
             XapianTag::Title => "S",
             XapianTag::Subtitle => "XS",
+            XapianTag::Summary => "XSUMMARY",
             XapianTag::Tag => "K",
         }
     }
@@ -387,13 +489,17 @@ impl XapianTag {
                 value(XapianTag::Filename, tag_no_case("filename")),
                 value(XapianTag::Fullpath, tag_no_case("fullpath")),
                 value(XapianTag::Subtitle, tag_no_case("subtitle")),
+                value(XapianTag::Summary, tag_no_case("summary")),
+                value(XapianTag::Status, tag_no_case("status")),
+                value(XapianTag::SourceUrl, tag_no_case("source_url")),
                 value(XapianTag::Author, tag_no_case("author")),
                 value(XapianTag::Title, tag_no_case("title")),
+                value(XapianTag::Id, tag_no_case("id")),
                 value(XapianTag::Date, tag_no_case("date")),
                 value(XapianTag::Tag, tag_no_case("tag")),
             )),
             tag(":"),
-            alt((quoted, word)),
+            alt((quoted, tag_value)),
         )(input)
     }
 }
@@ -434,6 +540,15 @@ mod xapiantag_tests {
         assert_eq!(&"bar", value.fragment());
         assert_eq!(&" other", rest.fragment());
     }
+
+    #[test]
+    fn domain_tag() {
+        let (rest, (tag, value)) = XapianTag::parse(Span::new(r#"source_url:github.com/ssosik ;"#))
+            .expect("Failed to parse input");
+        assert_eq!("XSOURCE", tag.to_xapian());
+        assert_eq!(&"github.com/ssosik", value.fragment());
+        assert_eq!(&" ;", rest.fragment());
+    }
 }
 
 fn expression(input: Span) -> IResult<Vec<Span>> {
@@ -457,27 +572,36 @@ fn span_into_query(qp: &mut QueryParser, flags: i16, token: Span) -> Result<Quer
     }
 }
 
-fn expression_into_query(mut qp: QueryParser, flags: i16, qstr: &str) -> Result<Query, Report> {
-    // Parse the query string into a Vec of matches
-    let mut matches = match expression(Span::new(qstr)) {
-        Ok((_rest, matches)) => matches.into_iter(),
+/// Parse an expression without operators, OR-ing its tokens together. The last token is parsed
+/// with `last_flags` rather than `flags`, e.g. to treat it as a partially typed word.
+fn expression_into_query(
+    mut qp: QueryParser,
+    flags: i16,
+    last_flags: i16,
+    qstr: &str,
+) -> Result<Query, Report> {
+    // Parse the query string into a Vec of matches, skipping whitespace-only tokens
+    let matches: Vec<Span> = match expression(Span::new(qstr)) {
+        Ok((_rest, matches)) => matches
+            .into_iter()
+            .filter(|token| whitespace(*token).is_err())
+            .collect(),
         Err(_) => return Ok(qp.parse_query("", flags)?),
     };
 
-    let token = matches.next();
-    if token.is_none() {
-        return Err(eyre!("Empty expression"));
-    }
-
-    let mut query = span_into_query(&mut qp, flags, token.unwrap())?;
+    let last = match matches.len() {
+        0 => return Err(eyre!("Empty expression")),
+        n => n - 1,
+    };
+    let token_flags = |i| if i == last { last_flags } else { flags };
 
-    for token in matches {
-        // Skip whitespace-only tokens
-        if let Ok(_) = whitespace(token) {
-            continue;
-        }
+    let mut query = span_into_query(&mut qp, token_flags(0), matches[0])?;
 
-        query = query.add_right(XapianOp::OpOr, &mut span_into_query(&mut qp, flags, token)?)?;
+    for (i, token) in matches.into_iter().enumerate().skip(1) {
+        query = query.add_right(
+            XapianOp::OpOr,
+            &mut span_into_query(&mut qp, token_flags(i), token)?,
+        )?;
     }
 
     Ok(query)
@@ -502,7 +626,7 @@ mod expression_tests {
             | FlagSpellingCorrection as i16;
 
         let s = &r#"title:foo  baz bar author:bob hee tag:rust "hee hee hee" \n"#;
-        let mut query = expression_into_query(qp, flags, s).expect("Failed to parse");
+        let mut query = expression_into_query(qp, flags, flags, s).expect("Failed to parse");
         assert_eq!("Query((((((((WILDCARD SYNONYM Sfoo OR ZSfoo@1) OR (WILDCARD SYNONYM baz OR Zbaz@1)) OR (WILDCARD SYNONYM bar OR Zbar@1)) OR (WILDCARD SYNONYM Abob OR ZAbob@1)) OR (WILDCARD SYNONYM hee OR Zhee@1)) OR (WILDCARD SYNONYM Krust OR ZKrust@1)) OR (hee@1 PHRASE 3 hee@2 PHRASE 3 hee@3)))",
         query.get_description(),
         "Generated query didn't match expected for input string '{}'", s);
@@ -524,7 +648,7 @@ mod expression_tests {
             | FlagSpellingCorrection as i16;
 
         let s = &r#"title:"foo bar" author:bob tag:rust\n"#;
-        let mut query = expression_into_query(qp, flags, s).expect("Failed to parse");
+        let mut query = expression_into_query(qp, flags, flags, s).expect("Failed to parse");
         assert_eq!("Query((((Sfoo@1 PHRASE 2 Sbar@2) OR (WILDCARD SYNONYM Abob OR ZAbob@1)) OR (tag@1 PHRASE 2 rust@2)))",
         query.get_description(),
         "Generated query didn't match expected for input string '{}'", s);
@@ -546,7 +670,7 @@ mod expression_tests {
             | FlagSpellingCorrection as i16;
 
         let s = &r#"title:foo "baz bar" author:"bob alice" hee tag:rust "hee hee"\n"#;
-        let mut query = expression_into_query(qp, flags, s).expect("Failed to parse");
+        let mut query = expression_into_query(qp, flags, flags, s).expect("Failed to parse");
         assert_eq!("Query(((((((WILDCARD SYNONYM Sfoo OR ZSfoo@1) OR (baz@1 PHRASE 2 bar@2)) OR (Abob@1 PHRASE 2 Aalice@2)) OR (WILDCARD SYNONYM hee OR Zhee@1)) OR (WILDCARD SYNONYM Krust OR ZKrust@1)) OR (hee@1 PHRASE 2 hee@2)))",
         query.get_description(),
         "Generated query didn't match expected for input string '{}'", s);
@@ -580,7 +704,287 @@ mod query_tests {
     }
 }
 
-pub fn parse_user_query(mut qstr: &str) -> Result<Query, Report> {
+/// The plain search terms of a user query, lowercased, without field prefixes, quoting or
+/// boolean operators, e.g. for highlighting matches
+pub fn query_terms(qstr: &str) -> Vec<String> {
+    qstr.split(|c: char| c.is_whitespace() || c == '"' || c == '\'' || c == '(' || c == ')')
+        .map(|word| match word.find(':') {
+            Some(i) => &word[i + 1..],
+            None => word,
+        })
+        .map(|word| word.trim_matches(|c: char| !c.is_alphanumeric()))
+        .filter(|word| !word.is_empty())
+        .filter(|word| !is_operator(word))
+        .map(|word| word.to_lowercase())
+        .collect()
+}
+
+fn is_operator(word: &str) -> bool {
+    matches!(
+        word.to_uppercase().as_str(),
+        "AND"
+            | "OR"
+            | "NOT"
+            | "XOR"
+            | "MAYBE"
+            | "NEAR"
+            | "PHRASE"
+            | "SYNONYM"
+            | "FILTER"
+            | "SCALED"
+            | "ELITE"
+            | "RANGE"
+    )
+}
+
+/// A looser version of a user query, matching each word of four or more letters on its first
+/// half as a prefix so that e.g. `kuberentes` finds `kubernetes`. `None` when there are no such
+/// words to loosen.
+pub fn fuzzy_query_string(qstr: &str) -> Option<String> {
+    let mut loosened = false;
+    let words: Vec<String> = split_args(qstr)
+        .into_iter()
+        .map(|word| {
+            let (prefix, term) = word.split_at(word.find(':').map_or(0, |i| i + 1));
+            let len = term.chars().count();
+            if len < 4 || !term.chars().all(char::is_alphanumeric) || is_operator(term) {
+                return word;
+            }
+            loosened = true;
+            let kept: String = term.chars().take(((len + 1) / 2).max(3)).collect();
+            format!("{}{}*", prefix, kept)
+        })
+        .collect();
+    if loosened {
+        Some(words.join(" "))
+    } else {
+        None
+    }
+}
+
+/// Titles of up to `limit` documents matching a looser version of `qstr`, to suggest when it
+/// matches nothing
+pub fn suggestions(
+    db_paths: &[&str],
+    qstr: &str,
+    include_drafts: bool,
+    limit: usize,
+) -> Result<Vec<String>, Report> {
+    let fuzzy = match fuzzy_query_string(qstr) {
+        Some(fuzzy) => fuzzy,
+        None => return Ok(Vec::new()),
+    };
+    let mut query = parse_user_query(&fuzzy)?;
+    if !include_drafts {
+        query = exclude_drafts(query)?;
+    }
+    Ok(query_db(db_paths, query, limit)?
+        .into_iter()
+        .map(|doc| doc.title)
+        .collect())
+}
+
+/// The message to show instead of an empty result, when there are suggestions
+pub fn no_matches_message(suggestions: &[String]) -> Option<String> {
+    if suggestions.is_empty() {
+        return None;
+    }
+    Some(format!(
+        "No exact matches. Did you mean: {}?",
+        suggestions.join(", ")
+    ))
+}
+
+/// The search terms of a user query without a field prefix, which only match document bodies
+pub fn body_terms(qstr: &str) -> Vec<String> {
+    split_args(qstr)
+        .iter()
+        .filter(|word| {
+            let word = word.trim_start_matches('(');
+            !matches!(word.find(':'), Some(i) if !word[..i].contains('"'))
+        })
+        .flat_map(|word| query_terms(word))
+        .collect()
+}
+
+#[cfg(test)]
+mod query_terms_tests {
+    use super::*;
+    #[test]
+    fn prefixes_quotes_and_operators_are_dropped() {
+        assert_eq!(
+            vec!["foo", "bar", "baz", "bob", "rust"],
+            query_terms(r#"title:foo "bar baz" AND author:Bob tag:rust ;"#)
+        );
+    }
+
+    #[test]
+    fn fuzzy_query_loosens_long_words() {
+        assert_eq!(
+            Some(String::from("kuber* AND tag:rus* a:b \"exact phrase\" ;")),
+            fuzzy_query_string(r#"kuberentes AND tag:rust a:b "exact phrase" ;"#)
+        );
+        assert_eq!(None, fuzzy_query_string("a OR b ;"));
+    }
+
+    #[test]
+    fn body_terms_have_no_prefix() {
+        assert_eq!(
+            vec!["baz", "qux"],
+            body_terms(r#"title:"foo bar" baz AND (tag:rust qux) ;"#)
+        );
+    }
+}
+
+/// A problem found in a query string, along with the (1-based) column where it occurs
+#[derive(Debug, PartialEq)]
+pub struct QueryError {
+    pub column: usize,
+    pub message: &'static str,
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at column {}", self.message, self.column)
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+/// Xapian silently ignores unbalanced quotes and parentheses, so catch them up front to point
+/// the user at where the query went wrong. A `'` only opens a quote at the start of a word, so
+/// apostrophes as in `don't` or `O'Reilly` are left alone.
+fn check_balanced(qstr: &str) -> Result<(), QueryError> {
+    let mut quote: Option<(char, usize)> = None;
+    let mut parens = Vec::new();
+    let mut previous = None;
+    for (i, c) in qstr.chars().enumerate() {
+        let column = i + 1;
+        let starts_word =
+            previous.map_or(true, |p: char| p.is_whitespace() || p == '(' || p == ':');
+        previous = Some(c);
+        match (quote, c) {
+            (Some((q, _)), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"') => quote = Some((c, column)),
+            (None, '\'') if starts_word => quote = Some((c, column)),
+            (None, '(') => parens.push(column),
+            (None, ')') => {
+                if parens.pop().is_none() {
+                    return Err(QueryError {
+                        column,
+                        message: "Unbalanced parenthesis",
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+    if let Some((_, column)) = quote {
+        return Err(QueryError {
+            column,
+            message: "Unclosed quote",
+        });
+    }
+    if let Some(column) = parens.pop() {
+        return Err(QueryError {
+            column,
+            message: "Unbalanced parenthesis",
+        });
+    }
+    Ok(())
+}
+
+/// Map an error from parsing or running a query into a short message suitable for display
+pub fn friendly_error(e: &Report) -> String {
+    if let Some(XError::Xapian(code)) = e.downcast_ref::<XError>() {
+        return match *code {
+            QUERY_PARSER_ERROR => String::from("Invalid query syntax"),
+            WILDCARD_ERROR => String::from("Wildcard matches too many terms, try a longer prefix"),
+            DATABASE_OPENING_ERROR | DATABASE_NOT_FOUND_ERROR => {
+                String::from("Index not found, run `tika -i` to build it")
+            }
+            DATABASE_LOCK_ERROR => String::from("Index is locked by another process"),
+            _ => format!("Query failed: {}", get_xapian_err_type(*code)),
+        };
+    }
+    e.to_string()
+}
+
+#[cfg(test)]
+mod friendly_error_tests {
+    use super::*;
+    #[test]
+    fn xapian_query_parser_error() {
+        let e = Report::new(XError::Xapian(QUERY_PARSER_ERROR));
+        assert_eq!("Invalid query syntax", friendly_error(&e));
+    }
+
+    #[test]
+    fn xapian_wildcard_error() {
+        let e = Report::new(XError::Xapian(WILDCARD_ERROR));
+        assert_eq!(
+            "Wildcard matches too many terms, try a longer prefix",
+            friendly_error(&e)
+        );
+    }
+
+    #[test]
+    fn unclosed_paren() {
+        let e = Report::new(check_balanced("title:(unclosed").unwrap_err());
+        assert_eq!("Unbalanced parenthesis at column 7", friendly_error(&e));
+    }
+
+    #[test]
+    fn unclosed_quote() {
+        let e = Report::new(check_balanced(r#"foo "bar baz"#).unwrap_err());
+        assert_eq!("Unclosed quote at column 5", friendly_error(&e));
+    }
+
+    #[test]
+    fn stray_closing_paren() {
+        assert_eq!(
+            Err(QueryError {
+                column: 4,
+                message: "Unbalanced parenthesis"
+            }),
+            check_balanced("foo) bar")
+        );
+    }
+
+    #[test]
+    fn balanced() {
+        assert_eq!(Ok(()), check_balanced(r#"(foo OR "bar (baz") AND 'qux'"#));
+    }
+
+    #[test]
+    fn apostrophes_are_not_quotes() {
+        assert_eq!(Ok(()), check_balanced("O'Reilly books"));
+        assert!(parse_user_query("don't panic ;").is_ok());
+        assert_eq!(
+            Err(QueryError {
+                column: 7,
+                message: "Unclosed quote"
+            }),
+            check_balanced("title:'unclosed phrase")
+        );
+    }
+}
+
+/// Parse a user query, matching its words exactly (after stemming)
+pub fn parse_user_query(qstr: &str) -> Result<Query, Report> {
+    parse_query_with(qstr, false)
+}
+
+/// Parse a user query whose last word may be partially typed, so e.g. `kube` also matches
+/// `kubernetes`
+pub fn parse_prefix_query(qstr: &str) -> Result<Query, Report> {
+    parse_query_with(qstr, true)
+}
+
+fn parse_query_with(mut qstr: &str, prefix: bool) -> Result<Query, Report> {
+    check_balanced(qstr)?;
+
     let mut qp = QueryParser::new()?;
     let mut stem = Stem::new("en")?;
     qp.set_stemmer(&mut stem)?;
@@ -591,8 +995,12 @@ pub fn parse_user_query(mut qstr: &str) -> Result<Query, Report> {
         | FlagBooleanAnyCase as i16
         | FlagWildcard as i16
         | FlagPureNot as i16
-        | FlagPartial as i16
         | FlagSpellingCorrection as i16;
+    let last_flags = if prefix {
+        flags | FlagPartial as i16
+    } else {
+        flags
+    };
 
     let mut query;
     let mut operator;
@@ -605,7 +1013,7 @@ pub fn parse_user_query(mut qstr: &str) -> Result<Query, Report> {
         }
         Err(_) => {
             // No operator found in the initial string, return a query for the entire string
-            return expression_into_query(qp, flags, qstr);
+            return expression_into_query(qp, flags, last_flags, qstr);
         }
     }
 
@@ -635,7 +1043,7 @@ pub fn parse_user_query(mut qstr: &str) -> Result<Query, Report> {
                 // There are no more operators, parse the rest of the string into a query and break
                 query = query.add_right(
                     operator.into(),
-                    &mut expression_into_query(qp, flags, qstr)?,
+                    &mut expression_into_query(qp, flags, last_flags, qstr)?,
                 )?;
                 // No more operators found, break out of the loop
                 break;
@@ -691,30 +1099,1003 @@ named!(
 );
 
 //fn query_db(mut db: Database, mut q: Query) -> Result<Vec<TikaDocument>, Report> {
-pub fn query_db(mut q: Query) -> Result<Vec<TikaDocument>, Report> {
-    // TODO Reuse existing DB instead of creating a new one on each query
-    let mut db = Database::new_with_path("mydb", DB_CREATE_OR_OVERWRITE)?;
-    let mut enq = db.new_enquire()?;
-    enq.set_query(&mut q)?;
-    // TODO set this based on terminal height?
-    let mut mset = enq.get_mset(0, 100)?;
+/// Matches for `q`, best first, up to `limit` of them; a `limit` of 0 returns every match
+pub fn query_db(db_paths: &[&str], q: Query, limit: usize) -> Result<Vec<TikaDocument>, Report> {
+    query_open_db(&mut open_dbs(db_paths)?, q, limit)
+}
+
+/// Same as `query_db`, against a DB that is already open
+pub fn query_open_db(
+    db: &mut Database,
+    q: Query,
+    limit: usize,
+) -> Result<Vec<TikaDocument>, Report> {
+    let matches = match_open_db(db, q, mset_limit(limit))?;
 
     // TODO with verbose logging log this:
     //let appx_matches = mset.get_matches_estimated()?;
     //println!("Approximate Matches {}", appx_matches);
 
-    let mut matches = Vec::new();
-    let mut v = mset.iterator().unwrap();
-    while v.is_next().unwrap() {
-        let res = v.get_document_data();
-        // Can use flatten() or some other iterators/combinators?
-        if let Ok(data) = res {
-            let v: TikaDocument = serde_json::from_str(&data)?;
-            //println!("Match {}", v.filename);
-            matches.push(v);
+    let mut docs = Vec::new();
+    for data in matches {
+        docs.push(TikaDocument::from_stored(&data)?);
+    }
+
+    Ok(docs)
+}
+
+/// Full paths of the matches for `q`, as `query_db` would order and limit them, without
+/// reconstructing the rest of each document. Paths are read lazily, as they're iterated.
+pub fn query_paths(
+    db_paths: &[&str],
+    q: Query,
+    limit: usize,
+) -> Result<impl Iterator<Item = OsString>, Report> {
+    /// Just the path out of a document's stored JSON data
+    #[derive(Deserialize)]
+    struct StoredPath {
+        #[serde(default)]
+        full_path: OsString,
+    }
+
+    Ok(
+        match_data(db_paths, q, mset_limit(limit))?.filter_map(|data| {
+            serde_json::from_str::<StoredPath>(&data)
+                .ok()
+                .map(|stored| stored.full_path)
+        }),
+    )
+}
+
+/// Size of the MSet to ask for to get `limit` matches, where a `limit` of 0 means every match
+fn mset_limit(limit: usize) -> i32 {
+    if limit == 0 {
+        i32::MAX
+    } else {
+        limit.min(i32::MAX as usize) as i32
+    }
+}
+
+/// Restrict `q` to documents that aren't drafts
+pub fn exclude_drafts(mut q: Query) -> Result<Query, Report> {
+    let mut drafts = Query::new_range(XapianOp::OpValueRange, DRAFT_SLOT, 1.0, 1.0)?;
+    Ok(q.add_right(XapianOp::OpAndNot, &mut drafts)?)
+}
+
+/// Restrict `q` to documents that link to local attachments
+pub fn with_attachments(mut q: Query) -> Result<Query, Report> {
+    let mut attached = Query::new_range(XapianOp::OpValueRange, ATTACHMENTS_SLOT, 1.0, 1.0)?;
+    Ok(q.add_right(XapianOp::OpFilter, &mut attached)?)
+}
+
+/// Restrict `q` to documents with any of the given `statuses`
+pub fn with_status(mut q: Query, statuses: &[&str]) -> Result<Query, Report> {
+    let mut qp = QueryParser::new()?;
+    let mut stem = Stem::new("en")?;
+    qp.set_stemmer(&mut stem)?;
+    let (first, rest) = statuses
+        .split_first()
+        .ok_or_else(|| eyre!("No status to match"))?;
+    let mut any = qp.parse_query_with_prefix(first, 0, XapianTag::Status.to_xapian())?;
+    for status in rest {
+        any = any.add_right(
+            XapianOp::OpOr,
+            &mut qp.parse_query_with_prefix(status, 0, XapianTag::Status.to_xapian())?,
+        )?;
+    }
+    Ok(q.add_right(XapianOp::OpFilter, &mut any)?)
+}
+
+/// Match any one of `tags`, or every one of them when `all` is set
+fn tags_query(tags: &[&str], all: bool) -> Result<Query, Report> {
+    let mut qp = QueryParser::new()?;
+    let mut stem = Stem::new("en")?;
+    qp.set_stemmer(&mut stem)?;
+    let (first, rest) = tags.split_first().ok_or_else(|| eyre!("No tag to match"))?;
+    let op = if all { XapianOp::OpAnd } else { XapianOp::OpOr };
+    let mut tagged = qp.parse_query_with_prefix(first, 0, XapianTag::Tag.to_xapian())?;
+    for tag in rest {
+        tagged = tagged.add_right(
+            op,
+            &mut qp.parse_query_with_prefix(tag, 0, XapianTag::Tag.to_xapian())?,
+        )?;
+    }
+    Ok(tagged)
+}
+
+/// Restrict `q` to documents tagged with any of `tags`, or with all of them when `all` is set
+pub fn with_tags(mut q: Query, tags: &[&str], all: bool) -> Result<Query, Report> {
+    Ok(q.add_right(XapianOp::OpFilter, &mut tags_query(tags, all)?)?)
+}
+
+/// Leave out of `q` the documents tagged with any of `tags`
+pub fn without_tags(mut q: Query, tags: &[&str]) -> Result<Query, Report> {
+    Ok(q.add_right(XapianOp::OpAndNot, &mut tags_query(tags, false)?)?)
+}
+
+/// Xapian's description of the parsed query `q`, followed by a line naming the field behind
+/// each term prefix in it, e.g. `S = title`, for `query --explain-query`
+pub fn explain_query(q: &mut Query) -> String {
+    let description = q.get_description();
+    let mut fields: Vec<(&str, Vec<&str>)> = Vec::new();
+    for token in description.split(|c: char| !c.is_alphanumeric()) {
+        // Stemmed terms carry an extra `Z` in front of their prefix
+        let term = token.strip_prefix('Z').unwrap_or(token);
+        // Prefixes are upper case and terms aren't, which tells e.g. `XS` and `XSTATUS` apart
+        let prefixed = XapianTag::ALL.iter().filter(|tag| {
+            term.strip_prefix(tag.to_xapian())
+                .and_then(|rest| rest.chars().next())
+                .map_or(false, |c| !c.is_ascii_uppercase())
+        });
+        for tag in prefixed {
+            match fields
+                .iter_mut()
+                .find(|(prefix, _)| *prefix == tag.to_xapian())
+            {
+                Some((_, names)) if !names.contains(&tag.name()) => names.push(tag.name()),
+                Some(_) => {}
+                None => fields.push((tag.to_xapian(), vec![tag.name()])),
+            }
         }
-        v.next()?;
     }
 
-    Ok(matches)
+    let mut out = description;
+    for (prefix, names) in fields {
+        out.push_str(&format!("\n{} = {}", prefix, names.join(" or ")));
+    }
+    out
+}
+
+/// Word identifying the directory `dir`, indexed with `DIR_PREFIX` for each directory a
+/// document is in. Paths are tokenized when indexed, so they're hashed into a single word.
+pub fn dir_id(dir: &Path) -> String {
+    // Drops any trailing separator or `.` components
+    let dir: PathBuf = dir.components().collect();
+    content_id(&dir.to_string_lossy())
+}
+
+/// Restrict `q` to documents somewhere under any of the directories `dirs`
+pub fn under(mut q: Query, dirs: &[PathBuf]) -> Result<Query, Report> {
+    let mut qp = QueryParser::new()?;
+    let mut stem = Stem::new("en")?;
+    qp.set_stemmer(&mut stem)?;
+    let (first, rest) = dirs
+        .split_first()
+        .ok_or_else(|| eyre!("No directory to look under"))?;
+    let mut any = qp.parse_query_with_prefix(&dir_id(first), 0, DIR_PREFIX)?;
+    for dir in rest {
+        any = any.add_right(
+            XapianOp::OpOr,
+            &mut qp.parse_query_with_prefix(&dir_id(dir), 0, DIR_PREFIX)?,
+        )?;
+    }
+    Ok(q.add_right(XapianOp::OpFilter, &mut any)?)
+}
+
+/// Match the documents whose full path contains the file name of any of `paths`, e.g. to find
+/// the documents read from files that have since been deleted. Paths are tokenized when
+/// indexed, so other files with the same words in their names may match too.
+pub fn with_file_names(paths: &[PathBuf]) -> Result<Query, Report> {
+    let mut qp = QueryParser::new()?;
+    let names: Vec<String> = paths
+        .iter()
+        .filter_map(|path| path.file_name())
+        .map(|name| format!("\"{}\"", name.to_string_lossy().replace('"', " ")))
+        .collect();
+    let (first, rest) = names
+        .split_first()
+        .ok_or_else(|| eyre!("No file name to match"))?;
+    let prefix = XapianTag::Fullpath.to_xapian();
+    let mut any = qp.parse_query_with_prefix(first, FlagPhrase as i16, prefix)?;
+    for name in rest {
+        any = any.add_right(
+            XapianOp::OpOr,
+            &mut qp.parse_query_with_prefix(name, FlagPhrase as i16, prefix)?,
+        )?;
+    }
+    Ok(any)
+}
+
+/// Restrict `q` to documents dated on `day`, from its first to its last second in `tz`
+pub fn on_day<Tz: TimeZone>(mut q: Query, day: NaiveDate, tz: &Tz) -> Result<Query, Report> {
+    let mut on = Query::new_range(
+        XapianOp::OpValueRange,
+        DATE_SLOT,
+        start_of_day(day, tz)? as f64,
+        (start_of_day(day.succ(), tz)? - 1) as f64,
+    )?;
+    Ok(q.add_right(XapianOp::OpFilter, &mut on)?)
+}
+
+/// Restrict `q` to documents dated on or after the day `after` and before the day `before` in
+/// `tz`, either of which may be left open
+pub fn in_date_range<Tz: TimeZone>(
+    mut q: Query,
+    after: Option<NaiveDate>,
+    before: Option<NaiveDate>,
+    tz: &Tz,
+) -> Result<Query, Report> {
+    let from = match after {
+        Some(day) => start_of_day(day, tz)? as f64,
+        None => f64::MIN,
+    };
+    let to = match before {
+        Some(day) => (start_of_day(day, tz)? - 1) as f64,
+        None => f64::MAX,
+    };
+    let mut range = Query::new_range(XapianOp::OpValueRange, DATE_SLOT, from, to)?;
+    Ok(q.add_right(XapianOp::OpFilter, &mut range)?)
+}
+
+/// Seconds since the epoch of the first moment of `day` in `tz`
+fn start_of_day<Tz: TimeZone>(day: NaiveDate, tz: &Tz) -> Result<i64, Report> {
+    tz.from_local_datetime(&day.and_hms(0, 0, 0))
+        .earliest()
+        .map(|t| t.timestamp())
+        .ok_or_else(|| eyre!("{} has no midnight in this timezone", day))
+}
+
+/// Filters written into a query string, which narrow the whole query rather than match terms
+#[derive(Debug, Default, PartialEq)]
+pub struct QueryFilters {
+    /// Fields the matches must have a value for, from `has:<field>`
+    pub has: Vec<String>,
+    /// Day the matches are dated on or after, from `after:YYYY-MM-DD`
+    pub after: Option<NaiveDate>,
+    /// Day the matches are dated before, from `before:YYYY-MM-DD`
+    pub before: Option<NaiveDate>,
+}
+
+/// Split the `has:`, `after:` and `before:` filters out of a user query, returning them along
+/// with the rest of the query. An `AND` left dangling by taking a filter out is dropped too, so
+/// e.g. `tag:work AND has:summary` leaves `tag:work`.
+pub fn extract_filters(qstr: &str) -> Result<(QueryFilters, String), Report> {
+    let parse_day = |filter: &str, day: &str| {
+        NaiveDate::parse_from_str(day, "%Y-%m-%d")
+            .map_err(|e| eyre!("Invalid {}: date '{}': {}", filter, day, e))
+    };
+    let mut filters = QueryFilters::default();
+    let mut rest = Vec::new();
+    for word in qstr.split_whitespace() {
+        match word.split_once(':') {
+            Some(("has", field)) if !field.is_empty() => filters.has.push(String::from(field)),
+            Some(("after", day)) => filters.after = Some(parse_day("after", day)?),
+            Some(("before", day)) => filters.before = Some(parse_day("before", day)?),
+            _ => rest.push(word),
+        }
+    }
+    if filters == QueryFilters::default() {
+        return Ok((filters, String::from(qstr)));
+    }
+
+    let is_and = |word: &&str| word.eq_ignore_ascii_case("and");
+    let mut kept: Vec<&str> = Vec::new();
+    for word in rest {
+        if is_and(&word) && kept.last().map_or(true, is_and) {
+            continue;
+        }
+        kept.push(word);
+    }
+    if kept.last().map_or(false, is_and) {
+        kept.pop();
+    }
+    Ok((filters, kept.join(" ")))
+}
+
+/// Query matching every document in the DB: every indexed document carries a date value, as
+/// long as the DB was written at the current `SCHEMA_VERSION` (see `require_schema`)
+pub fn match_all() -> Result<Query, Report> {
+    Ok(Query::new_range(
+        XapianOp::OpValueRange,
+        DATE_SLOT,
+        f64::MIN,
+        f64::MAX,
+    )?)
+}
+
+/// Lazily yields the stored JSON data of each document in an MSet, so callers can process
+/// arbitrarily large result sets without holding all of them in memory
+pub struct MatchData {
+    mset: MSet,
+    index: i32,
+}
+
+impl Iterator for MatchData {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        let mut v = MSetIterator {
+            mset: &mut self.mset,
+            index: self.index,
+        };
+        while v.is_next().ok()? {
+            let res = v.get_document_data();
+            v.next().ok()?;
+            self.index = v.index;
+            if let Ok(data) = res {
+                return Some(data);
+            }
+        }
+        None
+    }
+}
+
+/// Open the DBs at each of the given paths as a single DB, so results across all of them are
+/// ranked together
+pub fn open_dbs(db_paths: &[&str]) -> Result<Database, Report> {
+    let (first, rest) = db_paths
+        .split_first()
+        .ok_or_else(|| eyre!("No DB to open"))?;
+    let mut db = Database::new_with_path(first, DB_CREATE_OR_OVERWRITE)?;
+    for path in rest {
+        db.add_database(&mut Database::new_with_path(path, DB_CREATE_OR_OVERWRITE)?)?;
+    }
+    Ok(db)
+}
+
+/// Stored JSON data for the top `limit` matches of `q` across the DBs, in rank order
+pub fn match_data(db_paths: &[&str], q: Query, limit: i32) -> Result<MatchData, Report> {
+    // TODO Reuse existing DB instead of creating a new one on each query
+    match_open_db(&mut open_dbs(db_paths)?, q, limit)
+}
+
+fn match_open_db(db: &mut Database, mut q: Query, limit: i32) -> Result<MatchData, Report> {
+    let mut enq = db.new_enquire()?;
+    enq.set_query(&mut q)?;
+    let mset = enq.get_mset(0, limit)?;
+
+    Ok(MatchData { mset, index: 0 })
+}
+
+/// Fail with a hint to rebuild when any of the DBs predates the schema versions: those DBs
+/// carry no date values, so `match_all` would silently find nothing in them
+fn require_schema(db_paths: &[&str]) -> Result<(), Report> {
+    for db_path in db_paths {
+        if Path::new(db_path).exists() && schema_version(db_path).is_none() {
+            return Err(eyre!(
+                "Index {} was written by an older tika, rebuild it with `tika -i`",
+                db_path
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Stored JSON data for all documents in the DBs
+pub fn all_document_data(db_paths: &[&str]) -> Result<MatchData, Report> {
+    require_schema(db_paths)?;
+    match_data(db_paths, match_all()?, i32::MAX)
+}
+
+/// Every document in the DBs, reconstructed from its stored data
+pub fn documents(db_paths: &[&str]) -> Result<impl Iterator<Item = TikaDocument>, Report> {
+    Ok(all_document_data(db_paths)?.filter_map(|data| TikaDocument::from_stored(&data).ok()))
+}
+
+/// The document stored under each of `filenames`, in the same order, or `None` for a filename
+/// with no document; the DBs are read once however many filenames there are
+pub fn documents_by_filename(
+    db_paths: &[&str],
+    filenames: &[String],
+) -> Result<Vec<Option<TikaDocument>>, Report> {
+    let found: HashMap<String, TikaDocument> = documents(db_paths)?
+        .filter(|doc| filenames.contains(&doc.filename))
+        .map(|doc| (doc.filename.clone(), doc))
+        .collect();
+    Ok(filenames
+        .iter()
+        .map(|filename| found.get(filename).cloned())
+        .collect())
+}
+
+#[cfg(test)]
+mod documents_tests {
+    use super::*;
+    use crate::indexer::{index_paths, IndexOptions};
+    use std::fs;
+
+    fn index_vault(dir: &std::path::Path, vault: &str, titles: &[&str]) -> String {
+        let db_path = dir.join(vault);
+        let paths: Vec<_> = titles
+            .iter()
+            .map(|title| {
+                let path = dir.join(format!("{}.md", title));
+                fs::write(
+                    &path,
+                    format!(
+                        "---\ndate: 2021-06-22T12:48:16-0400\ntags: test\ntitle: {}\n---\nA note\n",
+                        title
+                    ),
+                )
+                .unwrap();
+                path
+            })
+            .collect();
+        let opts = IndexOptions {
+            vault: String::from(vault),
+            ..Default::default()
+        };
+        index_paths(db_path.to_str().unwrap(), paths, &opts).expect("Failed to index");
+        db_path.to_str().unwrap().to_owned()
+    }
+
+    #[test]
+    fn union_across_vaults_is_labeled() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let work = index_vault(dir.path(), "work", &["standup"]);
+        let personal = index_vault(dir.path(), "personal", &["groceries"]);
+
+        let query = parse_user_query("note ;").expect("Failed to parse");
+        let mut labels: Vec<(String, String)> = query_db(&[&work, &personal], query, DEFAULT_LIMIT)
+            .expect("Failed to query")
+            .into_iter()
+            .map(|doc| (doc.vault, doc.title))
+            .collect();
+        labels.sort();
+        assert_eq!(
+            vec![
+                (String::from("personal"), String::from("groceries")),
+                (String::from("work"), String::from("standup"))
+            ],
+            labels
+        );
+    }
+
+    #[test]
+    fn yields_every_indexed_document() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let db_path = dir.path().join("db");
+        let db_path = db_path.to_str().unwrap();
+
+        let paths: Vec<_> = ["one", "two", "three"]
+            .iter()
+            .map(|name| {
+                let path = dir.path().join(format!("{}.md", name));
+                fs::write(
+                    &path,
+                    format!(
+                        "---\ndate: 2021-06-22T12:48:16-0400\ntags: test\ntitle: {}\n---\nBody\n",
+                        name
+                    ),
+                )
+                .unwrap();
+                path
+            })
+            .collect();
+        index_paths(db_path, paths, &Default::default()).expect("Failed to index");
+
+        let mut titles: Vec<String> = documents(&[db_path])
+            .expect("Failed to read documents")
+            .map(|doc| doc.title)
+            .collect();
+        titles.sort();
+        assert_eq!(vec!["one", "three", "two"], titles);
+    }
+
+    #[test]
+    fn unversioned_index_asks_for_a_rebuild() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let db_path = index_vault(dir.path(), "work", &["standup"]);
+        fs::remove_file(Path::new(&db_path).join("tika-schema")).unwrap();
+
+        let err = documents(&[&db_path])
+            .err()
+            .expect("Read an unversioned index");
+        assert!(err.to_string().contains("rebuild it with `tika -i`"));
+    }
+}
+
+#[cfg(test)]
+mod documents_by_filename_tests {
+    use super::*;
+    use crate::fixtures::FixtureVault;
+
+    #[test]
+    fn in_the_order_asked_with_misses() {
+        let mut vault = FixtureVault::new();
+        vault.note("a.md", &[], "First");
+        vault.note("b.md", &[], "Second");
+        vault.index();
+
+        let filenames: Vec<String> = ["b.md", "missing.md", "a.md"]
+            .iter()
+            .map(|f| String::from(*f))
+            .collect();
+        let docs = documents_by_filename(&[&vault.db_path()], &filenames).unwrap();
+        let titles: Vec<Option<String>> = docs
+            .into_iter()
+            .map(|doc| doc.map(|doc| doc.title))
+            .collect();
+        assert_eq!(
+            vec![Some(String::from("b")), None, Some(String::from("a"))],
+            titles
+        );
+    }
+}
+
+#[cfg(test)]
+mod exclude_drafts_tests {
+    use super::*;
+    use crate::indexer::index_paths;
+    use std::fs;
+
+    fn index_notes(dir: &std::path::Path) -> String {
+        let db_path = dir.join("db");
+        let mut paths = Vec::new();
+        for (title, draft) in &[("published", false), ("unfinished", true)] {
+            let path = dir.join(format!("{}.md", title));
+            fs::write(
+                &path,
+                format!(
+                    "---\ndate: 2021-06-22T12:48:16-0400\ntags: test\ntitle: {}\ndraft: {}\n---\nA note\n",
+                    title, draft
+                ),
+            )
+            .unwrap();
+            paths.push(path);
+        }
+        index_paths(db_path.to_str().unwrap(), paths, &Default::default())
+            .expect("Failed to index");
+        db_path.to_str().unwrap().to_owned()
+    }
+
+    fn titles(db_path: &str, include_drafts: bool) -> Vec<String> {
+        let mut query = parse_user_query("note ;").expect("Failed to parse");
+        if !include_drafts {
+            query = exclude_drafts(query).expect("Failed to exclude drafts");
+        }
+        let mut titles: Vec<String> = query_db(&[db_path], query, DEFAULT_LIMIT)
+            .expect("Failed to query")
+            .into_iter()
+            .map(|doc| doc.title)
+            .collect();
+        titles.sort();
+        titles
+    }
+
+    #[test]
+    fn drafts_hidden_by_default() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let db_path = index_notes(dir.path());
+        assert_eq!(vec!["published"], titles(&db_path, false));
+    }
+
+    #[test]
+    fn drafts_revealed_when_included() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let db_path = index_notes(dir.path());
+        assert_eq!(vec!["published", "unfinished"], titles(&db_path, true));
+    }
+}
+
+#[cfg(test)]
+mod query_db_tests {
+    use super::*;
+    use crate::indexer::index_paths;
+    use std::fs;
+
+    #[test]
+    fn zero_limit_returns_every_match() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let db_path = dir.path().join("db");
+        let db_path = db_path.to_str().unwrap();
+        let paths: Vec<_> = (0..150)
+            .map(|i| {
+                let path = dir.path().join(format!("note{}.md", i));
+                fs::write(
+                    &path,
+                    format!(
+                        "---\ndate: 2021-06-22T12:48:16-0400\ntags: test\ntitle: Note {}\n---\nA note\n",
+                        i
+                    ),
+                )
+                .unwrap();
+                path
+            })
+            .collect();
+        index_paths(db_path, paths, &Default::default()).expect("Failed to index");
+
+        let query = || parse_user_query("note ;").expect("Failed to parse");
+        assert_eq!(
+            DEFAULT_LIMIT,
+            query_db(&[db_path], query(), DEFAULT_LIMIT).unwrap().len()
+        );
+        assert_eq!(150, query_db(&[db_path], query(), 0).unwrap().len());
+    }
+}
+
+#[cfg(test)]
+mod with_attachments_tests {
+    use super::*;
+    use crate::indexer::index_paths;
+    use std::fs;
+
+    #[test]
+    fn filter_isolates_notes_with_attachments() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let db_path = dir.path().join("db");
+        let db_path = db_path.to_str().unwrap();
+        let mut paths = Vec::new();
+        for (title, body) in &[
+            ("attached", "A note with ![a photo](photo.jpg)"),
+            ("plain", "A note with [a link](https://example.com)"),
+        ] {
+            let path = dir.path().join(format!("{}.md", title));
+            fs::write(
+                &path,
+                format!(
+                    "---\ndate: 2021-06-22T12:48:16-0400\ntags: test\ntitle: {}\n---\n{}\n",
+                    title, body
+                ),
+            )
+            .unwrap();
+            paths.push(path);
+        }
+        index_paths(db_path, paths, &Default::default()).expect("Failed to index");
+
+        let query = with_attachments(parse_user_query("note ;").unwrap()).unwrap();
+        let titles: Vec<String> = query_db(&[db_path], query, DEFAULT_LIMIT)
+            .expect("Failed to query")
+            .into_iter()
+            .map(|doc| doc.title)
+            .collect();
+        assert_eq!(vec!["attached"], titles);
+    }
+}
+
+#[cfg(test)]
+mod on_day_tests {
+    use super::*;
+    use crate::indexer::index_paths;
+    use chrono::FixedOffset;
+    use std::fs;
+
+    #[test]
+    fn only_the_given_day_matches() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let db_path = dir.path().join("db");
+        let db_path = db_path.to_str().unwrap();
+        let mut paths = Vec::new();
+        for (title, date) in &[
+            ("before", "2021-06-21T23:59:59-0400"),
+            ("morning", "2021-06-22T00:00:00-0400"),
+            ("evening", "2021-06-22T23:59:59-0400"),
+            ("after", "2021-06-23T00:00:00-0400"),
+        ] {
+            let path = dir.path().join(format!("{}.md", title));
+            fs::write(
+                &path,
+                format!(
+                    "---\ndate: {}\ntags: test\ntitle: {}\n---\nA day\n",
+                    date, title
+                ),
+            )
+            .unwrap();
+            paths.push(path);
+        }
+        index_paths(db_path, paths, &Default::default()).expect("Failed to index");
+
+        let day = NaiveDate::from_ymd(2021, 6, 22);
+        let query = on_day(match_all().unwrap(), day, &FixedOffset::west(4 * 3600)).unwrap();
+        let mut titles: Vec<String> = query_db(&[db_path], query, DEFAULT_LIMIT)
+            .expect("Failed to query")
+            .into_iter()
+            .map(|doc| doc.title)
+            .collect();
+        titles.sort();
+        assert_eq!(vec!["evening", "morning"], titles);
+    }
+}
+
+#[cfg(test)]
+mod extract_filters_tests {
+    use super::*;
+    use crate::indexer::index_paths;
+    use crate::tika_document::filter_fields;
+    use std::fs;
+
+    #[test]
+    fn filters_are_taken_out_of_the_query() {
+        let (filters, rest) =
+            extract_filters("tag:work AND has:summary AND after:2021-01-01 AND rust").unwrap();
+        assert_eq!(
+            QueryFilters {
+                has: vec![String::from("summary")],
+                after: Some(NaiveDate::from_ymd(2021, 1, 1)),
+                before: None,
+            },
+            filters
+        );
+        assert_eq!("tag:work AND rust", rest);
+
+        let (filters, rest) = extract_filters("has:author before:2021-06-22").unwrap();
+        assert_eq!(Some(NaiveDate::from_ymd(2021, 6, 22)), filters.before);
+        assert_eq!("", rest);
+    }
+
+    #[test]
+    fn query_without_filters_is_untouched() {
+        let (filters, rest) = extract_filters("title:foo  AND status:done").unwrap();
+        assert_eq!(QueryFilters::default(), filters);
+        assert_eq!("title:foo  AND status:done", rest);
+    }
+
+    #[test]
+    fn invalid_date_is_an_error() {
+        assert!(extract_filters("after:yesterday").is_err());
+    }
+
+    #[test]
+    fn has_filter_with_free_text() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let db_path = dir.path().join("db");
+        let db_path = db_path.to_str().unwrap();
+        let mut paths = Vec::new();
+        for (i, (title, date, body)) in [
+            (
+                "Summarized rust",
+                "2021-06-22T12:48:16-0400",
+                "Notes on borrowing",
+            ),
+            ("Empty rust", "2021-06-22T12:48:16-0400", ""),
+            ("Old rust", "2020-06-22T12:48:16-0400", "Notes on borrowing"),
+            (
+                "Unrelated",
+                "2021-06-22T12:48:16-0400",
+                "Notes on goroutines",
+            ),
+        ]
+        .iter()
+        .enumerate()
+        {
+            let path = dir.path().join(format!("{}.md", i));
+            fs::write(
+                &path,
+                format!(
+                    "---\ndate: {}\ntags: test\ntitle: {}\n---\n{}\n",
+                    date, title, body
+                ),
+            )
+            .unwrap();
+            paths.push(path);
+        }
+        index_paths(db_path, paths, &Default::default()).expect("Failed to index");
+
+        let (filters, rest) = extract_filters("rust AND has:summary AND after:2021-01-01").unwrap();
+        let query = parse_user_query(&format!("{} ;", rest)).expect("Failed to parse");
+        let query = in_date_range(query, filters.after, filters.before, &chrono::Utc).unwrap();
+        let has: Vec<&str> = filters.has.iter().map(String::as_str).collect();
+        let titles: Vec<String> = filter_fields(
+            query_db(&[db_path], query, DEFAULT_LIMIT).expect("Failed to query"),
+            &has,
+            &[],
+        )
+        .unwrap()
+        .into_iter()
+        .map(|doc| doc.title)
+        .collect();
+        assert_eq!(vec!["Summarized rust"], titles);
+    }
+}
+
+#[cfg(test)]
+mod explain_query_tests {
+    use super::*;
+
+    #[test]
+    fn names_the_targeted_fields() {
+        let mut query = parse_user_query("title:foo status:done bar ;").expect("Failed to parse");
+        let explained = explain_query(&mut query);
+        let mut lines = explained.lines();
+        assert!(lines.next().unwrap().contains("Sfoo"), "{}", explained);
+        assert_eq!(
+            vec!["S = title", "XSTATUS = status"],
+            lines.collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn shared_prefixes_name_every_field() {
+        let mut query = parse_user_query("filename:notes ;").expect("Failed to parse");
+        assert!(explain_query(&mut query).ends_with("\nF = filename or fullpath"));
+    }
+}
+
+#[cfg(test)]
+mod under_tests {
+    use super::*;
+    use crate::indexer::index_paths;
+    use std::fs;
+
+    #[test]
+    fn only_notes_under_the_directory_match() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let db_path = dir.path().join("db");
+        let db_path = db_path.to_str().unwrap();
+        let mut paths = Vec::new();
+        for (i, subdir) in ["work", "work/deep", "home", "workshop"].iter().enumerate() {
+            fs::create_dir_all(dir.path().join(subdir)).unwrap();
+            let path = dir.path().join(subdir).join(format!("{}.md", i));
+            fs::write(
+                &path,
+                format!(
+                    "---\ndate: 2021-06-22T12:48:16-0400\ntags: test\ntitle: {}\n---\nStandup\n",
+                    subdir
+                ),
+            )
+            .unwrap();
+            paths.push(path);
+        }
+        index_paths(db_path, paths, &Default::default()).expect("Failed to index");
+
+        let titles = |dirs: &[PathBuf]| {
+            let query = parse_user_query("standup ;").unwrap();
+            let mut titles: Vec<String> =
+                query_db(&[db_path], under(query, dirs).unwrap(), DEFAULT_LIMIT)
+                    .expect("Failed to query")
+                    .into_iter()
+                    .map(|doc| doc.title)
+                    .collect();
+            titles.sort();
+            titles
+        };
+        assert_eq!(
+            vec!["work", "work/deep"],
+            titles(&[dir.path().join("work/")])
+        );
+        assert_eq!(vec!["home"], titles(&[dir.path().join("home")]));
+        assert_eq!(
+            vec!["home", "work/deep"],
+            titles(&[dir.path().join("home"), dir.path().join("work/./deep")])
+        );
+    }
+}
+
+#[cfg(test)]
+mod with_status_tests {
+    use super::*;
+    use crate::indexer::index_paths;
+    use std::fs;
+
+    #[test]
+    fn only_the_given_statuses_match() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let db_path = dir.path().join("db");
+        let db_path = db_path.to_str().unwrap();
+        let mut paths = Vec::new();
+        for (title, status) in &[
+            ("planned", "todo"),
+            ("started", "doing"),
+            ("finished", "done"),
+        ] {
+            let path = dir.path().join(format!("{}.md", title));
+            fs::write(
+                &path,
+                format!(
+                    "---\ndate: 2021-06-22T12:48:16-0400\ntags: test\ntitle: {}\nstatus: {}\n---\nA task\n",
+                    title, status
+                ),
+            )
+            .unwrap();
+            paths.push(path);
+        }
+        index_paths(db_path, paths, &Default::default()).expect("Failed to index");
+
+        let titles = |statuses: &[&str]| {
+            let query = with_status(match_all().unwrap(), statuses).unwrap();
+            let mut titles: Vec<String> = query_db(&[db_path], query, DEFAULT_LIMIT)
+                .expect("Failed to query")
+                .into_iter()
+                .map(|doc| doc.title)
+                .collect();
+            titles.sort();
+            titles
+        };
+        assert_eq!(vec!["finished"], titles(&["done"]));
+        assert_eq!(vec!["planned", "started"], titles(&["todo", "doing"]));
+    }
+}
+
+#[cfg(test)]
+mod with_tags_tests {
+    use super::*;
+    use crate::fixtures::{titles, FixtureVault};
+
+    fn overlapping_tags() -> FixtureVault {
+        let mut vault = FixtureVault::new();
+        vault.note("ferris.md", &[("tags", "rust")], "Crabs");
+        vault.note("gopher.md", &[("tags", "go")], "Gophers");
+        vault.note("both.md", &[("tags", "[rust, go]")], "Ports");
+        vault.note("neither.md", &[("tags", "python")], "Snakes");
+        vault.index();
+        vault
+    }
+
+    #[test]
+    fn any_tag_matches_by_default() {
+        let vault = overlapping_tags();
+        let query = with_tags(match_all().unwrap(), &["rust", "go"], false).unwrap();
+        assert_eq!(vec!["both", "ferris", "gopher"], titles(vault.run(query)));
+    }
+
+    #[test]
+    fn all_tags_must_match() {
+        let vault = overlapping_tags();
+        let query = with_tags(match_all().unwrap(), &["rust", "go"], true).unwrap();
+        assert_eq!(vec!["both"], titles(vault.run(query)));
+    }
+
+    #[test]
+    fn composes_with_text_and_exclusions() {
+        let vault = overlapping_tags();
+        let query = parse_user_query("crabs OR ports ;").unwrap();
+        let query = with_tags(query, &["rust"], false).unwrap();
+        assert_eq!(vec!["both", "ferris"], titles(vault.run(query)));
+
+        let query = with_tags(match_all().unwrap(), &["rust", "go"], false).unwrap();
+        let query = without_tags(query, &["go"]).unwrap();
+        assert_eq!(vec!["ferris"], titles(vault.run(query)));
+    }
+}
+
+#[cfg(test)]
+mod suggestions_tests {
+    use super::*;
+    use crate::indexer::index_paths;
+    use std::fs;
+
+    #[test]
+    fn typo_suggests_the_intended_note() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let db_path = dir.path().join("db");
+        let db_path = db_path.to_str().unwrap();
+        let path = dir.path().join("cluster.md");
+        fs::write(
+            &path,
+            "---\ndate: 2021-06-22T12:48:16-0400\ntags: test\ntitle: Cluster\n---\nKubernetes upgrade\n",
+        )
+        .unwrap();
+        index_paths(db_path, vec![path], &Default::default()).expect("Failed to index");
+
+        let qstr = "kuberentes ;";
+        let exact = query_db(&[db_path], parse_user_query(qstr).unwrap(), DEFAULT_LIMIT).unwrap();
+        assert!(exact.is_empty());
+        assert_eq!(
+            vec!["Cluster"],
+            suggestions(&[db_path], qstr, false, 3).expect("Failed to suggest")
+        );
+        assert_eq!(
+            Some(String::from("No exact matches. Did you mean: Cluster?")),
+            no_matches_message(&[String::from("Cluster")])
+        );
+    }
+}
+
+#[cfg(test)]
+mod parse_prefix_query_tests {
+    use super::*;
+    use crate::indexer::index_paths;
+    use std::fs;
+
+    #[test]
+    fn last_word_matches_longer_term() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let db_path = dir.path().join("db");
+        let db_path = db_path.to_str().unwrap();
+        let path = dir.path().join("cluster.md");
+        fs::write(
+            &path,
+            "---\ndate: 2021-06-22T12:48:16-0400\ntags: test\ntitle: Cluster\n---\nKubernetes upgrade\n",
+        )
+        .unwrap();
+        index_paths(db_path, vec![path], &Default::default()).expect("Failed to index");
+
+        let count = |query| {
+            query_db(&[db_path], query, DEFAULT_LIMIT)
+                .expect("Failed to query")
+                .len()
+        };
+        assert_eq!(1, count(parse_prefix_query("upgrade kube ;").unwrap()));
+        assert_eq!(0, count(parse_user_query("kube ;").unwrap()));
+    }
 }
@@ -0,0 +1,44 @@
+use std::fs;
+use std::process::{Command, Stdio};
+
+#[test]
+fn bare_words_run_a_query_when_configured() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let notes = dir.path().join("notes");
+    fs::create_dir(&notes).unwrap();
+    fs::write(
+        notes.join("foo.md"),
+        "---\ndate: 2021-06-22T12:48:16-0400\ntags: test\ntitle: Foo\n---\nAll about foo\n",
+    )
+    .unwrap();
+    let config = dir.path().join("tika.toml");
+    fs::write(
+        &config,
+        format!(
+            "source-glob = \"{}/*.md\"\ndefault-command = \"query\"\n",
+            notes.display()
+        ),
+    )
+    .unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_tika"))
+        .arg("-c")
+        .arg(&config)
+        .arg("--index-dir")
+        .arg(dir.path().join("index"))
+        .arg("-i")
+        .arg("foo")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .output()
+        .expect("Failed to run tika");
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    // Without a terminal, the finder would exit with 2 and a hint
+    assert_eq!(Some(0), output.status.code(), "{}", stderr);
+    let result: serde_json::Value =
+        serde_json::from_slice(&output.stdout).expect("Query output isn't JSON");
+    assert_eq!(1, result["total"]);
+    assert_eq!("Foo", result["documents"][0]["title"]);
+}
@@ -0,0 +1,23 @@
+use std::process::{Command, Stdio};
+
+#[test]
+fn finder_without_a_terminal_exits_with_a_hint() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let output = Command::new(env!("CARGO_BIN_EXE_tika"))
+        .arg("-c")
+        .arg(dir.path().join("missing.toml"))
+        .arg("--index-dir")
+        .arg(dir.path().join("index"))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .output()
+        .expect("Failed to run tika");
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(Some(2), output.status.code(), "{}", stderr);
+    assert!(stderr.contains("requires a terminal"), "{}", stderr);
+    // Entering raw mode fails with an ioctl error and a backtrace
+    assert!(!stderr.contains("ioctl"), "{}", stderr);
+    assert!(output.stdout.is_empty());
+}
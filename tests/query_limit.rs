@@ -0,0 +1,144 @@
+use std::fs;
+use std::process::{Command, Stdio};
+
+/// A note's file name, extra frontmatter lines and body
+type Note<'a> = (&'a str, &'a str, &'a str);
+
+/// Index `notes` and run `tika query` with `args` over them, returning the titles of the matches
+/// in the order printed
+fn query(notes: &[Note], args: &[&str]) -> Vec<String> {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let source = dir.path().join("notes");
+    fs::create_dir(&source).unwrap();
+    for (name, frontmatter, body) in notes {
+        let mut fields = String::from(*frontmatter);
+        if !frontmatter.contains("title:") {
+            fields.push_str(&format!("title: {}\n", name.trim_end_matches(".md")));
+        }
+        if !frontmatter.contains("date:") {
+            fields.push_str("date: 2021-06-22T12:48:16-0400\n");
+        }
+        fs::write(source.join(name), format!("---\n{}---\n{}\n", fields, body)).unwrap();
+    }
+    let config = dir.path().join("tika.toml");
+    fs::write(
+        &config,
+        format!("source-glob = \"{}/*.md\"\n", source.display()),
+    )
+    .unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_tika"))
+        .arg("-c")
+        .arg(&config)
+        .arg("--index-dir")
+        .arg(dir.path().join("index"))
+        .arg("-i")
+        .arg("query")
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .output()
+        .expect("Failed to run tika");
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(Some(0), output.status.code(), "{}", stderr);
+    let result: serde_json::Value =
+        serde_json::from_slice(&output.stdout).expect("Query output isn't JSON");
+    result["documents"]
+        .as_array()
+        .expect("No documents")
+        .iter()
+        .map(|doc| doc["title"].as_str().unwrap().to_owned())
+        .collect()
+}
+
+fn sorted(mut titles: Vec<String>) -> Vec<String> {
+    titles.sort();
+    titles
+}
+
+#[test]
+fn presence_filters_fill_the_limit() {
+    // The best match has no author, so filtering only the top 2 matches would leave one
+    let notes = [
+        ("anonymous.md", "", "rust rust rust rust"),
+        ("first.md", "author: Ann\n", "rust and more"),
+        ("second.md", "author: Bob\n", "rust and more"),
+    ];
+    assert_eq!(
+        vec!["first", "second"],
+        sorted(query(
+            &notes,
+            &["--has-field", "author", "--limit", "2", "rust"]
+        ))
+    );
+    assert_eq!(
+        vec!["anonymous"],
+        query(&notes, &["--lacks-field", "author", "--limit", "2", "rust"])
+    );
+}
+
+/// The most relevant note is the oldest and last by title, the least relevant the newest and
+/// first by title
+fn spread() -> Vec<Note<'static>> {
+    vec![
+        (
+            "zebra.md",
+            "date: 2020-01-01T00:00:00+00:00\n",
+            "rust rust rust rust",
+        ),
+        (
+            "middle.md",
+            "date: 2020-06-01T00:00:00+00:00\n",
+            "rust rust and more",
+        ),
+        (
+            "apple.md",
+            "date: 2021-01-01T00:00:00+00:00\n",
+            "rust and much more",
+        ),
+    ]
+}
+
+#[test]
+fn sorting_orders_every_match_before_the_limit() {
+    let notes = spread();
+    assert_eq!(vec!["zebra"], query(&notes, &["--limit", "1", "rust"]));
+    assert_eq!(
+        vec!["apple"],
+        query(&notes, &["--sort", "date", "--limit", "1", "rust"])
+    );
+    assert_eq!(
+        vec!["zebra"],
+        query(
+            &notes,
+            &["--sort", "date", "--reverse", "--limit", "1", "rust"]
+        )
+    );
+    assert_eq!(
+        vec!["apple", "middle"],
+        query(&notes, &["--sort", "title", "--limit", "2", "rust"])
+    );
+}
+
+#[test]
+fn deduplication_fills_the_limit() {
+    // Copies of the best match would take up the whole limit before deduplicating
+    let notes = [
+        ("standup.md", "", "standup standup standup standup"),
+        (
+            "standup-copy.md",
+            "title: standup\n",
+            "standup standup standup standup",
+        ),
+        ("retro.md", "", "standup notes"),
+    ];
+    assert_eq!(
+        vec!["retro", "standup"],
+        sorted(query(
+            &notes,
+            &["--dedupe-by", "title", "--limit", "2", "standup"]
+        ))
+    );
+}